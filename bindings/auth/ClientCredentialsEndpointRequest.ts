@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ClientCredentialsEndpointRequest { grant_type: string | null, scope: string | null, client_id: string | null, client_secret: string | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ClientEndpointResponse { client_id: string, name: string, scopes: Array<string>, created_by: bigint, created_at: bigint, client_secret: string | null, }
\ No newline at end of file
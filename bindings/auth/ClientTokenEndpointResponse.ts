@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ClientTokenEndpointResponse { access_token: string, token_type: string, expires_in: bigint, scope: string, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface IntrospectEndpointRequest { token: string | null, token_type_hint: string | null, }
\ No newline at end of file
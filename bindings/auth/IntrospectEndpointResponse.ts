@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface IntrospectEndpointResponse { active: boolean, sub: string | null, user_id: bigint | null, permissions: Array<string> | null, exp: number | null, iat: number | null, jti: string | null, act: string | null, client_id: string | null, scope: string | null, token_type: string | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface SenderTokenEndpointResponse { group_name: string, admin_email: string, token_id: string, issued_at: bigint, expires_at: bigint, token: string | null, }
\ No newline at end of file
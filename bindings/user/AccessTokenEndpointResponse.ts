@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface AccessTokenEndpointResponse { id: string, name: string, permissions: Array<string>, created_at: bigint, expires_at: bigint, last_used_at: bigint | null, token: string | null, }
\ No newline at end of file
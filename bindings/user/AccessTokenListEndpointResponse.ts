@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AccessTokenEndpointResponse } from "./AccessTokenEndpointResponse";
+
+export interface AccessTokenListEndpointResponse { tokens: Array<AccessTokenEndpointResponse>, }
\ No newline at end of file
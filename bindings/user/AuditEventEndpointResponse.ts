@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface AuditEventEndpointResponse { action: string, actor_id: bigint, actor_email: string, target_user_id: bigint, token_id: string | null, occurred_at: bigint, }
\ No newline at end of file
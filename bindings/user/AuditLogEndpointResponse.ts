@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AuditEventEndpointResponse } from "./AuditEventEndpointResponse";
+
+export interface AuditLogEndpointResponse { events: Array<AuditEventEndpointResponse>, count: bigint, }
\ No newline at end of file
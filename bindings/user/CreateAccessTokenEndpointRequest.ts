@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface CreateAccessTokenEndpointRequest { name: string | null, permissions: Array<string> | null, expires_in_days: bigint | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ImpersonationEndpointResponse { user_id: bigint, bearer_token: string, expires_at: bigint, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface LoginAttemptEndpointResponse { method: string, success: boolean, ip_address: string | null, user_agent: string | null, occurred_at: bigint, }
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface LoginEndpointRequest { email: string | null, password: string | null, }
\ No newline at end of file
+export interface LoginEndpointRequest { email: string | null, password: string | null, device_label: string | null, use_cookie: boolean | null, }
\ No newline at end of file
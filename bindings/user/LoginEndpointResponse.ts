@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MfaRequiredResponse } from "./MfaRequiredResponse";
+import type { ObtainTokenResponse } from "./ObtainTokenResponse";
+
+export type LoginEndpointResponse = ObtainTokenResponse | MfaRequiredResponse;
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LoginAttemptEndpointResponse } from "./LoginAttemptEndpointResponse";
+
+export interface LoginHistoryEndpointResponse { attempts: Array<LoginAttemptEndpointResponse>, count: bigint, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface LoginMfaEndpointRequest { mfa_token: string | null, code: string | null, device_label: string | null, use_cookie: boolean | null, }
\ No newline at end of file
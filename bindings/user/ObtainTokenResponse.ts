@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface ObtainTokenResponse { refresh_token: string, bearer_token: string, }
\ No newline at end of file
+export interface ObtainTokenResponse { refresh_token: string | null, bearer_token: string, csrf_token: string | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PasskeyEndpointResponse { id: string, name: string, created_at: bigint, last_used_at: bigint | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PasskeyEndpointResponse } from "./PasskeyEndpointResponse";
+
+export interface PasskeyListEndpointResponse { passkeys: Array<PasskeyEndpointResponse>, }
\ No newline at end of file
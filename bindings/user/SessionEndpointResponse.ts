@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface SessionEndpointResponse { id: string, device_label: string | null, ip_address: string | null, user_agent: string | null, created_at: bigint, last_used_at: bigint, current: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SessionEndpointResponse } from "./SessionEndpointResponse";
+
+export interface SessionListEndpointResponse { sessions: Array<SessionEndpointResponse>, }
\ No newline at end of file
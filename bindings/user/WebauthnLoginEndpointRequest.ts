@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface WebauthnLoginEndpointRequest { credential_id: string | null, client_data_json: string | null, authenticator_data: string | null, signature: string | null, device_label: string | null, use_cookie: boolean | null, }
\ No newline at end of file
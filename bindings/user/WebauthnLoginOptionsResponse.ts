@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface WebauthnLoginOptionsResponse { challenge: string, rp_id: string, allow_credentials: Array<string>, user_verification: string, timeout_seconds: bigint, }
\ No newline at end of file
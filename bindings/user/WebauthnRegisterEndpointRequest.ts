@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface WebauthnRegisterEndpointRequest { name: string | null, client_data_json: string | null, attestation_object: string | null, }
\ No newline at end of file
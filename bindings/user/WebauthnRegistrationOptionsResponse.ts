@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface WebauthnRegistrationOptionsResponse { challenge: string, rp_id: string, rp_name: string, user_handle: string, user_name: string, algorithms: Array<bigint>, exclude_credentials: Array<string>, user_verification: string, timeout_seconds: bigint, }
\ No newline at end of file
@@ -1,16 +1,20 @@
+use crate::routes::auth::AuthRouter;
 use crate::routes::notification::NotificationRouter;
 use crate::routes::templating::TemplatingRouter;
 use crate::routes::user::UserRouter;
+use crate::routes::well_known::WellKnownRouter;
 use anyhow::Ok;
 use axum::Router;
 use clap::Parser;
 use dotenv::dotenv;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tracing::info;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use utilities::config::AppConfig;
+use utilities::rate_limit::RateLimitLayer;
 use utilities::service_register::ServiceRegister;
 
 mod request;
@@ -35,25 +39,51 @@ async fn main() -> anyhow::Result<()> {
     // let cors_origin = config.cors_origin.as_str();
     let app_url = format!("{}:{}", app_host, app_port);
     let service_register = ServiceRegister::new(config.clone()).await?;
+    if config.user_directory_sync_seconds > 0 {
+        service_register.user_directory.spawn_synchronisation(
+            service_register.user_service.clone(),
+            config.user_directory_sync_seconds,
+        );
+    }
 
     info!("migrations successfully ran, initializing axum server...");
     let app = Router::new()
         .nest(
             "/api/user",
-            UserRouter::new_router(service_register.clone()),
+            UserRouter::new_router(service_register.clone()).layer(RateLimitLayer::new(
+                &service_register,
+                config.rate_limit_user,
+            )),
         )
         .nest(
             "/api/templating",
-            TemplatingRouter::new_router(service_register.clone()),
+            TemplatingRouter::new_router(service_register.clone()).layer(RateLimitLayer::new(
+                &service_register,
+                config.rate_limit_templating,
+            )),
         )
         .nest(
             "/api/notification",
-            NotificationRouter::new_router(service_register.clone()),
+            NotificationRouter::new_router(service_register.clone()).layer(RateLimitLayer::new(
+                &service_register,
+                config.rate_limit_notification,
+            )),
+        )
+        .nest(
+            "/api/auth",
+            AuthRouter::new_router(service_register.clone()).layer(RateLimitLayer::new(
+                &service_register,
+                config.rate_limit_auth,
+            )),
+        )
+        .nest(
+            "/.well-known",
+            WellKnownRouter::new_router(service_register.clone()),
         )
         .layer(CorsLayer::permissive());
 
     axum::Server::bind(&app_url.parse().unwrap())
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use validator::Validate;
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct IntrospectEndpointRequest {
+    #[validate(required, length(min = 1))]
+    pub token: Option<String>,
+    pub token_type_hint: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct ClientCredentialsEndpointRequest {
+    #[validate(required, length(min = 1))]
+    pub grant_type: Option<String>,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct CreateClientEndpointRequest {
+    #[validate(required, length(min = 1, max = 64))]
+    pub name: Option<String>,
+    #[validate(required, length(min = 1))]
+    pub scopes: Option<Vec<String>>,
+}
@@ -1,5 +1,6 @@
 use serde::Deserialize;
 
+pub mod auth;
 pub mod notification;
 pub mod templating;
 pub mod user;
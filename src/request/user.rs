@@ -22,12 +22,15 @@ pub struct LoginEndpointRequest {
     pub email: Option<String>,
     #[validate(required, length(min = 8, max = 30))]
     pub password: Option<String>,
+    #[validate(length(max = 100))]
+    pub device_label: Option<String>,
+    pub use_cookie: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Validate, TS)]
 #[ts(export, export_to = "bindings/user/")]
 pub struct RefreshtokenEndpointRequest {
-    #[validate(required, length(min = 1))]
+    #[validate(length(min = 1))]
     pub token: Option<String>,
 }
 
@@ -59,3 +62,113 @@ pub struct AuthorizeRevokeUserRoleRequest {
 pub struct AuthorizeRevokeRolePermissionRequest {
     pub permissions: Option<Vec<String>>,
 }
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct ForgotPasswordEndpointRequest {
+    #[validate(required, length(min = 1), email(message = "Email is invalid"))]
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct MagicLinkEndpointRequest {
+    #[validate(required, length(min = 1), email(message = "Email is invalid"))]
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct MagicLinkLoginEndpointRequest {
+    #[validate(length(max = 100))]
+    pub device_label: Option<String>,
+    pub use_cookie: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct ResetPasswordEndpointRequest {
+    #[validate(required, length(min = 1))]
+    pub token: Option<String>,
+    #[validate(required, length(min = 8, max = 30))]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct ResendVerificationEndpointRequest {
+    #[validate(required, length(min = 1), email(message = "Email is invalid"))]
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct TotpCodeEndpointRequest {
+    #[validate(required, length(min = 6, max = 10))]
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct CreateAccessTokenEndpointRequest {
+    #[validate(required, length(min = 1, max = 50))]
+    pub name: Option<String>,
+    pub permissions: Option<Vec<String>>,
+    #[validate(range(min = 1, max = 365))]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct OAuthCallbackEndpointRequest {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct LoginMfaEndpointRequest {
+    #[validate(required, length(min = 1))]
+    pub mfa_token: Option<String>,
+    #[validate(required, length(min = 6, max = 10))]
+    pub code: Option<String>,
+    #[validate(length(max = 100))]
+    pub device_label: Option<String>,
+    pub use_cookie: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct WebauthnRegisterEndpointRequest {
+    #[validate(required, length(min = 1, max = 64))]
+    pub name: Option<String>,
+    #[validate(required, length(min = 1))]
+    pub client_data_json: Option<String>,
+    #[validate(required, length(min = 1))]
+    pub attestation_object: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct WebauthnLoginStartEndpointRequest {
+    #[validate(email(message = "Email is invalid"))]
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Validate, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct WebauthnLoginEndpointRequest {
+    #[validate(required, length(min = 1))]
+    pub credential_id: Option<String>,
+    #[validate(required, length(min = 1))]
+    pub client_data_json: Option<String>,
+    #[validate(required, length(min = 1))]
+    pub authenticator_data: Option<String>,
+    #[validate(required, length(min = 1))]
+    pub signature: Option<String>,
+    #[validate(length(max = 100))]
+    pub device_label: Option<String>,
+    pub use_cookie: Option<bool>,
+}
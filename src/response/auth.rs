@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::utilities::{oauth_clients::OAuthClientRecord, token::BearerClaims};
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct IntrospectEndpointResponse {
+    pub active: bool,
+    pub sub: Option<String>,
+    pub user_id: Option<i64>,
+    pub permissions: Option<Vec<String>>,
+    pub exp: Option<usize>,
+    pub iat: Option<usize>,
+    pub jti: Option<String>,
+    pub act: Option<String>,
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+    pub token_type: Option<String>,
+}
+
+impl IntrospectEndpointResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    pub fn from_claims(claims: BearerClaims) -> Self {
+        let user_id = match claims.is_client() {
+            true => None,
+            false => Some(claims.user_id),
+        };
+        Self {
+            active: true,
+            sub: Some(claims.sub),
+            user_id,
+            permissions: Some(claims.permissions),
+            exp: Some(claims.registered.exp),
+            iat: Some(claims.registered.iat),
+            jti: Some(claims.jti),
+            act: claims.act.map(|actor| actor.sub),
+            client_id: claims.client_id,
+            scope: claims.scope,
+            token_type: Some("Bearer".to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct ClientTokenEndpointResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub scope: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct ClientEndpointResponse {
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_by: i64,
+    pub created_at: i64,
+    pub client_secret: Option<String>,
+}
+
+impl ClientEndpointResponse {
+    pub fn from_record(record: OAuthClientRecord) -> Self {
+        Self {
+            client_id: record.client_id,
+            name: record.name,
+            scopes: record.scopes,
+            created_by: record.created_by,
+            created_at: record.created_at as i64,
+            client_secret: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/auth/")]
+pub struct ClientListEndpointResponse {
+    pub clients: Vec<ClientEndpointResponse>,
+}
@@ -1,6 +1,12 @@
+use axum::{
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
+pub mod auth;
 pub mod notification;
 pub mod templating;
 pub mod user;
@@ -10,3 +16,15 @@ pub mod user;
 pub struct StatusMessageResponse {
     pub status: String,
 }
+
+impl StatusMessageResponse {
+    /// A 429 response telling the client how many seconds to wait through `Retry-After`.
+    pub fn too_many_requests(status: String, retry_after: i64) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after.to_string())],
+            Json(Self { status }),
+        )
+            .into_response()
+    }
+}
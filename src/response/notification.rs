@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-use crate::utilities::events::NotificationMessage;
+use crate::utilities::{events::NotificationMessage, sender_tokens::SenderTokenRecord};
 
 #[derive(Serialize, Deserialize, Default, Debug, TS)]
 #[ts(export, export_to = "bindings/notification/")]
@@ -15,3 +15,34 @@ pub struct NotificationLogsEndpointResponse {
     pub notifications: Vec<NotificationMessage>,
     pub count: i64,
 }
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/notification/")]
+pub struct SenderTokenEndpointResponse {
+    pub group_name: String,
+    pub admin_email: String,
+    pub token_id: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    pub token: Option<String>,
+}
+
+impl SenderTokenEndpointResponse {
+    pub fn from_record(group_name: String, record: SenderTokenRecord) -> Self {
+        Self {
+            group_name,
+            admin_email: record.admin_email,
+            token_id: record.jti,
+            issued_at: record.issued_at as i64,
+            expires_at: record.expires_at as i64,
+            token: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/notification/")]
+pub struct SseTicketEndpointResponse {
+    pub ticket: String,
+    pub expires_at: i64,
+}
@@ -2,7 +2,10 @@ use madtofan_microservice_common::user::{ListResponse, UserList, UserListRespons
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-use crate::utilities::token::Tokens;
+use crate::utilities::{
+    access_tokens::AccessTokenRecord, audit::AuditEvent, login_history::LoginAttempt,
+    sessions::SessionRecord, token::Tokens, webauthn::PasskeyRecord,
+};
 
 #[derive(Serialize, Deserialize, Default, Debug, TS)]
 #[ts(export, export_to = "bindings/user/")]
@@ -41,17 +44,188 @@ impl UserEndpointResponse {
 #[derive(Serialize, Deserialize, Default, Debug, TS)]
 #[ts(export, export_to = "bindings/user/")]
 pub struct ObtainTokenResponse {
-    pub refresh_token: String,
+    pub refresh_token: Option<String>,
     pub bearer_token: String,
+    pub csrf_token: Option<String>,
 }
 
 impl ObtainTokenResponse {
     pub fn from_tokens(tokens: Tokens) -> Self {
         Self {
-            refresh_token: tokens.refresh,
+            refresh_token: Some(tokens.refresh),
             bearer_token: tokens.bearer,
+            csrf_token: None,
         }
     }
+
+    /// The refresh token travels in a cookie instead, only the CSRF token is returned with it.
+    pub fn from_cookie_tokens(tokens: Tokens, csrf_token: String) -> Self {
+        Self {
+            refresh_token: None,
+            bearer_token: tokens.bearer,
+            csrf_token: Some(csrf_token),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct MfaRequiredResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, TS)]
+#[serde(untagged)]
+#[ts(export, export_to = "bindings/user/")]
+pub enum LoginEndpointResponse {
+    Tokens(ObtainTokenResponse),
+    MfaRequired(MfaRequiredResponse),
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct TotpSetupEndpointResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct RecoveryCodesEndpointResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct ImpersonationEndpointResponse {
+    pub user_id: i64,
+    pub bearer_token: String,
+    pub expires_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct AuditEventEndpointResponse {
+    pub action: String,
+    pub actor_id: i64,
+    pub actor_email: String,
+    pub target_user_id: i64,
+    pub token_id: Option<String>,
+    pub occurred_at: i64,
+}
+
+impl From<AuditEvent> for AuditEventEndpointResponse {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            action: event.action,
+            actor_id: event.actor_id,
+            actor_email: event.actor_email,
+            target_user_id: event.target_user_id,
+            token_id: event.token_id,
+            occurred_at: event.occurred_at as i64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct AuditLogEndpointResponse {
+    pub events: Vec<AuditEventEndpointResponse>,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct LoginAttemptEndpointResponse {
+    pub method: String,
+    pub success: bool,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub occurred_at: i64,
+}
+
+impl From<LoginAttempt> for LoginAttemptEndpointResponse {
+    fn from(attempt: LoginAttempt) -> Self {
+        Self {
+            method: attempt.method,
+            success: attempt.success,
+            ip_address: attempt.ip_address,
+            user_agent: attempt.user_agent,
+            occurred_at: attempt.occurred_at as i64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct LoginHistoryEndpointResponse {
+    pub attempts: Vec<LoginAttemptEndpointResponse>,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct SessionEndpointResponse {
+    pub id: String,
+    pub device_label: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub current: bool,
+}
+
+impl SessionEndpointResponse {
+    pub fn from_record(record: SessionRecord, current_session_id: &Option<String>) -> Self {
+        Self {
+            current: current_session_id.as_ref() == Some(&record.id),
+            id: record.id,
+            device_label: record.device_label,
+            ip_address: record.ip_address,
+            user_agent: record.user_agent,
+            created_at: record.created_at as i64,
+            last_used_at: record.last_used_at as i64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct SessionListEndpointResponse {
+    pub sessions: Vec<SessionEndpointResponse>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct AccessTokenEndpointResponse {
+    pub id: String,
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub last_used_at: Option<i64>,
+    pub token: Option<String>,
+}
+
+impl AccessTokenEndpointResponse {
+    pub fn from_record(record: AccessTokenRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            permissions: record.permissions,
+            created_at: record.created_at as i64,
+            expires_at: record.expires_at as i64,
+            last_used_at: record.last_used_at.map(|last_used_at| last_used_at as i64),
+            token: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct AccessTokenListEndpointResponse {
+    pub tokens: Vec<AccessTokenEndpointResponse>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, TS)]
@@ -171,3 +345,53 @@ impl UserListEndpointResponse {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct WebauthnRegistrationOptionsResponse {
+    pub challenge: String,
+    pub rp_id: String,
+    pub rp_name: String,
+    pub user_handle: String,
+    pub user_name: String,
+    pub algorithms: Vec<i64>,
+    pub exclude_credentials: Vec<String>,
+    pub user_verification: String,
+    pub timeout_seconds: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct WebauthnLoginOptionsResponse {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<String>,
+    pub user_verification: String,
+    pub timeout_seconds: i64,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct PasskeyEndpointResponse {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+impl PasskeyEndpointResponse {
+    pub fn from_record(record: PasskeyRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            created_at: record.created_at as i64,
+            last_used_at: record.last_used_at.map(|last_used_at| last_used_at as i64),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, TS)]
+#[ts(export, export_to = "bindings/user/")]
+pub struct PasskeyListEndpointResponse {
+    pub passkeys: Vec<PasskeyEndpointResponse>,
+}
@@ -0,0 +1,246 @@
+use axum::{
+    extract::{Extension, Path, State},
+    headers::{authorization::Basic, Authorization},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Form, Json, Router, TypedHeader,
+};
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use validator::Validate;
+
+use crate::{
+    request::auth::{
+        ClientCredentialsEndpointRequest, CreateClientEndpointRequest, IntrospectEndpointRequest,
+    },
+    response::auth::{
+        ClientEndpointResponse, ClientListEndpointResponse, ClientTokenEndpointResponse,
+        IntrospectEndpointResponse,
+    },
+    utilities::{
+        authorization::RequirePermissionLayer,
+        constants::{CLIENT_READ, CLIENT_WRITE},
+        service_register::ServiceRegister,
+        states::{service_clients::StateServiceClients, token_service::StateTokenService},
+        token::BearerClaims,
+    },
+};
+use tracing::info;
+
+pub struct AuthRouter;
+
+impl AuthRouter {
+    pub fn new_router(service_register: ServiceRegister) -> Router {
+        Router::new()
+            .route("/introspect", post(AuthRouter::introspect_endpoint))
+            .route("/token", post(AuthRouter::client_token_endpoint))
+            .route(
+                "/clients",
+                get(AuthRouter::list_clients_endpoint)
+                    .route_layer(RequirePermissionLayer::new(&service_register, CLIENT_READ)),
+            )
+            .route(
+                "/clients",
+                post(AuthRouter::create_client_endpoint)
+                    .route_layer(RequirePermissionLayer::new(&service_register, CLIENT_WRITE)),
+            )
+            .route(
+                "/clients/:client_id",
+                delete(AuthRouter::delete_client_endpoint)
+                    .route_layer(RequirePermissionLayer::new(&service_register, CLIENT_WRITE)),
+            )
+            .with_state(service_register)
+    }
+
+    pub async fn introspect_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(service_clients): State<StateServiceClients>,
+        authorization: TypedHeader<Authorization<Basic>>,
+        Form(request): Form<IntrospectEndpointRequest>,
+    ) -> ServiceResult<Json<IntrospectEndpointResponse>> {
+        info!("Introspect Endpoint, authenticating service client...");
+        if !service_clients.authenticate(authorization.username(), authorization.password()) {
+            return Err(ServiceError::Unauthorized);
+        }
+        request.validate()?;
+
+        info!("Service client authenticated, decoding token...");
+        let response = match token_service.decode_authorized_token(&request.token.unwrap()) {
+            Ok(claims) => IntrospectEndpointResponse::from_claims(claims),
+            Err(_) => IntrospectEndpointResponse::inactive(),
+        };
+
+        info!("Token introspected, returning response!");
+        Ok(Json(response))
+    }
+
+    pub async fn client_token_endpoint(
+        State(token_service): State<StateTokenService>,
+        authorization: Option<TypedHeader<Authorization<Basic>>>,
+        Form(request): Form<ClientCredentialsEndpointRequest>,
+    ) -> ServiceResult<Json<ClientTokenEndpointResponse>> {
+        info!("Client Token Endpoint, authenticating client...");
+        request.validate()?;
+        if request.grant_type.as_deref() != Some("client_credentials") {
+            return Err(ServiceError::BadRequest(
+                "Unsupported grant type, expected client_credentials".to_string(),
+            ));
+        }
+        let (client_id, client_secret) =
+            match (authorization, request.client_id, request.client_secret) {
+                (Some(TypedHeader(basic)), _, _) => {
+                    (basic.username().to_string(), basic.password().to_string())
+                }
+                (None, Some(client_id), Some(client_secret)) => (client_id, client_secret),
+                _ => return Err(ServiceError::Unauthorized),
+            };
+
+        let (access_token, claims) = token_service.create_client_token(
+            &client_id,
+            &client_secret,
+            request.scope.as_deref(),
+        )?;
+
+        info!("Client {} authenticated, returning response!", &client_id);
+        Ok(Json(ClientTokenEndpointResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: (claims.registered.exp - claims.registered.iat) as i64,
+            scope: claims.scope.unwrap_or_default(),
+        }))
+    }
+
+    pub async fn list_clients_endpoint(
+        State(token_service): State<StateTokenService>,
+    ) -> ServiceResult<Json<ClientListEndpointResponse>> {
+        info!("List Clients Endpoint, returning response!");
+        Ok(Json(ClientListEndpointResponse {
+            clients: token_service
+                .list_clients()
+                .into_iter()
+                .map(ClientEndpointResponse::from_record)
+                .collect(),
+        }))
+    }
+
+    pub async fn create_client_endpoint(
+        State(token_service): State<StateTokenService>,
+        Extension(bearer_claims): Extension<BearerClaims>,
+        Json(request): Json<CreateClientEndpointRequest>,
+    ) -> ServiceResult<(StatusCode, Json<ClientEndpointResponse>)> {
+        info!("Create Client Endpoint, registering client...");
+        request.validate()?;
+        let (client_secret, record) = token_service.register_client(
+            &bearer_claims,
+            &request.name.unwrap(),
+            request.scopes.unwrap(),
+        )?;
+
+        info!(
+            "Client {} registered, returning response!",
+            &record.client_id
+        );
+        Ok((
+            StatusCode::CREATED,
+            Json(ClientEndpointResponse {
+                client_secret: Some(client_secret),
+                ..ClientEndpointResponse::from_record(record)
+            }),
+        ))
+    }
+
+    pub async fn delete_client_endpoint(
+        State(token_service): State<StateTokenService>,
+        Path(client_id): Path<String>,
+    ) -> ServiceResult<Json<ClientEndpointResponse>> {
+        info!("Delete Client Endpoint, removing client...");
+        let record = token_service
+            .remove_client(&client_id)
+            .ok_or_else(|| ServiceError::NotFound(format!("No client {}", &client_id)))?;
+
+        info!("Client removed, returning response!");
+        Ok(Json(ClientEndpointResponse::from_record(record)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Method, Request},
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::utilities::{
+        constants::USER_READ,
+        test_support::{bearer_for, json_body, service_register},
+    };
+
+    fn introspect(credentials: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/introspect")
+            .header(
+                AUTHORIZATION,
+                format!("Basic {}", STANDARD.encode(credentials)),
+            )
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(format!("token={}", token)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn introspection_requires_a_service_client() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[USER_READ]);
+        let router = AuthRouter::new_router(service_register);
+
+        let response = router
+            .oneshot(introspect("notification-service:WrongSecret", &bearer))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn introspection_reports_active_tokens() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[USER_READ]);
+        let router = AuthRouter::new_router(service_register);
+
+        let response = router
+            .oneshot(introspect(
+                "notification-service:TestServiceClientSecret",
+                &bearer,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["active"], true);
+        assert_eq!(body["user_id"], 1);
+        assert_eq!(body["permissions"][0], USER_READ);
+    }
+
+    #[tokio::test]
+    async fn introspection_reports_revoked_and_invalid_tokens_as_inactive() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[USER_READ]);
+        service_register.token_service.revoke_user_tokens(1);
+        let router = AuthRouter::new_router(service_register);
+
+        for token in [bearer.as_str(), "not-a-token"] {
+            let response = router
+                .clone()
+                .oneshot(introspect(
+                    "notification-service:TestServiceClientSecret",
+                    token,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(json_body(response).await["active"], false);
+        }
+    }
+}
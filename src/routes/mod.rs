@@ -1,3 +1,5 @@
+pub mod auth;
 pub mod notification;
 pub mod templating;
 pub mod user;
+pub mod well_known;
@@ -2,20 +2,21 @@ use std::convert::Infallible;
 
 use async_stream::stream;
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Extension, Path, Query, State},
     headers::{authorization::Bearer, Authorization},
     response::sse::{Event as SseEvent, Sse},
     routing::{delete, get, post},
     Json, Router, TypedHeader,
 };
-use futures::Stream;
+use futures::{future, Stream};
 use madtofan_microservice_common::{
-    errors::ServiceResult,
+    errors::{ServiceError, ServiceResult},
     notification::{
         AddGroupRequest, AddMessageRequest, AddSubscriberRequest, GetGroupsRequest,
         GetMessagesRequest, RemoveGroupRequest, RemoveSubscriberRequest,
     },
 };
+use tokio::sync::broadcast;
 use validator::Validate;
 
 use crate::{
@@ -23,15 +24,21 @@ use crate::{
         notification::{AddGroupEndpointRequest, SendNotificationEndpointRequest},
         Pagination,
     },
-    response::notification::{NotificationEndpointResponse, NotificationLogsEndpointResponse},
+    response::notification::{
+        NotificationEndpointResponse, NotificationLogsEndpointResponse,
+        SenderTokenEndpointResponse, SseTicketEndpointResponse,
+    },
     utilities::{
-        constants::PAGINATION_SIZE,
+        authorization::RequirePermissionLayer,
+        constants::{GROUP_READ, GROUP_WRITE, PAGINATION_SIZE},
         events::{ChannelTag, EventMessage, NotificationMessage},
+        revocations::Revocation,
         service_register::ServiceRegister,
         states::{
             channels::StateChannelsService, notification_service::StateNotificationService,
             token_service::StateTokenService,
         },
+        token::BearerClaims,
     },
 };
 use tracing::info;
@@ -42,10 +49,8 @@ impl NotificationRouter {
     pub fn new_router(service_register: ServiceRegister) -> Router {
         Router::new()
             .route("/", post(NotificationRouter::send_notification))
-            .route(
-                "/:bearer_token",
-                get(NotificationRouter::event_notification),
-            )
+            .route("/ticket", post(NotificationRouter::create_sse_ticket))
+            .route("/:ticket", get(NotificationRouter::event_notification))
             .route("/log", get(NotificationRouter::get_notification_logs))
             .route(
                 "/subscribe/:group",
@@ -57,6 +62,17 @@ impl NotificationRouter {
                 "/group/:group_name/:admin_email",
                 delete(NotificationRouter::remove_group),
             )
+            .route(
+                "/group/:group_name/token",
+                get(NotificationRouter::get_sender_token)
+                    .route_layer(RequirePermissionLayer::new(&service_register, GROUP_READ)),
+            )
+            .route(
+                "/group/:group_name/token",
+                post(NotificationRouter::rotate_sender_token)
+                    .delete(NotificationRouter::revoke_sender_token)
+                    .route_layer(RequirePermissionLayer::new(&service_register, GROUP_WRITE)),
+            )
             .with_state(service_register)
     }
 
@@ -64,39 +80,74 @@ impl NotificationRouter {
         State(mut channels_service): State<StateChannelsService>,
         State(mut notification_service): State<StateNotificationService>,
         State(token_service): State<StateTokenService>,
-        Path(bearer_token): Path<String>,
-    ) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
-        info!("Subscribe Notification Endpoint");
+        Path(ticket): Path<String>,
+    ) -> ServiceResult<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>> {
+        info!("Subscribe Notification Endpoint, redeeming ticket...");
+        let claims = token_service.consume_sse_ticket(&ticket)?;
+        let mut revocations = token_service.subscribe_revocations();
+
+        info!("Ticket redeemed, subscribing to channels...");
         let stream = stream! {
-            let bearer_claims = token_service
-                .decode_bearer_token(&bearer_token);
-            let tags = match bearer_claims {
-                Ok(claims) => {
-                    let mut new_tags = Vec::new();
-                    new_tags.push(ChannelTag::UserId(claims.user_id));
-                    let get_groups_request = GetGroupsRequest {
-                        user_id: claims.user_id
-                    };
-                    let groups_response = notification_service.get_groups(get_groups_request).await;
-                    if let Ok(result) = groups_response {
-                        for group in result.into_inner().groups {
-                            new_tags.push(ChannelTag::ChannelId(group.name));
-                        }
-                    }
-                    new_tags
-                }
-                Err(_) => {
-                    vec![]
-                }
+            let mut tags = Vec::new();
+            tags.push(ChannelTag::UserId(claims.user_id));
+            let get_groups_request = GetGroupsRequest {
+                user_id: claims.user_id
             };
+            let groups_response = notification_service.get_groups(get_groups_request).await;
+            if let Ok(result) = groups_response {
+                for group in result.into_inner().groups {
+                    tags.push(ChannelTag::ChannelId(group.name));
+                }
+            }
             let mut rx = channels_service.create_channel(tags);
 
-            while let Some(msg) = rx.recv().await {
+            loop {
+                let msg = tokio::select! {
+                    msg = rx.recv() => msg,
+                    _ = NotificationRouter::token_revoked(&mut revocations, &claims) => None,
+                };
+                let Some(msg) = msg else { break };
                 let Ok(json) = serde_json::to_string(&msg) else { continue };
-                yield Ok(SseEvent::default().data(json));
+                let event = SseEvent::default().data(json);
+                yield Ok(match msg.event_name() {
+                    Some(name) => event.event(name),
+                    None => event,
+                });
             }
         };
-        Sse::new(stream)
+        Ok(Sse::new(stream))
+    }
+
+    pub async fn create_sse_ticket(
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<SseTicketEndpointResponse>> {
+        info!("Create SSE Ticket Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, creating ticket...");
+        let (ticket, expires_at) = token_service.create_sse_ticket(claims);
+
+        Ok(Json(SseTicketEndpointResponse {
+            ticket,
+            expires_at: expires_at as i64,
+        }))
+    }
+
+    async fn token_revoked(
+        revocations: &mut broadcast::Receiver<Revocation>,
+        claims: &BearerClaims,
+    ) {
+        loop {
+            match revocations.recv().await {
+                Ok(revocation) if revocation.matches(&claims.jti, claims.user_id) => return,
+                Ok(Revocation::Token(revoked_jti)) if claims.sid.as_ref() == Some(&revoked_jti) => {
+                    return
+                }
+                Err(broadcast::error::RecvError::Closed) => return future::pending().await,
+                _ => continue,
+            }
+        }
     }
 
     pub async fn send_notification(
@@ -258,6 +309,12 @@ impl NotificationRouter {
         token_service.decode_bearer_token(authorization.token())?;
         let group_name = request.group_name.unwrap_or_default();
         let admin_email = request.admin_email.unwrap_or_default();
+        if token_service.is_notification_group_known(&group_name) {
+            return Err(ServiceError::BadRequest(format!(
+                "Group {} already exists",
+                &group_name
+            )));
+        }
 
         let token = token_service.create_notification_sender_token(&group_name, &admin_email)?;
         let add_group_request = AddGroupRequest {
@@ -266,7 +323,10 @@ impl NotificationRouter {
             token: token.clone(),
         };
 
-        notification_service.add_group(add_group_request).await?;
+        if let Err(status) = notification_service.add_group(add_group_request).await {
+            token_service.remove_notification_group(&group_name);
+            return Err(status.into());
+        }
 
         let message = format!(
             "successfully created group: {}, group token is: {}",
@@ -285,16 +345,250 @@ impl NotificationRouter {
         token_service.decode_bearer_token(authorization.token())?;
 
         let remove_group_request = RemoveGroupRequest {
-            name: group_name,
+            name: group_name.clone(),
             admin_email,
         };
 
         notification_service
             .remove_group(remove_group_request)
             .await?;
+        token_service.remove_notification_group(&group_name);
 
         Ok(Json(NotificationEndpointResponse {
             message: "successfully removed group".to_string(),
         }))
     }
+
+    pub async fn get_sender_token(
+        State(token_service): State<StateTokenService>,
+        Path(group_name): Path<String>,
+    ) -> ServiceResult<Json<SenderTokenEndpointResponse>> {
+        info!("Get Sender Token Endpoint");
+        let record = token_service
+            .active_notification_sender_token(&group_name)
+            .ok_or_else(|| {
+                ServiceError::NotFound(format!("No active sender token for group {}", &group_name))
+            })?;
+
+        Ok(Json(SenderTokenEndpointResponse::from_record(
+            group_name, record,
+        )))
+    }
+
+    pub async fn rotate_sender_token(
+        State(token_service): State<StateTokenService>,
+        Extension(bearer_claims): Extension<BearerClaims>,
+        Path(group_name): Path<String>,
+    ) -> ServiceResult<Json<SenderTokenEndpointResponse>> {
+        info!("Rotate Sender Token Endpoint");
+        if !token_service.is_notification_group_known(&group_name) {
+            return Err(ServiceError::NotFound(format!(
+                "Group {} does not exist",
+                &group_name
+            )));
+        }
+
+        let admin_email = token_service
+            .active_notification_sender_token(&group_name)
+            .map(|record| record.admin_email)
+            .unwrap_or(bearer_claims.sub);
+
+        let token = token_service.create_notification_sender_token(&group_name, &admin_email)?;
+        let record = token_service
+            .active_notification_sender_token(&group_name)
+            .ok_or_else(|| {
+                ServiceError::InternalServerErrorWithContext(
+                    "Rotated sender token was not activated".to_string(),
+                )
+            })?;
+
+        info!("Rotated sender token of group {}", &group_name);
+        Ok(Json(SenderTokenEndpointResponse {
+            token: Some(token),
+            ..SenderTokenEndpointResponse::from_record(group_name, record)
+        }))
+    }
+
+    pub async fn revoke_sender_token(
+        State(token_service): State<StateTokenService>,
+        Path(group_name): Path<String>,
+    ) -> ServiceResult<Json<SenderTokenEndpointResponse>> {
+        info!("Revoke Sender Token Endpoint");
+        let record = token_service
+            .revoke_notification_sender_token(&group_name)
+            .ok_or_else(|| {
+                ServiceError::NotFound(format!("No active sender token for group {}", &group_name))
+            })?;
+
+        info!("Revoked sender token of group {}", &group_name);
+        Ok(Json(SenderTokenEndpointResponse::from_record(
+            group_name, record,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::utilities::{
+        config::AppConfig,
+        test_support::{bearer_for, json_body, request, service_register, service_register_with},
+    };
+
+    #[tokio::test]
+    async fn rotating_an_unknown_group_is_not_found() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[GROUP_WRITE]);
+        let response = NotificationRouter::new_router(service_register)
+            .oneshot(request(
+                Method::POST,
+                "/group/unknown/token",
+                Some(&bearer),
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn groups_created_before_the_registry_can_be_rotated() {
+        let mut config = AppConfig::for_tests();
+        config.notification_groups = vec!["legacy".to_string()];
+        let service_register = service_register_with(config).await;
+        let token_service = service_register.token_service.clone();
+        let bearer = bearer_for(&service_register, 1, &[GROUP_WRITE]);
+        let response = NotificationRouter::new_router(service_register)
+            .oneshot(request(
+                Method::POST,
+                "/group/legacy/token",
+                Some(&bearer),
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let token = json_body(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(token_service
+            .decode_notification_sender_token(&token)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn rotation_rejects_the_previous_sender_token() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let previous = token_service
+            .create_notification_sender_token("group", "admin@example.com")
+            .unwrap();
+        let bearer = bearer_for(&service_register, 1, &[GROUP_WRITE]);
+        let router = NotificationRouter::new_router(service_register);
+
+        let response = router
+            .clone()
+            .oneshot(request(
+                Method::POST,
+                "/group/group/token",
+                Some(&bearer),
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(token_service
+            .decode_notification_sender_token(&previous)
+            .is_err());
+
+        let body = r#"{"address":"broadcast","subject":"subject","message":"message"}"#;
+        let response = router
+            .oneshot(request(Method::POST, "/", Some(&previous), Some(body)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn revoked_sender_token_is_rejected() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let token = token_service
+            .create_notification_sender_token("group", "admin@example.com")
+            .unwrap();
+        assert!(token_service
+            .decode_notification_sender_token(&token)
+            .is_ok());
+
+        token_service.revoke_notification_sender_token("group");
+        assert!(token_service
+            .decode_notification_sender_token(&token)
+            .is_err());
+    }
+
+    async fn sse_ticket(router: &Router, bearer: &str) -> String {
+        let response = router
+            .clone()
+            .oneshot(request(Method::POST, "/ticket", Some(bearer), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        json_body(response).await["ticket"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn sse_ticket_opens_a_single_stream() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[]);
+        let router = NotificationRouter::new_router(service_register);
+        let ticket = sse_ticket(&router, &bearer).await;
+
+        let uri = format!("/{}", ticket);
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, &uri, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .oneshot(request(Method::GET, &uri, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unknown_sse_ticket_is_unauthorized() {
+        let service_register = service_register().await;
+        let response = NotificationRouter::new_router(service_register)
+            .oneshot(request(Method::GET, "/not-a-ticket", None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn sse_ticket_of_a_revoked_session_is_unauthorized() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let bearer = bearer_for(&service_register, 1, &[]);
+        let router = NotificationRouter::new_router(service_register);
+        let ticket = sse_ticket(&router, &bearer).await;
+
+        token_service.revoke_user_tokens(1);
+        let response = router
+            .oneshot(request(Method::GET, &format!("/{}", ticket), None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
 }
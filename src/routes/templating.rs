@@ -1,8 +1,7 @@
 use axum::{
     extract::{Path, Query, State},
-    headers::{authorization::Bearer, Authorization},
-    routing::{delete, get},
-    Json, Router, TypedHeader,
+    routing::{delete, get, post},
+    Json, Router,
 };
 use madtofan_microservice_common::{
     errors::{ServiceError, ServiceResult},
@@ -14,9 +13,10 @@ use crate::{
     request::{templating::AddTemplateEndpointRequest, Pagination},
     response::templating::{ListTemplateEndpointResponse, TemplateEndpointResponse},
     utilities::{
-        constants::PAGINATION_SIZE,
+        authorization::RequirePermissionLayer,
+        constants::{PAGINATION_SIZE, TEMPLATE_DELETE, TEMPLATE_READ, TEMPLATE_WRITE},
         service_register::ServiceRegister,
-        states::{templating_service::StateTemplatingService, token_service::StateTokenService},
+        states::templating_service::StateTemplatingService,
     },
 };
 use tracing::info;
@@ -28,24 +28,30 @@ impl TemplatingRouter {
         Router::new()
             .route(
                 "/",
-                get(TemplatingRouter::list_templates_endpoint)
-                    .post(TemplatingRouter::add_template_endpoint),
+                get(TemplatingRouter::list_templates_endpoint).route_layer(
+                    RequirePermissionLayer::new(&service_register, TEMPLATE_READ),
+                ),
+            )
+            .route(
+                "/",
+                post(TemplatingRouter::add_template_endpoint).route_layer(
+                    RequirePermissionLayer::new(&service_register, TEMPLATE_WRITE),
+                ),
             )
             .route(
                 "/:template_name",
-                delete(TemplatingRouter::remove_template_endpoint),
+                delete(TemplatingRouter::remove_template_endpoint).route_layer(
+                    RequirePermissionLayer::new(&service_register, TEMPLATE_DELETE),
+                ),
             )
             .with_state(service_register)
     }
 
     pub async fn list_templates_endpoint(
         State(mut templating_service): State<StateTemplatingService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         pagination: Query<Pagination>,
     ) -> ServiceResult<Json<ListTemplateEndpointResponse>> {
         info!("List Templates Endpoint");
-        token_service.decode_bearer_token(authorization.token())?;
         let pagination: Pagination = pagination.0;
         let offset = pagination.page.unwrap_or_default() * *PAGINATION_SIZE;
 
@@ -65,13 +71,9 @@ impl TemplatingRouter {
 
     pub async fn add_template_endpoint(
         State(mut templating_service): State<StateTemplatingService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         Json(request): Json<AddTemplateEndpointRequest>,
     ) -> ServiceResult<Json<TemplateEndpointResponse>> {
         info!("Add Template Endpoint");
-
-        token_service.decode_bearer_token(authorization.token())?;
         request.validate()?;
         let add_template_request: AddTemplateRequest =
             if let (Some(name), Some(description), Some(body), Some(template_inputs)) = (
@@ -106,13 +108,10 @@ impl TemplatingRouter {
 
     pub async fn remove_template_endpoint(
         State(mut templating_service): State<StateTemplatingService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         Path(template_name): Path<String>,
     ) -> ServiceResult<Json<TemplateEndpointResponse>> {
         info!("Remove Template Endpoint");
 
-        token_service.decode_bearer_token(authorization.token())?;
         let remove_template_request = RemoveTemplateRequest {
             name: template_name,
         };
@@ -1,6 +1,8 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Extension, Path, Query, State},
     headers::{authorization::Bearer, Authorization},
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
     routing::{delete, get, post},
     Json, Router, TypedHeader,
 };
@@ -11,9 +13,10 @@ use madtofan_microservice_common::{
     user::{
         update_request::UpdateFields, AuthorizeRevokeUser, GetListRequest, GetUserRequest,
         LoginRequest, RefreshTokenRequest, RegisterRequest, Role, RolesPermissionsRequest,
-        UpdateRequest, VerifyRegistrationRequest, VerifyTokenRequest,
+        UpdateRequest, UserResponse, VerifyRegistrationRequest,
     },
 };
+use tonic::Code;
 use urlencoding::{decode, encode};
 use validator::Validate;
 
@@ -21,28 +24,54 @@ use crate::{
     request::{
         user::{
             AddRolePermissionRequest, AuthorizeRevokeRolePermissionRequest,
-            AuthorizeRevokeUserRoleRequest, LoginEndpointRequest, RefreshtokenEndpointRequest,
-            RegisterEndpointRequest, UpdateEndpointRequest,
+            AuthorizeRevokeUserRoleRequest, CreateAccessTokenEndpointRequest,
+            ForgotPasswordEndpointRequest, LoginEndpointRequest, LoginMfaEndpointRequest,
+            MagicLinkEndpointRequest, MagicLinkLoginEndpointRequest, OAuthCallbackEndpointRequest,
+            RefreshtokenEndpointRequest, RegisterEndpointRequest,
+            ResendVerificationEndpointRequest, ResetPasswordEndpointRequest,
+            TotpCodeEndpointRequest, UpdateEndpointRequest, WebauthnLoginEndpointRequest,
+            WebauthnLoginStartEndpointRequest, WebauthnRegisterEndpointRequest,
         },
         Pagination,
     },
     response::{
         user::{
-            ObtainTokenResponse, PermissionsListResponse, RegisterUserEndpointResponse,
-            RolesListResponse, UserEndpointResponse, UserListEndpointResponse,
+            AccessTokenEndpointResponse, AccessTokenListEndpointResponse, AuditLogEndpointResponse,
+            ImpersonationEndpointResponse, LoginEndpointResponse, LoginHistoryEndpointResponse,
+            MfaRequiredResponse, ObtainTokenResponse, PasskeyEndpointResponse,
+            PasskeyListEndpointResponse, PermissionsListResponse, RecoveryCodesEndpointResponse,
+            RegisterUserEndpointResponse, RolesListResponse, SessionEndpointResponse,
+            SessionListEndpointResponse, TotpSetupEndpointResponse, UserEndpointResponse,
+            UserListEndpointResponse, WebauthnLoginOptionsResponse,
+            WebauthnRegistrationOptionsResponse,
         },
         StatusMessageResponse,
     },
     utilities::{
-        constants::PAGINATION_SIZE,
+        access_tokens::ACCESS_TOKEN_PREFIX,
+        authorization::RequirePermissionLayer,
+        constants::{
+            AUDIT_READ, IMPERSONATE, PAGINATION_SIZE, PERMISSION_DELETE, PERMISSION_READ,
+            PERMISSION_WRITE, ROLE_DELETE, ROLE_READ, ROLE_WRITE, USER_READ, USER_WRITE,
+        },
+        cookies::DEVICE_ID_LENGTH,
+        helpers::random_string,
+        notifications::{notify_permissions_changed, notify_user},
         service_register::ServiceRegister,
+        sessions::SessionClient,
         states::{
-            email_service::StateEmailService, templating_service::StateTemplatingService,
-            token_service::StateTokenService, user_service::StateUserService,
+            alert_services::StateAlertServices, audit_service::StateAuditService,
+            channels::StateChannelsService, email_service::StateEmailService,
+            login_history::StateLoginHistory, login_services::StateLoginServices,
+            mfa_service::StateMfaService, oauth_service::StateOAuthService,
+            templating_service::StateTemplatingService, token_service::StateTokenService,
+            user_directory::StateUserDirectory, user_service::StateUserService,
+            verification_service::StateVerificationService, webauthn_service::StateWebauthnService,
         },
+        token::{BearerClaims, RefreshClaims, Tokens},
     },
 };
-use tracing::info;
+use tracing::{info, warn};
 
 pub struct UserRouter;
 
@@ -56,32 +85,157 @@ impl UserRouter {
                     .put(UserRouter::update_user_endpoint),
             )
             .route("/login", post(UserRouter::login_user_endpoint))
+            .route("/login/mfa", post(UserRouter::login_mfa_endpoint))
+            .route("/login/magic", post(UserRouter::magic_link_endpoint))
+            .route(
+                "/login/magic/:token",
+                get(UserRouter::magic_link_confirm_endpoint)
+                    .post(UserRouter::magic_link_login_endpoint),
+            )
+            .route(
+                "/oauth/:provider/start",
+                get(UserRouter::oauth_start_endpoint),
+            )
+            .route(
+                "/oauth/:provider/callback",
+                get(UserRouter::oauth_callback_endpoint),
+            )
             .route("/refresh", post(UserRouter::refresh_token_endpoint))
+            .route("/logout", post(UserRouter::logout_endpoint))
+            .route("/logout-all", post(UserRouter::logout_all_endpoint))
+            .route(
+                "/password/forgot",
+                post(UserRouter::forgot_password_endpoint),
+            )
+            .route("/password/reset", post(UserRouter::reset_password_endpoint))
             .route(
                 "/verify/:token",
                 get(UserRouter::verify_registration_endpoint),
             )
+            .route(
+                "/verify/resend",
+                post(UserRouter::resend_verification_endpoint),
+            )
+            .route("/mfa/totp/setup", post(UserRouter::totp_setup_endpoint))
+            .route("/mfa/totp/confirm", post(UserRouter::totp_confirm_endpoint))
+            .route("/mfa/totp/disable", post(UserRouter::totp_disable_endpoint))
+            .route(
+                "/webauthn/register/start",
+                post(UserRouter::webauthn_register_start_endpoint),
+            )
+            .route(
+                "/webauthn/register/finish",
+                post(UserRouter::webauthn_register_finish_endpoint),
+            )
+            .route(
+                "/webauthn/login/start",
+                post(UserRouter::webauthn_login_start_endpoint),
+            )
+            .route(
+                "/webauthn/login/finish",
+                post(UserRouter::webauthn_login_finish_endpoint),
+            )
+            .route(
+                "/webauthn/credentials",
+                get(UserRouter::list_passkeys_endpoint),
+            )
+            .route(
+                "/webauthn/credentials/:credential_id",
+                delete(UserRouter::delete_passkey_endpoint),
+            )
+            .route(
+                "/login-history",
+                get(UserRouter::get_login_history_endpoint),
+            )
+            .route("/sessions", get(UserRouter::list_sessions_endpoint))
+            .route(
+                "/sessions/:session_id",
+                delete(UserRouter::revoke_session_endpoint),
+            )
+            .route(
+                "/tokens",
+                get(UserRouter::list_access_tokens_endpoint)
+                    .post(UserRouter::create_access_token_endpoint),
+            )
+            .route(
+                "/tokens/:token_id",
+                delete(UserRouter::revoke_access_token_endpoint),
+            )
+            .route(
+                "/impersonate/stop",
+                post(UserRouter::stop_impersonation_endpoint),
+            )
+            .route(
+                "/impersonate/:user_id",
+                post(UserRouter::start_impersonation_endpoint)
+                    .route_layer(RequirePermissionLayer::new(&service_register, IMPERSONATE)),
+            )
+            .route(
+                "/audit",
+                get(UserRouter::get_audit_log)
+                    .route_layer(RequirePermissionLayer::new(&service_register, AUDIT_READ)),
+            )
             .route(
                 "/roles",
-                get(UserRouter::get_roles).post(UserRouter::add_role),
+                get(UserRouter::get_roles)
+                    .route_layer(RequirePermissionLayer::new(&service_register, ROLE_READ)),
+            )
+            .route(
+                "/roles",
+                post(UserRouter::add_role)
+                    .route_layer(RequirePermissionLayer::new(&service_register, ROLE_WRITE)),
+            )
+            .route(
+                "/roles/:role_name",
+                delete(UserRouter::delete_role)
+                    .route_layer(RequirePermissionLayer::new(&service_register, ROLE_DELETE)),
             )
-            .route("/roles/:role_name", delete(UserRouter::delete_role))
             .route(
                 "/permissions",
-                get(UserRouter::get_permissions).post(UserRouter::add_permission),
+                get(UserRouter::get_permissions).route_layer(RequirePermissionLayer::new(
+                    &service_register,
+                    PERMISSION_READ,
+                )),
+            )
+            .route(
+                "/permissions",
+                post(UserRouter::add_permission).route_layer(RequirePermissionLayer::new(
+                    &service_register,
+                    PERMISSION_WRITE,
+                )),
             )
             .route(
                 "/permissions/:permission_name",
-                delete(UserRouter::delete_permission),
+                delete(UserRouter::delete_permission).route_layer(RequirePermissionLayer::new(
+                    &service_register,
+                    PERMISSION_DELETE,
+                )),
+            )
+            .route(
+                "/authorize/user/:user_id",
+                post(UserRouter::authorize_user)
+                    .route_layer(RequirePermissionLayer::new(&service_register, USER_WRITE)),
+            )
+            .route(
+                "/revoke/user/:user_id",
+                post(UserRouter::revoke_user)
+                    .route_layer(RequirePermissionLayer::new(&service_register, USER_WRITE)),
             )
-            .route("/authorize/user/:user_id", post(UserRouter::authorize_user))
-            .route("/revoke/user/:user_id", post(UserRouter::revoke_user))
             .route(
                 "/authorize/role/:role_name",
-                post(UserRouter::authorize_role),
+                post(UserRouter::authorize_role)
+                    .route_layer(RequirePermissionLayer::new(&service_register, ROLE_WRITE)),
+            )
+            .route(
+                "/revoke/role/:role_name",
+                post(UserRouter::revoke_role)
+                    .route_layer(RequirePermissionLayer::new(&service_register, ROLE_WRITE)),
+            )
+            .route(
+                "/list",
+                get(UserRouter::get_users)
+                    .route_layer(RequirePermissionLayer::new(&service_register, USER_READ)),
             )
-            .route("/revoke/role/:role_name", post(UserRouter::revoke_role))
-            .route("/list", get(UserRouter::get_users))
             .with_state(service_register)
     }
 
@@ -90,6 +244,7 @@ impl UserRouter {
         State(mut email_service): State<StateEmailService>,
         State(mut templating_service): State<StateTemplatingService>,
         State(token_service): State<StateTokenService>,
+        State(user_directory): State<StateUserDirectory>,
         Json(request): Json<RegisterEndpointRequest>,
     ) -> ServiceResult<Json<RegisterUserEndpointResponse>> {
         info!("Register User Endpoint");
@@ -116,46 +271,19 @@ impl UserRouter {
 
         info!("Sending request to User Service");
         let user = user_service.register(register_request).await?.into_inner();
+        user_directory.record(&user.email, user.id);
 
         info!("Success response by User Service creating verification token");
-        let verify_token =
-            encode(&token_service.create_verify_registration_token(user.id)?).into_owned();
-
-        info!("Composing email for verification");
-        let compose_request: ComposeRequest = ComposeRequest {
-            name: "registration".to_string(),
-            input_values: vec![
-                InputValue {
-                    name: "name".to_string(),
-                    value: format!(
-                        "{:#?} {:#?}",
-                        user.first_name.clone(),
-                        user.last_name.clone()
-                    ),
-                },
-                InputValue {
-                    name: "verification_token".to_string(),
-                    value: verify_token.clone(),
-                },
-            ],
-        };
-
-        let email_template = templating_service
-            .compose(compose_request)
-            .await?
-            .into_inner();
-
-        info!(
-            "Sending verification email for token: {}",
-            verify_token.clone()
-        );
-        let send_email_request: SendEmailRequest = SendEmailRequest {
-            email: user.email.clone(),
-            title: "Thank you for registering".to_string(),
-            body: email_template.result,
-        };
-
-        email_service.send_email(send_email_request).await?;
+        let verify_token = UserRouter::send_registration_email(
+            &mut email_service,
+            &mut templating_service,
+            &token_service,
+            user.id,
+            &user.email,
+            &user.first_name,
+            &user.last_name,
+        )
+        .await?;
 
         info!("Verification email sent");
         Ok(Json(RegisterUserEndpointResponse {
@@ -169,6 +297,7 @@ impl UserRouter {
         State(mut email_service): State<StateEmailService>,
         State(mut templating_service): State<StateTemplatingService>,
         State(token_service): State<StateTokenService>,
+        State(user_directory): State<StateUserDirectory>,
         Path(token): Path<String>,
     ) -> ServiceResult<Json<UserEndpointResponse>> {
         info!("Verify Registration Endpoint");
@@ -179,15 +308,22 @@ impl UserRouter {
                 )
             })?
             .into_owned();
-        let user_id = token_service.decode_verify_registration_token(&verification_token)?;
+        let user_id = token_service.consume_verify_registration_token(&verification_token)?;
 
         let verify_registration_request: VerifyRegistrationRequest =
             VerifyRegistrationRequest { id: user_id };
 
-        let user = user_service
+        let user = match user_service
             .verify_registration(verify_registration_request)
-            .await?
-            .into_inner();
+            .await
+        {
+            Ok(response) => response.into_inner(),
+            Err(status) => {
+                token_service.restore_verify_registration_token(&verification_token);
+                return Err(status.into());
+            }
+        };
+        user_directory.record(&user.email, user.id);
 
         let compose_request: ComposeRequest = ComposeRequest {
             name: "verified".to_string(),
@@ -217,11 +353,57 @@ impl UserRouter {
         Ok(Json(UserEndpointResponse::from_user_response(user)))
     }
 
-    pub async fn login_user_endpoint(
-        State(mut user_service): State<StateUserService>,
+    pub async fn resend_verification_endpoint(
+        State(user_service): State<StateUserService>,
+        State(email_service): State<StateEmailService>,
+        State(templating_service): State<StateTemplatingService>,
         State(token_service): State<StateTokenService>,
+        State(verification_service): State<StateVerificationService>,
+        State(user_directory): State<StateUserDirectory>,
+        Json(request): Json<ResendVerificationEndpointRequest>,
+    ) -> ServiceResult<Response> {
+        info!("Resend Verification Endpoint");
+        request.validate()?;
+        let email = request.email.unwrap_or_default();
+
+        if let Err(retry_after) = verification_service.try_resend(&email) {
+            info!("Verification resend throttled for {} seconds", retry_after);
+            return Ok(StatusMessageResponse::too_many_requests(
+                "Verification email was sent recently, please try again later".to_string(),
+                retry_after,
+            ));
+        }
+
+        info!("Resending verification email in the background");
+        tokio::spawn(async move {
+            if let Err(err) = UserRouter::resend_registration_email(
+                user_service,
+                email_service,
+                templating_service,
+                token_service,
+                user_directory,
+                email,
+            )
+            .await
+            {
+                warn!("Unable to resend verification email: {}", err);
+            }
+        });
+
+        Ok(Json(StatusMessageResponse {
+            status:
+                "If the account exists and is not verified yet, a verification email has been sent"
+                    .to_string(),
+        })
+        .into_response())
+    }
+
+    pub async fn login_user_endpoint(
+        State(alert_services): State<StateAlertServices>,
+        State(mut login_services): State<StateLoginServices>,
+        client: SessionClient,
         Json(request): Json<LoginEndpointRequest>,
-    ) -> ServiceResult<Json<ObtainTokenResponse>> {
+    ) -> ServiceResult<Response> {
         info!("Login User Endpoint, creating service request...");
         request.validate()?;
         let login_request: LoginRequest =
@@ -232,72 +414,915 @@ impl UserRouter {
                     "Missing parameters in the request".to_string(),
                 ))
             }?;
+        let email = login_request.email.clone();
+        let ip_address = client.ip_address.as_deref();
+        let login_throttle = &login_services.login_throttle;
+        let login_history = &login_services.login_history;
+
+        if let Err(retry_after) = login_throttle.check(&email, ip_address) {
+            info!("Login attempt throttled for {} seconds", retry_after);
+            login_history.record(None, &email, "password", false, &client);
+            return Ok(StatusMessageResponse::too_many_requests(
+                "Too many failed login attempts, please try again later".to_string(),
+                retry_after,
+            ));
+        }
 
         info!("Created Service Request, obtaining response from User service...");
-        // let user = user_service.login(login_request).await?.into_inner();
-        let user_result = user_service.login(login_request).await;
-        dbg!(&user_result);
-        let user = user_result?.into_inner();
+        let user = match login_services.user_service.login(login_request).await {
+            Ok(response) => response.into_inner(),
+            Err(status) => {
+                let failed_attempt = matches!(
+                    status.code(),
+                    Code::Unauthenticated
+                        | Code::NotFound
+                        | Code::InvalidArgument
+                        | Code::PermissionDenied
+                );
+                if failed_attempt {
+                    login_history.record(None, &email, "password", false, &client);
+                    UserRouter::record_failed_login(
+                        &login_services,
+                        alert_services,
+                        &email,
+                        &client,
+                    );
+                }
+                return Err(status.into());
+            }
+        };
+        login_services.user_directory.record(&user.email, user.id);
+
+        UserRouter::complete_login(
+            &login_services,
+            alert_services,
+            &user,
+            "password",
+            &client,
+            request.device_label,
+            request.use_cookie.unwrap_or_default(),
+        )
+    }
+
+    pub async fn login_mfa_endpoint(
+        State(alert_services): State<StateAlertServices>,
+        State(mut login_services): State<StateLoginServices>,
+        client: SessionClient,
+        Json(request): Json<LoginMfaEndpointRequest>,
+    ) -> ServiceResult<Response> {
+        info!("Login MFA Endpoint, decoding mfa token...");
+        request.validate()?;
+        let token_service = &login_services.token_service;
+        let login_throttle = &login_services.login_throttle;
+        let login_history = &login_services.login_history;
+        let claims = token_service.decode_mfa_pending_token(&request.mfa_token.unwrap())?;
+        let user = login_services
+            .user_service
+            .get_user(GetUserRequest { id: claims.user_id })
+            .await?
+            .into_inner();
+        let ip_address = client.ip_address.as_deref();
+
+        if let Err(retry_after) = login_throttle.check(&user.email, ip_address) {
+            info!("Mfa attempt throttled for {} seconds", retry_after);
+            login_history.record(Some(user.id), &user.email, "mfa", false, &client);
+            return Ok(StatusMessageResponse::too_many_requests(
+                "Too many failed login attempts, please try again later".to_string(),
+                retry_after,
+            ));
+        }
+
+        info!("Mfa token decoded, verifying authentication code...");
+        if let Err(err) = login_services
+            .mfa_service
+            .verify(claims.user_id, &request.code.unwrap())
+        {
+            login_history.record(Some(user.id), &user.email, "mfa", false, &client);
+            if token_service.record_mfa_failure(&claims) {
+                warn!("Too many wrong authentication codes, mfa token burned");
+            }
+            UserRouter::record_failed_login(&login_services, alert_services, &user.email, &client);
+            return Err(err);
+        }
+        token_service.consume_mfa_pending_token(&claims)?;
+
+        info!("Authentication code verified, starting session...");
+        UserRouter::start_session(
+            &login_services,
+            alert_services,
+            &user,
+            "mfa",
+            &client,
+            request.device_label,
+            request.use_cookie.unwrap_or_default(),
+        )
+    }
 
-        info!("Obtained response from service, creating bearer token...");
-        let tokens = token_service.create_token(user.id, &user.email, &user.roles)?;
+    pub async fn magic_link_endpoint(
+        State(user_service): State<StateUserService>,
+        State(email_service): State<StateEmailService>,
+        State(templating_service): State<StateTemplatingService>,
+        State(token_service): State<StateTokenService>,
+        State(user_directory): State<StateUserDirectory>,
+        Json(request): Json<MagicLinkEndpointRequest>,
+    ) -> ServiceResult<(StatusCode, Json<StatusMessageResponse>)> {
+        info!("Magic Link Endpoint");
+        request.validate()?;
+        let email = request.email.unwrap_or_default();
+
+        info!("Sending magic link email in the background");
+        tokio::spawn(async move {
+            if let Err(err) = UserRouter::send_magic_link_email(
+                user_service,
+                email_service,
+                templating_service,
+                token_service,
+                user_directory,
+                email,
+            )
+            .await
+            {
+                warn!("Unable to send magic link email: {}", err);
+            }
+        });
+
+        Ok((
+            StatusCode::ACCEPTED,
+            Json(StatusMessageResponse {
+                status: "If the account exists, a sign in link has been sent".to_string(),
+            }),
+        ))
+    }
 
-        info!("Token created, updating user token!");
+    /// Opening the link only asks to confirm the sign in, so link scanners that follow it do not
+    /// use it up. The form posts back to the same url, query included.
+    pub async fn magic_link_confirm_endpoint(
+        State(token_service): State<StateTokenService>,
+        Path(token): Path<String>,
+    ) -> ServiceResult<Html<&'static str>> {
+        info!("Magic Link Confirm Endpoint, checking magic link token...");
+        let magic_link_token = decode(&token)
+            .map_err(|_| ServiceError::BadRequest("Unable to decode sign in link".to_string()))?;
+        token_service.check_magic_link_token(&magic_link_token)?;
+
+        Ok(Html(concat!(
+            "<!DOCTYPE html><html><head><title>Sign in</title></head><body>",
+            "<form method=\"post\"><button type=\"submit\">Sign in</button></form>",
+            "</body></html>"
+        )))
+    }
+
+    pub async fn magic_link_login_endpoint(
+        State(alert_services): State<StateAlertServices>,
+        State(mut login_services): State<StateLoginServices>,
+        client: SessionClient,
+        Path(token): Path<String>,
+        Query(request): Query<MagicLinkLoginEndpointRequest>,
+    ) -> ServiceResult<Response> {
+        request.validate()?;
+
+        info!("Magic Link Login Endpoint, consuming magic link token...");
+        let magic_link_token = decode(&token)
+            .map_err(|_| ServiceError::BadRequest("Unable to decode sign in link".to_string()))?
+            .into_owned();
+        let user_id = login_services
+            .token_service
+            .consume_magic_link_token(&magic_link_token)?;
+
+        let user = match login_services
+            .user_service
+            .get_user(GetUserRequest { id: user_id })
+            .await
+        {
+            Ok(response) => response.into_inner(),
+            Err(status) => {
+                login_services
+                    .token_service
+                    .restore_magic_link_token(&magic_link_token);
+                return Err(status.into());
+            }
+        };
+
+        info!("Magic link consumed, completing login...");
+        UserRouter::complete_login(
+            &login_services,
+            alert_services,
+            &user,
+            "magic_link",
+            &client,
+            request.device_label,
+            request.use_cookie.unwrap_or_default(),
+        )
+    }
+
+    pub async fn oauth_start_endpoint(
+        State(oauth_service): State<StateOAuthService>,
+        Path(provider): Path<String>,
+    ) -> ServiceResult<(HeaderMap, Redirect)> {
+        info!("OAuth Start Endpoint, creating authorization url...");
+        let (headers, authorization_url) = oauth_service.authorization_url(&provider).await?;
+
+        info!("Authorization url created, redirecting to provider!");
+        Ok((headers, Redirect::to(&authorization_url)))
+    }
+
+    pub async fn oauth_callback_endpoint(
+        State(alert_services): State<StateAlertServices>,
+        State(mut login_services): State<StateLoginServices>,
+        State(oauth_service): State<StateOAuthService>,
+        Path(provider): Path<String>,
+        client: SessionClient,
+        headers: HeaderMap,
+        Query(request): Query<OAuthCallbackEndpointRequest>,
+    ) -> ServiceResult<Response> {
+        info!("OAuth Callback Endpoint, exchanging authorization code...");
+        if let Some(error) = request.error {
+            return Err(ServiceError::BadRequest(format!(
+                "Sign in was rejected by the provider: {}",
+                request.error_description.unwrap_or(error)
+            )));
+        }
+        let identity = if let (Some(code), Some(state)) = (request.code, request.state) {
+            oauth_service
+                .exchange_code(&provider, &code, &state, &headers)
+                .await
+        } else {
+            Err(ServiceError::BadRequest(
+                "Missing parameters in the request".to_string(),
+            ))
+        }?;
+
+        info!("Identity obtained, finding linked user...");
+        let user_service = &mut login_services.user_service;
+        let user_directory = &login_services.user_directory;
+        let user_id = match oauth_service.linked_user(&provider, &identity.subject) {
+            Some(user_id) => user_id,
+            None => {
+                let email = identity
+                    .email
+                    .filter(|_| identity.email_verified)
+                    .ok_or_else(|| {
+                        ServiceError::BadRequest(
+                            "The provider did not return a verified email".to_string(),
+                        )
+                    })?;
+                let user_id =
+                    match UserRouter::find_user_by_email(user_service, user_directory, &email)
+                        .await?
+                    {
+                        Some(user) => user.id,
+                        None => {
+                            info!("No user with this email, registering user...");
+                            let user = user_service
+                                .register(RegisterRequest {
+                                    email: email.clone(),
+                                    password: random_string(30),
+                                    first_name: identity.first_name.unwrap_or(email.clone()),
+                                    last_name: identity.last_name.unwrap_or_default(),
+                                })
+                                .await?
+                                .into_inner();
+                            user_service
+                                .verify_registration(VerifyRegistrationRequest { id: user.id })
+                                .await?;
+                            user_directory.record(&user.email, user.id);
+                            user.id
+                        }
+                    };
+                oauth_service.link(&provider, &identity.subject, user_id);
+                user_id
+            }
+        };
+
+        let user = user_service
+            .get_user(GetUserRequest { id: user_id })
+            .await?
+            .into_inner();
+
+        info!("Obtained user, completing login...");
+        let response = UserRouter::complete_login(
+            &login_services,
+            alert_services,
+            &user,
+            "oauth",
+            &client,
+            None,
+            false,
+        )?;
+        Ok((oauth_service.clear_state_cookie()?, response).into_response())
+    }
+
+    pub async fn refresh_token_endpoint(
+        State(mut user_service): State<StateUserService>,
+        State(mut alert_services): State<StateAlertServices>,
+        State(login_services): State<StateLoginServices>,
+        client: SessionClient,
+        headers: HeaderMap,
+        Json(request): Json<RefreshtokenEndpointRequest>,
+    ) -> ServiceResult<(HeaderMap, Json<ObtainTokenResponse>)> {
+        info!("Refresh token Endpoint, creating service request...");
+        request.validate()?;
+        let token_service = &login_services.token_service;
+        let login_history = &login_services.login_history;
+        let use_cookie = request.token.is_none();
+        let refresh_token = match request.token {
+            Some(token) => token,
+            None => token_service.refresh_cookies().refresh_token(&headers)?,
+        };
+        let claims = token_service.decode_refresh_token(&refresh_token)?;
+        let user_id = claims.user_id;
+
+        if token_service.is_refresh_token_reused(&claims) {
+            return Err(UserRouter::refresh_token_reused(
+                &mut alert_services,
+                &login_services,
+                &claims,
+                &client,
+            )
+            .await);
+        }
+
+        info!("Token decoded, obtaining user roles...");
+        let user = user_service
+            .get_user(GetUserRequest { id: claims.user_id })
+            .await?
+            .into_inner();
+
+        info!("Validated token, creating token...");
+        let Some(tokens) = token_service.rotate_token(&claims, &user.roles, &client)? else {
+            return Err(UserRouter::refresh_token_reused(
+                &mut alert_services,
+                &login_services,
+                &claims,
+                &client,
+            )
+            .await);
+        };
+        login_history.record(Some(user_id), &claims.user_email, "refresh", true, &client);
+
+        info!("Session renewed, returning response!");
+        let (headers, response) = UserRouter::token_response(token_service, tokens, use_cookie)?;
+        Ok((headers, Json(response)))
+    }
+
+    pub async fn logout_endpoint(
+        State(mut user_service): State<StateUserService>,
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<(HeaderMap, Json<StatusMessageResponse>)> {
+        info!("Logout Endpoint, obtaining authorization...");
+        let bearer_claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, ending session...");
+        if let Some(session_id) = &bearer_claims.sid {
+            token_service.revoke_session(bearer_claims.user_id, session_id);
+        }
+        token_service.revoke_bearer_token(&bearer_claims);
+
+        // Ending an impersonation leaves the account of the impersonated user alone.
+        if !bearer_claims.is_impersonated() {
+            info!("Session ended, clearing user token...");
+            user_service
+                .refresh_token(RefreshTokenRequest {
+                    id: bearer_claims.user_id,
+                    token: String::new(),
+                })
+                .await?;
+        }
+
+        info!("Logged out, returning response!");
+        Ok((
+            token_service.refresh_cookies().clear()?,
+            Json(StatusMessageResponse {
+                status: "Successfully logged out".to_string(),
+            }),
+        ))
+    }
+
+    pub async fn logout_all_endpoint(
+        State(mut user_service): State<StateUserService>,
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<StatusMessageResponse>> {
+        info!("Logout All Endpoint, obtaining authorization...");
+        let bearer_claims = token_service.decode_bearer_token(authorization.token())?;
+        bearer_claims.require_own_account()?;
+
+        info!("Obtained authorization, ending all sessions...");
+        token_service.revoke_user_tokens(bearer_claims.user_id);
+
+        info!("Sessions ended, clearing user token...");
         user_service
             .refresh_token(RefreshTokenRequest {
-                id: user.id,
-                token: tokens.clone().refresh,
+                id: bearer_claims.user_id,
+                token: String::new(),
             })
             .await?;
 
-        info!("User token updated, returning response!");
-        Ok(Json(ObtainTokenResponse::from_tokens(tokens)))
+        info!("User token cleared, returning response!");
+        Ok(Json(StatusMessageResponse {
+            status: "Successfully logged out from all devices".to_string(),
+        }))
     }
 
-    pub async fn refresh_token_endpoint(
+    pub async fn forgot_password_endpoint(
+        State(user_service): State<StateUserService>,
+        State(email_service): State<StateEmailService>,
+        State(templating_service): State<StateTemplatingService>,
+        State(token_service): State<StateTokenService>,
+        State(user_directory): State<StateUserDirectory>,
+        Json(request): Json<ForgotPasswordEndpointRequest>,
+    ) -> ServiceResult<(StatusCode, Json<StatusMessageResponse>)> {
+        info!("Forgot Password Endpoint");
+        request.validate()?;
+        let email = request.email.unwrap_or_default();
+
+        info!("Sending password reset email in the background");
+        tokio::spawn(async move {
+            if let Err(err) = UserRouter::send_password_reset_email(
+                user_service,
+                email_service,
+                templating_service,
+                token_service,
+                user_directory,
+                email,
+            )
+            .await
+            {
+                warn!("Unable to send password reset email: {}", err);
+            }
+        });
+
+        Ok((
+            StatusCode::ACCEPTED,
+            Json(StatusMessageResponse {
+                status: "If the account exists, a password reset email has been sent".to_string(),
+            }),
+        ))
+    }
+
+    pub async fn reset_password_endpoint(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        Json(request): Json<RefreshtokenEndpointRequest>,
-    ) -> ServiceResult<Json<ObtainTokenResponse>> {
-        info!("Refresh token Endpoint, creating service request...");
+        Json(request): Json<ResetPasswordEndpointRequest>,
+    ) -> ServiceResult<Json<StatusMessageResponse>> {
+        info!("Reset Password Endpoint");
         request.validate()?;
-        let refresh_token = request.token.unwrap();
-        let claims = token_service.decode_refresh_token(&refresh_token.clone())?;
-        let email = claims.user_email;
-        let user_id = claims.user_id;
-        info!("Token decoded, checking if token match user...");
-        let is_valid_token = user_service
-            .verify_token(VerifyTokenRequest {
-                id: claims.user_id,
-                token: refresh_token,
+        let (token, password) =
+            if let (Some(token), Some(password)) = (request.token, request.password) {
+                Ok((token, password))
+            } else {
+                Err(ServiceError::BadRequest(
+                    "Missing parameters in the request".to_string(),
+                ))
+            }?;
+        let user_id = token_service.consume_password_reset_token(&token)?;
+
+        info!("Reset token validated, updating password...");
+        let update_response = user_service
+            .update(UpdateRequest {
+                id: user_id,
+                fields: Some(UpdateFields {
+                    password: Some(password),
+                    ..Default::default()
+                }),
             })
+            .await;
+        if let Err(status) = update_response {
+            token_service.restore_password_reset_token(&token);
+            return Err(status.into());
+        }
+
+        info!("Password updated, ending all sessions...");
+        token_service.revoke_user_tokens(user_id);
+
+        Ok(Json(StatusMessageResponse {
+            status: "Password has been reset, please login again".to_string(),
+        }))
+    }
+
+    pub async fn totp_setup_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(mfa_service): State<StateMfaService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<TotpSetupEndpointResponse>> {
+        info!("TOTP Setup Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+
+        info!("Obtained authorization, generating secret...");
+        let setup = mfa_service.begin_enrollment(claims.user_id, &claims.sub)?;
+
+        info!("Secret generated, returning response!");
+        Ok(Json(TotpSetupEndpointResponse {
+            secret: setup.secret,
+            otpauth_uri: setup.otpauth_uri,
+        }))
+    }
+
+    pub async fn totp_confirm_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(mfa_service): State<StateMfaService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Json(request): Json<TotpCodeEndpointRequest>,
+    ) -> ServiceResult<Json<RecoveryCodesEndpointResponse>> {
+        info!("TOTP Confirm Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+        request.validate()?;
+
+        info!("Obtained authorization, confirming enrollment...");
+        let recovery_codes =
+            mfa_service.confirm_enrollment(claims.user_id, &request.code.unwrap())?;
+
+        info!("Enrollment confirmed, returning response!");
+        Ok(Json(RecoveryCodesEndpointResponse { recovery_codes }))
+    }
+
+    pub async fn totp_disable_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(mfa_service): State<StateMfaService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Json(request): Json<TotpCodeEndpointRequest>,
+    ) -> ServiceResult<Json<StatusMessageResponse>> {
+        info!("TOTP Disable Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+        request.validate()?;
+
+        info!("Obtained authorization, disabling two-factor authentication...");
+        mfa_service.disable(claims.user_id, &request.code.unwrap())?;
+
+        info!("Two-factor authentication disabled, returning response!");
+        Ok(Json(StatusMessageResponse {
+            status: "Two-factor authentication disabled".to_string(),
+        }))
+    }
+
+    pub async fn webauthn_register_start_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(webauthn_service): State<StateWebauthnService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<WebauthnRegistrationOptionsResponse>> {
+        info!("Webauthn Register Start Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+
+        info!("Obtained authorization, creating registration challenge...");
+        let options = webauthn_service.begin_registration(claims.user_id);
+
+        info!("Challenge created, returning response!");
+        Ok(Json(WebauthnRegistrationOptionsResponse {
+            challenge: options.challenge,
+            rp_id: options.rp_id,
+            rp_name: options.rp_name,
+            user_handle: options.user_handle,
+            user_name: claims.sub,
+            algorithms: options.algorithms,
+            exclude_credentials: options.exclude_credentials,
+            user_verification: "required".to_string(),
+            timeout_seconds: options.timeout_seconds,
+        }))
+    }
+
+    pub async fn webauthn_register_finish_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(webauthn_service): State<StateWebauthnService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Json(request): Json<WebauthnRegisterEndpointRequest>,
+    ) -> ServiceResult<(StatusCode, Json<PasskeyEndpointResponse>)> {
+        info!("Webauthn Register Finish Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+        request.validate()?;
+
+        info!("Obtained authorization, verifying attestation...");
+        let record = webauthn_service.finish_registration(
+            claims.user_id,
+            &request.name.unwrap(),
+            &request.client_data_json.unwrap(),
+            &request.attestation_object.unwrap(),
+        )?;
+
+        info!("Passkey registered, returning response!");
+        Ok((
+            StatusCode::CREATED,
+            Json(PasskeyEndpointResponse::from_record(record)),
+        ))
+    }
+
+    pub async fn webauthn_login_start_endpoint(
+        State(mut user_service): State<StateUserService>,
+        State(webauthn_service): State<StateWebauthnService>,
+        State(user_directory): State<StateUserDirectory>,
+        Json(request): Json<WebauthnLoginStartEndpointRequest>,
+    ) -> ServiceResult<Json<WebauthnLoginOptionsResponse>> {
+        info!("Webauthn Login Start Endpoint, creating login challenge...");
+        request.validate()?;
+        let user_id = match request.email {
+            Some(email) => {
+                UserRouter::find_user_by_email(&mut user_service, &user_directory, &email)
+                    .await?
+                    .map(|user| user.id)
+            }
+            None => None,
+        };
+        let options = webauthn_service.begin_login(user_id);
+
+        info!("Challenge created, returning response!");
+        Ok(Json(WebauthnLoginOptionsResponse {
+            challenge: options.challenge,
+            rp_id: options.rp_id,
+            allow_credentials: options.allow_credentials,
+            user_verification: "required".to_string(),
+            timeout_seconds: options.timeout_seconds,
+        }))
+    }
+
+    pub async fn webauthn_login_finish_endpoint(
+        State(alert_services): State<StateAlertServices>,
+        State(mut login_services): State<StateLoginServices>,
+        State(webauthn_service): State<StateWebauthnService>,
+        client: SessionClient,
+        Json(request): Json<WebauthnLoginEndpointRequest>,
+    ) -> ServiceResult<Response> {
+        info!("Webauthn Login Finish Endpoint, verifying assertion...");
+        request.validate()?;
+        let user_id = webauthn_service.finish_login(
+            &request.credential_id.unwrap(),
+            &request.client_data_json.unwrap(),
+            &request.authenticator_data.unwrap(),
+            &request.signature.unwrap(),
+        )?;
+
+        let user = login_services
+            .user_service
+            .get_user(GetUserRequest { id: user_id })
+            .await?
+            .into_inner();
+
+        // A user verified passkey already is a second factor, so no totp code is asked for.
+        info!("Assertion verified, starting session...");
+        UserRouter::start_session(
+            &login_services,
+            alert_services,
+            &user,
+            "passkey",
+            &client,
+            request.device_label,
+            request.use_cookie.unwrap_or_default(),
+        )
+    }
+
+    pub async fn list_passkeys_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(webauthn_service): State<StateWebauthnService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<PasskeyListEndpointResponse>> {
+        info!("List Passkeys Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, returning response!");
+        Ok(Json(PasskeyListEndpointResponse {
+            passkeys: webauthn_service
+                .list(claims.user_id)
+                .into_iter()
+                .map(PasskeyEndpointResponse::from_record)
+                .collect(),
+        }))
+    }
+
+    pub async fn delete_passkey_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(webauthn_service): State<StateWebauthnService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Path(credential_id): Path<String>,
+    ) -> ServiceResult<Json<PasskeyEndpointResponse>> {
+        info!("Delete Passkey Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+
+        info!("Obtained authorization, removing passkey...");
+        let record = webauthn_service
+            .remove(claims.user_id, &credential_id)
+            .ok_or_else(|| ServiceError::NotFound(format!("No passkey {}", &credential_id)))?;
+
+        info!("Passkey removed, returning response!");
+        Ok(Json(PasskeyEndpointResponse::from_record(record)))
+    }
+
+    pub async fn get_login_history_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(login_history): State<StateLoginHistory>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        pagination: Query<Pagination>,
+    ) -> ServiceResult<Json<LoginHistoryEndpointResponse>> {
+        info!("Get Login History Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        let pagination: Pagination = pagination.0;
+        let offset = pagination.page.unwrap_or_default() * *PAGINATION_SIZE;
+
+        info!("Obtained authorization, returning response!");
+        let (attempts, count) = login_history.list(
+            claims.user_id,
+            &claims.sub,
+            offset as usize,
+            *PAGINATION_SIZE as usize,
+        );
+        Ok(Json(LoginHistoryEndpointResponse {
+            attempts: attempts.into_iter().map(|attempt| attempt.into()).collect(),
+            count: count as i64,
+        }))
+    }
+
+    pub async fn list_sessions_endpoint(
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<SessionListEndpointResponse>> {
+        info!("List Sessions Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, returning response!");
+        Ok(Json(SessionListEndpointResponse {
+            sessions: token_service
+                .list_sessions(claims.user_id)
+                .into_iter()
+                .map(|session| SessionEndpointResponse::from_record(session, &claims.sid))
+                .collect(),
+        }))
+    }
+
+    pub async fn revoke_session_endpoint(
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Path(session_id): Path<String>,
+    ) -> ServiceResult<Json<SessionEndpointResponse>> {
+        info!("Revoke Session Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, revoking session...");
+        let session = token_service
+            .revoke_session(claims.user_id, &session_id)
+            .ok_or_else(|| ServiceError::NotFound(format!("No session {}", &session_id)))?;
+
+        info!("Session revoked, returning response!");
+        Ok(Json(SessionEndpointResponse::from_record(
+            session,
+            &claims.sid,
+        )))
+    }
+
+    pub async fn create_access_token_endpoint(
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Json(request): Json<CreateAccessTokenEndpointRequest>,
+    ) -> ServiceResult<(StatusCode, Json<AccessTokenEndpointResponse>)> {
+        info!("Create Access Token Endpoint, obtaining authorization...");
+        if authorization.token().starts_with(ACCESS_TOKEN_PREFIX) {
+            return Err(ServiceError::Forbidden);
+        }
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        claims.require_own_account()?;
+        request.validate()?;
+
+        info!("Obtained authorization, creating access token...");
+        let permissions = request
+            .permissions
+            .unwrap_or_else(|| claims.permissions.clone());
+        let (token, record) = token_service.create_personal_access_token(
+            &claims,
+            &request.name.unwrap(),
+            permissions,
+            request
+                .expires_in_days
+                .map(|expires_in_days| expires_in_days as u64 * 86400),
+        )?;
+
+        info!("Access token created, returning response!");
+        Ok((
+            StatusCode::CREATED,
+            Json(AccessTokenEndpointResponse {
+                token: Some(token),
+                ..AccessTokenEndpointResponse::from_record(record)
+            }),
+        ))
+    }
+
+    pub async fn list_access_tokens_endpoint(
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<AccessTokenListEndpointResponse>> {
+        info!("List Access Tokens Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, returning response!");
+        Ok(Json(AccessTokenListEndpointResponse {
+            tokens: token_service
+                .list_personal_access_tokens(claims.user_id)
+                .into_iter()
+                .map(AccessTokenEndpointResponse::from_record)
+                .collect(),
+        }))
+    }
+
+    pub async fn revoke_access_token_endpoint(
+        State(token_service): State<StateTokenService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+        Path(token_id): Path<String>,
+    ) -> ServiceResult<Json<AccessTokenEndpointResponse>> {
+        info!("Revoke Access Token Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+
+        info!("Obtained authorization, revoking access token...");
+        let record = token_service
+            .revoke_personal_access_token(claims.user_id, &token_id)
+            .ok_or_else(|| ServiceError::NotFound(format!("No access token {}", &token_id)))?;
+
+        info!("Access token revoked, returning response!");
+        Ok(Json(AccessTokenEndpointResponse::from_record(record)))
+    }
+
+    pub async fn start_impersonation_endpoint(
+        State(mut user_service): State<StateUserService>,
+        State(token_service): State<StateTokenService>,
+        State(audit_service): State<StateAuditService>,
+        Extension(actor): Extension<BearerClaims>,
+        Path(user_id): Path<String>,
+    ) -> ServiceResult<Json<ImpersonationEndpointResponse>> {
+        info!("Start Impersonation Endpoint, obtaining target user...");
+        actor.require_own_account()?;
+        let user_id = user_id
+            .parse::<i64>()
+            .map_err(|_| ServiceError::BadRequest("Invalid user id".to_string()))?;
+        if user_id == actor.user_id {
+            return Err(ServiceError::BadRequest(
+                "Cannot impersonate yourself".to_string(),
+            ));
+        }
+        let user = user_service
+            .get_user(GetUserRequest { id: user_id })
             .await?
-            .into_inner()
-            .valid;
+            .into_inner();
+
+        info!("Obtained target user, creating impersonation token...");
+        let (bearer_token, claims) =
+            token_service.create_impersonation_token(&actor, user.id, &user.email, &user.roles)?;
+        audit_service.record(
+            "impersonation_started",
+            actor.user_id,
+            &actor.sub,
+            user.id,
+            Some(claims.jti),
+        );
+
+        info!("Impersonation token created, returning response!");
+        Ok(Json(ImpersonationEndpointResponse {
+            user_id: user.id,
+            bearer_token,
+            expires_at: claims.registered.exp as i64,
+        }))
+    }
+
+    pub async fn stop_impersonation_endpoint(
+        State(token_service): State<StateTokenService>,
+        State(audit_service): State<StateAuditService>,
+        authorization: TypedHeader<Authorization<Bearer>>,
+    ) -> ServiceResult<Json<StatusMessageResponse>> {
+        info!("Stop Impersonation Endpoint, obtaining authorization...");
+        let claims = token_service.decode_bearer_token(authorization.token())?;
+        let actor = claims.act.clone().ok_or_else(|| {
+            ServiceError::BadRequest("This token is not impersonating a user".to_string())
+        })?;
+
+        info!("Obtained authorization, revoking impersonation token...");
+        token_service.revoke_bearer_token(&claims);
+        audit_service.record(
+            "impersonation_stopped",
+            actor.user_id,
+            &actor.sub,
+            claims.user_id,
+            Some(claims.jti),
+        );
 
-        let user = user_service
-            .get_user(GetUserRequest { id: claims.user_id })
-            .await?
-            .into_inner();
+        info!("Impersonation stopped, returning response!");
+        Ok(Json(StatusMessageResponse {
+            status: "Impersonation stopped".to_string(),
+        }))
+    }
 
-        match is_valid_token {
-            true => {
-                info!("Validated token, creating token...");
-                let tokens = token_service.create_token(user_id, &email, &user.roles)?;
-
-                info!("Token created, updating user token!");
-                user_service
-                    .refresh_token(RefreshTokenRequest {
-                        id: claims.user_id,
-                        token: tokens.clone().refresh,
-                    })
-                    .await?;
-
-                info!("User token updated, returning response!");
-                Ok(Json(ObtainTokenResponse::from_tokens(tokens)))
-            }
-            false => Err(ServiceError::Unauthorized),
-        }
+    pub async fn get_audit_log(
+        State(audit_service): State<StateAuditService>,
+        pagination: Query<Pagination>,
+    ) -> ServiceResult<Json<AuditLogEndpointResponse>> {
+        info!("Get Audit Log Endpoint");
+        let pagination: Pagination = pagination.0;
+        let offset = pagination.page.unwrap_or_default() * *PAGINATION_SIZE;
+        let (events, count) = audit_service.list(offset as usize, *PAGINATION_SIZE as usize);
+
+        Ok(Json(AuditLogEndpointResponse {
+            events: events.into_iter().map(|event| event.into()).collect(),
+            count: count as i64,
+        }))
     }
 
     pub async fn get_current_user_endpoint(
@@ -328,6 +1353,9 @@ impl UserRouter {
     ) -> ServiceResult<Json<UserEndpointResponse>> {
         info!("Update User Endpoint, obtaining authorization...");
         let bearer_claims = token_service.decode_bearer_token(authorization.token())?;
+        if bearer_claims.is_impersonated() && request.password.is_some() {
+            return Err(ServiceError::Forbidden);
+        }
 
         info!("Obtained authorization, obtaining response from User service...");
         let user = user_service
@@ -350,12 +1378,9 @@ impl UserRouter {
 
     pub async fn get_roles(
         State(mut user_service): State<StateUserService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         pagination: Query<Pagination>,
     ) -> ServiceResult<Json<RolesListResponse>> {
         info!("Get Roles Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
         let pagination = pagination.0;
         let offset = pagination.page.unwrap_or_default() * *PAGINATION_SIZE;
 
@@ -374,12 +1399,9 @@ impl UserRouter {
 
     pub async fn add_role(
         State(mut user_service): State<StateUserService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         Json(request): Json<AddRolePermissionRequest>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Add Role Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, adding role...");
         match request.name {
@@ -401,14 +1423,15 @@ impl UserRouter {
     pub async fn delete_role(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(channels_service): State<StateChannelsService>,
         Path(role_name): Path<String>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Delete Role Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, deleting role {:?}...", &role_name);
-        let delete_role_request = RolesPermissionsRequest { name: role_name };
+        let delete_role_request = RolesPermissionsRequest {
+            name: role_name.clone(),
+        };
 
         let status = user_service
             .delete_role(delete_role_request)
@@ -416,6 +1439,7 @@ impl UserRouter {
             .into_inner();
 
         info!("Role deleted!");
+        UserRouter::role_permissions_changed(&token_service, &channels_service, &role_name).await;
         Ok(Json(StatusMessageResponse {
             status: status.message,
         }))
@@ -423,12 +1447,9 @@ impl UserRouter {
 
     pub async fn get_permissions(
         State(mut user_service): State<StateUserService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         pagination: Query<Pagination>,
     ) -> ServiceResult<Json<PermissionsListResponse>> {
         info!("Get Permissions Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
         let pagination: Pagination = pagination.0;
         let offset = pagination.page.unwrap_or_default() * *PAGINATION_SIZE;
 
@@ -449,12 +1470,9 @@ impl UserRouter {
 
     pub async fn add_permission(
         State(mut user_service): State<StateUserService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
         Json(request): Json<AddRolePermissionRequest>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Add Permission Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, adding permission...");
         match request.name {
@@ -481,18 +1499,17 @@ impl UserRouter {
     pub async fn delete_permission(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(channels_service): State<StateChannelsService>,
         Path(permission_name): Path<String>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Delete Permission Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!(
             "Obtained authorization, deleting permission {:?}...",
             &permission_name
         );
         let delete_permission_request = RolesPermissionsRequest {
-            name: permission_name,
+            name: permission_name.clone(),
         };
 
         let status = user_service
@@ -501,6 +1518,7 @@ impl UserRouter {
             .into_inner();
 
         info!("Permission deleted!");
+        UserRouter::permission_deleted(&token_service, &channels_service, &permission_name).await;
 
         Ok(Json(StatusMessageResponse {
             status: status.message,
@@ -510,12 +1528,11 @@ impl UserRouter {
     pub async fn authorize_role(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(channels_service): State<StateChannelsService>,
         Path(role_name): Path<String>,
         Json(request): Json<AuthorizeRevokeRolePermissionRequest>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Authorize Role Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, adding permission...");
         match request.permissions {
@@ -526,7 +1543,7 @@ impl UserRouter {
                     &role_name, &permissions_string
                 );
                 let authorize_request = Role {
-                    name: role_name,
+                    name: role_name.clone(),
                     permissions,
                 };
 
@@ -536,6 +1553,8 @@ impl UserRouter {
                     .into_inner();
 
                 info!("Role authorized!");
+                UserRouter::role_permissions_changed(&token_service, &channels_service, &role_name)
+                    .await;
 
                 Ok(Json(StatusMessageResponse {
                     status: status.message,
@@ -546,15 +1565,15 @@ impl UserRouter {
             )),
         }
     }
+
     pub async fn revoke_role(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(channels_service): State<StateChannelsService>,
         Path(role_name): Path<String>,
         Json(request): Json<AuthorizeRevokeRolePermissionRequest>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Revoking Role Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, removing permission...");
         match request.permissions {
@@ -565,7 +1584,7 @@ impl UserRouter {
                     &role_name, &permissions_string
                 );
                 let authorize_request = Role {
-                    name: role_name,
+                    name: role_name.clone(),
                     permissions,
                 };
 
@@ -575,6 +1594,8 @@ impl UserRouter {
                     .into_inner();
 
                 info!("Role revoked!");
+                UserRouter::role_permissions_changed(&token_service, &channels_service, &role_name)
+                    .await;
 
                 Ok(Json(StatusMessageResponse {
                     status: status.message,
@@ -589,12 +1610,11 @@ impl UserRouter {
     pub async fn authorize_user(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(channels_service): State<StateChannelsService>,
         Path(user_id): Path<String>,
         Json(request): Json<AuthorizeRevokeUserRoleRequest>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Authorize User Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, adding role...");
         match request.roles {
@@ -615,6 +1635,8 @@ impl UserRouter {
                     .into_inner();
 
                 info!("User authorized!");
+                let version = token_service.bump_user_permissions(user_id_int as i64);
+                notify_permissions_changed(&channels_service, user_id_int as i64, version).await;
                 let current_roles = user
                     .roles
                     .into_iter()
@@ -635,12 +1657,11 @@ impl UserRouter {
     pub async fn revoke_user(
         State(mut user_service): State<StateUserService>,
         State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(channels_service): State<StateChannelsService>,
         Path(user_id): Path<String>,
         Json(request): Json<AuthorizeRevokeUserRoleRequest>,
     ) -> ServiceResult<Json<StatusMessageResponse>> {
         info!("Revoking User Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
 
         info!("Obtained authorization, removing role...");
         match request.roles {
@@ -661,6 +1682,8 @@ impl UserRouter {
                     .into_inner();
 
                 info!("Role revoked!");
+                let version = token_service.bump_user_permissions(user_id_int as i64);
+                notify_permissions_changed(&channels_service, user_id_int as i64, version).await;
                 let current_roles = user
                     .roles
                     .into_iter()
@@ -680,12 +1703,10 @@ impl UserRouter {
 
     pub async fn get_users(
         State(mut user_service): State<StateUserService>,
-        State(token_service): State<StateTokenService>,
-        authorization: TypedHeader<Authorization<Bearer>>,
+        State(user_directory): State<StateUserDirectory>,
         pagination: Query<Pagination>,
     ) -> ServiceResult<Json<UserListEndpointResponse>> {
         info!("Get Users Endpoint, obtaining authorization...");
-        token_service.decode_bearer_token(authorization.token())?;
         let pagination: Pagination = pagination.0;
         let offset = pagination.page.unwrap_or_default() * *PAGINATION_SIZE;
 
@@ -697,10 +1718,825 @@ impl UserRouter {
             })
             .await?
             .into_inner();
+        for user in user_list_response.users.iter() {
+            user_directory.record(&user.email, user.id);
+        }
 
         info!("Returning user list response!");
         Ok(Json(UserListEndpointResponse::from_list_response(
             user_list_response,
         )))
     }
+
+    async fn send_registration_email(
+        email_service: &mut StateEmailService,
+        templating_service: &mut StateTemplatingService,
+        token_service: &StateTokenService,
+        user_id: i64,
+        email: &str,
+        first_name: &str,
+        last_name: &str,
+    ) -> ServiceResult<String> {
+        let verify_token =
+            encode(&token_service.create_verify_registration_token(user_id)?).into_owned();
+
+        info!("Composing email for verification");
+        let compose_request: ComposeRequest = ComposeRequest {
+            name: "registration".to_string(),
+            input_values: vec![
+                InputValue {
+                    name: "name".to_string(),
+                    value: format!("{:#?} {:#?}", first_name, last_name),
+                },
+                InputValue {
+                    name: "verification_token".to_string(),
+                    value: verify_token.clone(),
+                },
+            ],
+        };
+
+        let email_template = templating_service
+            .compose(compose_request)
+            .await?
+            .into_inner();
+
+        info!(
+            "Sending verification email for token: {}",
+            verify_token.clone()
+        );
+        let send_email_request: SendEmailRequest = SendEmailRequest {
+            email: email.to_string(),
+            title: "Thank you for registering".to_string(),
+            body: email_template.result,
+        };
+
+        email_service.send_email(send_email_request).await?;
+
+        Ok(verify_token)
+    }
+
+    async fn resend_registration_email(
+        mut user_service: StateUserService,
+        mut email_service: StateEmailService,
+        mut templating_service: StateTemplatingService,
+        token_service: StateTokenService,
+        user_directory: StateUserDirectory,
+        email: String,
+    ) -> ServiceResult<()> {
+        let Some(user) =
+            UserRouter::find_user_by_email(&mut user_service, &user_directory, &email).await?
+        else {
+            info!("No account found for verification resend");
+            return Ok(());
+        };
+        if user.verified {
+            info!("Account is already verified, not resending");
+            return Ok(());
+        }
+
+        info!("Account found, resending verification email");
+        UserRouter::send_registration_email(
+            &mut email_service,
+            &mut templating_service,
+            &token_service,
+            user.id,
+            &user.email,
+            &user.first_name,
+            &user.last_name,
+        )
+        .await?;
+
+        info!("Verification email resent");
+        Ok(())
+    }
+
+    async fn send_password_reset_email(
+        mut user_service: StateUserService,
+        mut email_service: StateEmailService,
+        mut templating_service: StateTemplatingService,
+        token_service: StateTokenService,
+        user_directory: StateUserDirectory,
+        email: String,
+    ) -> ServiceResult<()> {
+        let Some(user) =
+            UserRouter::find_user_by_email(&mut user_service, &user_directory, &email).await?
+        else {
+            info!("No account found for password reset request");
+            return Ok(());
+        };
+
+        info!("Account found, creating password reset token");
+        let reset_token = encode(&token_service.create_password_reset_token(user.id)?).into_owned();
+
+        let compose_request: ComposeRequest = ComposeRequest {
+            name: "password_reset".to_string(),
+            input_values: vec![
+                InputValue {
+                    name: "name".to_string(),
+                    value: format!("{} {}", user.first_name, user.last_name),
+                },
+                InputValue {
+                    name: "reset_token".to_string(),
+                    value: reset_token,
+                },
+            ],
+        };
+
+        let email_template = templating_service
+            .compose(compose_request)
+            .await?
+            .into_inner();
+
+        let send_email_request: SendEmailRequest = SendEmailRequest {
+            email: user.email,
+            title: "Reset your password".to_string(),
+            body: email_template.result,
+        };
+
+        email_service.send_email(send_email_request).await?;
+
+        info!("Password reset email sent");
+        Ok(())
+    }
+
+    async fn send_magic_link_email(
+        mut user_service: StateUserService,
+        mut email_service: StateEmailService,
+        mut templating_service: StateTemplatingService,
+        token_service: StateTokenService,
+        user_directory: StateUserDirectory,
+        email: String,
+    ) -> ServiceResult<()> {
+        let Some(user) =
+            UserRouter::find_user_by_email(&mut user_service, &user_directory, &email).await?
+        else {
+            info!("No account found for magic link request");
+            return Ok(());
+        };
+
+        info!("Account found, creating magic link token");
+        let magic_link_token =
+            encode(&token_service.create_magic_link_token(user.id)?).into_owned();
+
+        let compose_request: ComposeRequest = ComposeRequest {
+            name: "magic_login".to_string(),
+            input_values: vec![
+                InputValue {
+                    name: "name".to_string(),
+                    value: format!("{} {}", user.first_name, user.last_name),
+                },
+                InputValue {
+                    name: "magic_link_token".to_string(),
+                    value: magic_link_token,
+                },
+            ],
+        };
+
+        let email_template = templating_service
+            .compose(compose_request)
+            .await?
+            .into_inner();
+
+        let send_email_request: SendEmailRequest = SendEmailRequest {
+            email: user.email,
+            title: "Your sign in link".to_string(),
+            body: email_template.result,
+        };
+
+        email_service.send_email(send_email_request).await?;
+
+        info!("Magic link email sent");
+        Ok(())
+    }
+
+    /// Asks for the second factor when the user enabled one, otherwise starts the session.
+    fn complete_login(
+        login_services: &StateLoginServices,
+        alert_services: StateAlertServices,
+        user: &UserResponse,
+        method: &str,
+        client: &SessionClient,
+        device_label: Option<String>,
+        use_cookie: bool,
+    ) -> ServiceResult<Response> {
+        if login_services.mfa_service.is_enabled(user.id) {
+            info!("Two-factor authentication enabled, creating mfa token...");
+            let mfa_token = login_services
+                .token_service
+                .create_mfa_pending_token(user.id)?;
+            return Ok(
+                Json(LoginEndpointResponse::MfaRequired(MfaRequiredResponse {
+                    mfa_required: true,
+                    mfa_token,
+                }))
+                .into_response(),
+            );
+        }
+
+        UserRouter::start_session(
+            login_services,
+            alert_services,
+            user,
+            method,
+            client,
+            device_label,
+            use_cookie,
+        )
+    }
+
+    /// Records the login, alerts the user in the background when it came from a new device and
+    /// returns the tokens of the new session.
+    fn start_session(
+        login_services: &StateLoginServices,
+        alert_services: StateAlertServices,
+        user: &UserResponse,
+        method: &str,
+        client: &SessionClient,
+        device_label: Option<String>,
+        use_cookie: bool,
+    ) -> ServiceResult<Response> {
+        let login_history = &login_services.login_history;
+        login_services
+            .login_throttle
+            .record_success(&user.email, client.ip_address.as_deref());
+        login_history.record(Some(user.id), &user.email, method, true, client);
+        let device_id = client
+            .device_id
+            .clone()
+            .unwrap_or_else(|| random_string(DEVICE_ID_LENGTH));
+        if login_history.remember_device(user.id, client, &device_id) {
+            info!("Login from a new device, sending alert in the background");
+            let user_id = user.id;
+            let email = user.email.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(err) =
+                    UserRouter::send_new_device_alert(alert_services, user_id, email, client).await
+                {
+                    warn!("Unable to send new device alert: {}", err);
+                }
+            });
+        }
+
+        info!("Login recorded, creating bearer token...");
+        let token_service = &login_services.token_service;
+        let tokens =
+            token_service.create_token(user.id, &user.email, &user.roles, device_label, client)?;
+
+        info!("Session started, returning response!");
+        let (mut headers, response) =
+            UserRouter::token_response(token_service, tokens, use_cookie)?;
+        headers.append(
+            SET_COOKIE,
+            token_service.refresh_cookies().device(&device_id)?,
+        );
+        Ok((headers, Json(LoginEndpointResponse::Tokens(response))).into_response())
+    }
+
+    /// Signs out the session of a refresh token that was already rotated and warns the user, the
+    /// token was either replayed or refreshed twice at the same time.
+    async fn refresh_token_reused(
+        alert_services: &mut StateAlertServices,
+        login_services: &StateLoginServices,
+        claims: &RefreshClaims,
+        client: &SessionClient,
+    ) -> ServiceError {
+        let user_id = claims.user_id;
+        warn!(
+            "Rotated refresh token of session {} replayed, revoking the session of user {}",
+            &claims.family_id, user_id
+        );
+        login_services
+            .token_service
+            .revoke_session(user_id, &claims.family_id);
+        login_services.login_history.record(
+            Some(user_id),
+            &claims.user_email,
+            "refresh",
+            false,
+            client,
+        );
+        if let Err(err) = notify_user(
+            &mut alert_services.notification_service,
+            &alert_services.channels_service,
+            user_id,
+            "Security alert",
+            "An old sign-in token was reused, so the session it belonged to was signed out. Please sign in again and change your password if this was not you.",
+        )
+        .await
+        {
+            warn!("Unable to notify user {} of refresh token reuse: {}", user_id, err);
+        }
+
+        ServiceError::Unauthorized
+    }
+
+    /// Counts a failed login and emails the user in the background when it locked the account.
+    fn record_failed_login(
+        login_services: &StateLoginServices,
+        alert_services: StateAlertServices,
+        email: &str,
+        client: &SessionClient,
+    ) {
+        let login_throttle = &login_services.login_throttle;
+        if !login_throttle.record_failure(email, client.ip_address.as_deref()) {
+            return;
+        }
+
+        warn!("Too many failed logins, account {} locked out", email);
+        let email = email.to_string();
+        let lockout_seconds = login_throttle.lockout_seconds();
+        let mut user_service = login_services.user_service.clone();
+        let user_directory = login_services.user_directory.clone();
+        tokio::spawn(async move {
+            // Only existing accounts are told, so the endpoint cannot mail arbitrary addresses.
+            let user =
+                match UserRouter::find_user_by_email(&mut user_service, &user_directory, &email)
+                    .await
+                {
+                    Ok(Some(user)) => user,
+                    Ok(None) => return,
+                    Err(err) => {
+                        warn!("Unable to look up locked out account: {}", err);
+                        return;
+                    }
+                };
+            if let Err(err) =
+                UserRouter::send_lockout_email(alert_services, user.email, lockout_seconds).await
+            {
+                warn!("Unable to send lockout email: {}", err);
+            }
+        });
+    }
+
+    async fn send_new_device_alert(
+        mut alert_services: StateAlertServices,
+        user_id: i64,
+        email: String,
+        client: SessionClient,
+    ) -> ServiceResult<()> {
+        let ip_address = client.ip_address.unwrap_or_else(|| "unknown".to_string());
+        let user_agent = client.user_agent.unwrap_or_else(|| "unknown".to_string());
+        let compose_request: ComposeRequest = ComposeRequest {
+            name: "new_device_login".to_string(),
+            input_values: vec![
+                InputValue {
+                    name: "email".to_string(),
+                    value: email.clone(),
+                },
+                InputValue {
+                    name: "ip_address".to_string(),
+                    value: ip_address.clone(),
+                },
+                InputValue {
+                    name: "user_agent".to_string(),
+                    value: user_agent.clone(),
+                },
+            ],
+        };
+
+        let email_template = alert_services
+            .templating_service
+            .compose(compose_request)
+            .await?
+            .into_inner();
+
+        let send_email_request: SendEmailRequest = SendEmailRequest {
+            email,
+            title: "New sign-in to your account".to_string(),
+            body: email_template.result,
+        };
+
+        alert_services
+            .email_service
+            .send_email(send_email_request)
+            .await?;
+
+        notify_user(
+            &mut alert_services.notification_service,
+            &alert_services.channels_service,
+            user_id,
+            "New sign-in",
+            &format!(
+                "Your account was signed in from a new device ({}) at {}. If this was not you, change your password and sign out of all sessions.",
+                user_agent, ip_address
+            ),
+        )
+        .await?;
+
+        info!("New device alert sent");
+        Ok(())
+    }
+
+    async fn send_lockout_email(
+        mut alert_services: StateAlertServices,
+        email: String,
+        lockout_seconds: i64,
+    ) -> ServiceResult<()> {
+        let compose_request: ComposeRequest = ComposeRequest {
+            name: "account_locked".to_string(),
+            input_values: vec![
+                InputValue {
+                    name: "email".to_string(),
+                    value: email.clone(),
+                },
+                InputValue {
+                    name: "lockout_minutes".to_string(),
+                    value: (lockout_seconds / 60).max(1).to_string(),
+                },
+            ],
+        };
+
+        let email_template = alert_services
+            .templating_service
+            .compose(compose_request)
+            .await?
+            .into_inner();
+
+        let send_email_request: SendEmailRequest = SendEmailRequest {
+            email,
+            title: "Your account has been temporarily locked".to_string(),
+            body: email_template.result,
+        };
+
+        alert_services
+            .email_service
+            .send_email(send_email_request)
+            .await?;
+
+        info!("Lockout email sent");
+        Ok(())
+    }
+
+    async fn role_permissions_changed(
+        token_service: &StateTokenService,
+        channels_service: &StateChannelsService,
+        role_name: &str,
+    ) {
+        let affected_users = token_service.bump_role_permissions(role_name);
+        info!(
+            "Permissions of role {:?} changed, invalidating tokens of {} users",
+            role_name,
+            affected_users.len()
+        );
+        for (user_id, version) in affected_users {
+            notify_permissions_changed(channels_service, user_id, version).await;
+        }
+    }
+
+    async fn permission_deleted(
+        token_service: &StateTokenService,
+        channels_service: &StateChannelsService,
+        permission_name: &str,
+    ) {
+        let affected_users = token_service.bump_permission_holders(permission_name);
+        info!(
+            "Permission {:?} deleted, invalidating tokens of {} users",
+            permission_name,
+            affected_users.len()
+        );
+        for (user_id, version) in affected_users {
+            notify_permissions_changed(channels_service, user_id, version).await;
+        }
+    }
+
+    fn token_response(
+        token_service: &StateTokenService,
+        tokens: Tokens,
+        use_cookie: bool,
+    ) -> ServiceResult<(HeaderMap, ObtainTokenResponse)> {
+        match use_cookie {
+            true => {
+                let (headers, csrf_token) =
+                    token_service.refresh_cookies().issue(&tokens.refresh)?;
+                Ok((
+                    headers,
+                    ObtainTokenResponse::from_cookie_tokens(tokens, csrf_token),
+                ))
+            }
+            false => Ok((HeaderMap::new(), ObtainTokenResponse::from_tokens(tokens))),
+        }
+    }
+
+    /// Looks the account up through the user directory, since the user service cannot look
+    /// users up by email.
+    async fn find_user_by_email(
+        user_service: &mut StateUserService,
+        user_directory: &StateUserDirectory,
+        email: &str,
+    ) -> ServiceResult<Option<UserResponse>> {
+        let Some(user_id) = user_directory.find(email) else {
+            return Ok(None);
+        };
+
+        match user_service.get_user(GetUserRequest { id: user_id }).await {
+            Ok(response) => {
+                let user = response.into_inner();
+                if user.email.eq_ignore_ascii_case(email) {
+                    return Ok(Some(user));
+                }
+                user_directory.forget(email);
+                user_directory.record(&user.email, user.id);
+                Ok(None)
+            }
+            Err(status) if status.code() == Code::NotFound => {
+                user_directory.forget(email);
+                Ok(None)
+            }
+            Err(status) => Err(status.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::utilities::test_support::{bearer_for, request, role, service_register};
+
+    #[tokio::test]
+    async fn replayed_refresh_token_is_unauthorized_even_when_notifying_fails() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let roles = [role("tester", &[USER_READ])];
+        let first = token_service
+            .create_token(
+                1,
+                "user1@example.com",
+                &roles,
+                None,
+                &SessionClient::default(),
+            )
+            .unwrap();
+        let claims = token_service.decode_refresh_token(&first.refresh).unwrap();
+        let second = token_service
+            .rotate_token(&claims, &roles, &SessionClient::default())
+            .unwrap()
+            .unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let replay = format!("{{\"token\":\"{}\"}}", first.refresh);
+        let response = router
+            .clone()
+            .oneshot(request(Method::POST, "/refresh", None, Some(&replay)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let current = format!("{{\"token\":\"{}\"}}", second.refresh);
+        let response = router
+            .oneshot(request(Method::POST, "/refresh", None, Some(&current)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unknown_email_is_resolved_without_listing_users() {
+        let service_register = service_register().await;
+        let mut user_service = service_register.user_service.clone();
+
+        let user = UserRouter::find_user_by_email(
+            &mut user_service,
+            &service_register.user_directory,
+            "nobody@example.com",
+        )
+        .await
+        .unwrap();
+        assert!(user.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_password_reset_keeps_the_token_usable() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let token = token_service.create_password_reset_token(1).unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let body = format!(r#"{{"token":"{}","password":"NewPassword123"}}"#, token);
+        let response = router
+            .oneshot(request(Method::POST, "/password/reset", None, Some(&body)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            token_service.consume_password_reset_token(&token).unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn resend_verification_is_generic_and_throttled() {
+        let service_register = service_register().await;
+        let router = UserRouter::new_router(service_register);
+        let body = r#"{"email":"nobody@example.com"}"#;
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::POST, "/verify/resend", None, Some(body)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .oneshot(request(Method::POST, "/verify/resend", None, Some(body)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn failed_verification_keeps_the_token_usable() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let token = token_service.create_verify_registration_token(1).unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let uri = format!("/verify/{}", encode(&token));
+        let response = router
+            .oneshot(request(Method::GET, &uri, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            token_service
+                .consume_verify_registration_token(&token)
+                .unwrap(),
+            1
+        );
+        assert!(token_service
+            .consume_verify_registration_token(&token)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_magic_link_login_keeps_the_link_usable() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let token = token_service.create_magic_link_token(1).unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let uri = format!("/login/magic/{}?use_cookie=true", encode(&token));
+        let response = router
+            .oneshot(request(Method::POST, &uri, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(token_service.consume_magic_link_token(&token).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn used_magic_link_is_rejected() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let token = token_service.create_magic_link_token(1).unwrap();
+        token_service.consume_magic_link_token(&token).unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let uri = format!("/login/magic/{}", encode(&token));
+        for method in [Method::GET, Method::POST] {
+            let response = router
+                .clone()
+                .oneshot(request(method, &uri, None, None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn opening_a_magic_link_only_asks_to_confirm() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let token = token_service.create_magic_link_token(1).unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let uri = format!("/login/magic/{}", encode(&token));
+        let response = router
+            .oneshot(request(Method::GET, &uri, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(token_service.consume_magic_link_token(&token).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn burned_mfa_token_cannot_complete_the_login() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let mfa_token = token_service.create_mfa_pending_token(1).unwrap();
+        let claims = token_service.decode_mfa_pending_token(&mfa_token).unwrap();
+        while !token_service.record_mfa_failure(&claims) {}
+        let router = UserRouter::new_router(service_register);
+
+        let body = format!("{{\"mfa_token\":\"{}\",\"code\":\"123456\"}}", mfa_token);
+        let response = router
+            .oneshot(request(Method::POST, "/login/mfa", None, Some(&body)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn cookie_refresh_without_the_csrf_header_is_forbidden() {
+        let service_register = service_register().await;
+        let tokens = service_register
+            .token_service
+            .create_token(
+                1,
+                "user1@example.com",
+                &[role("tester", &[USER_READ])],
+                None,
+                &SessionClient::default(),
+            )
+            .unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        let mut refresh = request(Method::POST, "/refresh", None, Some("{}"));
+        refresh.headers_mut().insert(
+            "cookie",
+            format!("refresh_token={}; csrf_token=csrf", tokens.refresh)
+                .parse()
+                .unwrap(),
+        );
+        let response = router.oneshot(refresh).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn impersonation_tokens_cannot_change_how_the_account_signs_in() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let actor = token_service
+            .decode_bearer_token(&bearer_for(&service_register, 1, &[USER_READ]))
+            .unwrap();
+        let (bearer, _) = token_service
+            .create_impersonation_token(&actor, 2, "user2@example.com", &[])
+            .unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        for (uri, body) in [
+            ("/logout-all", None),
+            ("/mfa/totp/setup", None),
+            ("/mfa/totp/disable", Some("{\"code\":\"123456\"}")),
+            ("/webauthn/register/start", None),
+            ("/tokens", Some("{\"name\":\"ci\",\"permissions\":[]}")),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(request(Method::POST, uri, Some(&bearer), body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn client_tokens_cannot_use_account_endpoints() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let bearer = bearer_for(&service_register, 1, &[USER_READ]);
+        let claims = token_service.decode_bearer_token(&bearer).unwrap();
+        let (secret, record) = token_service
+            .register_client(&claims, "jobs", vec![USER_READ.to_string()])
+            .unwrap();
+        let (client_token, _) = token_service
+            .create_client_token(&record.client_id, &secret, None)
+            .unwrap();
+        let router = UserRouter::new_router(service_register);
+
+        for (method, uri) in [
+            (Method::POST, "/logout-all"),
+            (Method::GET, "/sessions"),
+            (Method::POST, "/mfa/totp/setup"),
+        ] {
+            let response = router
+                .clone()
+                .oneshot(request(method, uri, Some(&client_token), None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+        assert!(token_service.decode_bearer_token(&bearer).is_ok());
+    }
+
+    #[tokio::test]
+    async fn throttled_login_is_rejected_with_retry_after() {
+        let service_register = service_register().await;
+        service_register
+            .login_throttle
+            .record_failure("user@example.com", None);
+        let router = UserRouter::new_router(service_register);
+
+        let body = "{\"email\":\"user@example.com\",\"password\":\"password\"}";
+        let response = router
+            .oneshot(request(Method::POST, "/login", None, Some(body)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+    }
 }
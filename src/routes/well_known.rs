@@ -0,0 +1,25 @@
+use axum::{extract::State, routing::get, Json, Router};
+use jsonwebtoken::jwk::JwkSet;
+use madtofan_microservice_common::errors::ServiceResult;
+
+use crate::utilities::{
+    service_register::ServiceRegister, states::token_service::StateTokenService,
+};
+use tracing::info;
+
+pub struct WellKnownRouter;
+
+impl WellKnownRouter {
+    pub fn new_router(service_register: ServiceRegister) -> Router {
+        Router::new()
+            .route("/jwks.json", get(WellKnownRouter::jwks_endpoint))
+            .with_state(service_register)
+    }
+
+    pub async fn jwks_endpoint(
+        State(token_service): State<StateTokenService>,
+    ) -> ServiceResult<Json<JwkSet>> {
+        info!("JWKS Endpoint");
+        Ok(Json(token_service.jwks().clone()))
+    }
+}
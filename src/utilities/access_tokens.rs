@@ -0,0 +1,163 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use madtofan_microservice_common::errors::ServiceResult;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use super::{config::AppConfig, persistence::Persisted};
+
+pub const ACCESS_TOKEN_PREFIX: &str = "pat_";
+/// Uses within this many seconds of the recorded one do not update `last_used_at`, so a busy
+/// token does not rewrite the store on every request.
+const LAST_USED_RESOLUTION: usize = 60;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccessTokenRecord {
+    pub id: String,
+    pub user_id: i64,
+    pub email: String,
+    pub name: String,
+    pub permissions: Vec<String>,
+    /// Permission version of the user when the token was created.
+    pub pver: u64,
+    pub created_at: usize,
+    pub expires_at: usize,
+    pub last_used_at: Option<usize>,
+}
+
+/// Personal access tokens keyed by the sha256 of the token, the token itself is never stored.
+#[derive(Clone, Default)]
+pub struct AccessTokenStore(Persisted<HashMap<String, AccessTokenRecord>>);
+
+impl AccessTokenStore {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "access_tokens")?))
+    }
+
+    pub fn insert(&self, token: &str, record: AccessTokenRecord) {
+        let mut tokens = self.0.lock();
+        let now = now();
+        tokens.retain(|_, record| record.expires_at >= now);
+        tokens.insert(hash_token(token), record);
+    }
+
+    /// Returns the record of an unexpired token without marking it as used.
+    pub fn find(&self, token: &str) -> Option<AccessTokenRecord> {
+        let tokens = self.0.lock();
+        tokens
+            .get(&hash_token(token))
+            .filter(|record| record.expires_at >= now())
+            .cloned()
+    }
+
+    /// Returns the record of an unexpired token and marks it as used.
+    pub fn authenticate(&self, token: &str) -> Option<AccessTokenRecord> {
+        let mut tokens = self.0.lock();
+        let now = now();
+        let hash = hash_token(token);
+        let record = tokens
+            .get(&hash)
+            .filter(|record| record.expires_at >= now)?;
+        let recently_used = record
+            .last_used_at
+            .map(|last_used_at| last_used_at + LAST_USED_RESOLUTION > now)
+            .unwrap_or_default();
+        if recently_used {
+            return Some(record.clone());
+        }
+
+        let record = tokens.get_mut(&hash)?;
+        record.last_used_at = Some(now);
+        Some(record.clone())
+    }
+
+    pub fn list(&self, user_id: i64) -> Vec<AccessTokenRecord> {
+        let tokens = self.0.lock();
+        let mut records = tokens
+            .values()
+            .filter(|record| record.user_id == user_id)
+            .cloned()
+            .collect::<Vec<AccessTokenRecord>>();
+        records.sort_by_key(|record| record.created_at);
+        records
+    }
+
+    pub fn revoke(&self, user_id: i64, id: &str) -> Option<AccessTokenRecord> {
+        let mut tokens = self.0.lock();
+        let hash = tokens
+            .iter()
+            .find(|(_, record)| record.user_id == user_id && record.id == id)
+            .map(|(hash, _)| hash.clone())?;
+        tokens.remove(&hash)
+    }
+
+    /// Revokes every token of the user.
+    pub fn revoke_user(&self, user_id: i64) {
+        if self.list(user_id).is_empty() {
+            return;
+        }
+        let mut tokens = self.0.lock();
+        tokens.retain(|_, record| record.user_id != user_id);
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn now() -> usize {
+    OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(user_id: i64) -> AccessTokenRecord {
+        AccessTokenRecord {
+            id: format!("token-{}", user_id),
+            user_id,
+            email: format!("user{}@example.com", user_id),
+            name: "ci".to_string(),
+            permissions: vec![],
+            pver: 0,
+            created_at: now(),
+            expires_at: now() + 60,
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn tokens_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        AccessTokenStore::open(&config)
+            .unwrap()
+            .insert("pat_first", record(1));
+
+        let reopened = AccessTokenStore::open(&config).unwrap();
+        assert_eq!(reopened.authenticate("pat_first").unwrap().user_id, 1);
+        assert!(reopened.authenticate("pat_other").is_none());
+    }
+
+    #[test]
+    fn revoking_a_user_keeps_other_users_tokens() {
+        let store = AccessTokenStore::default();
+        store.insert("pat_first", record(1));
+        store.insert("pat_second", record(2));
+
+        store.revoke_user(1);
+        assert!(store.authenticate("pat_first").is_none());
+        assert!(store.authenticate("pat_second").is_some());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let store = AccessTokenStore::default();
+        let mut expired = record(1);
+        expired.expires_at = now() - 1;
+        store.insert("pat_first", expired);
+
+        assert!(store.authenticate("pat_first").is_none());
+    }
+}
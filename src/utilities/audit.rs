@@ -0,0 +1,103 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+
+use super::{config::AppConfig, persistence::JsonLog};
+
+const AUDIT_LOG_CAPACITY: usize = 10000;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub action: String,
+    pub actor_id: i64,
+    pub actor_email: String,
+    pub target_user_id: i64,
+    pub token_id: Option<String>,
+    pub occurred_at: usize,
+}
+
+/// Appends every audit event to `DATA_DIR/audit.jsonl` and mirrors it to the logs, keeping the
+/// most recent events in memory, newest last, for the audit endpoint.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    events: Arc<Mutex<VecDeque<AuditEvent>>>,
+    log: JsonLog,
+}
+
+impl AuditLog {
+    pub fn open(config: &AppConfig) -> Self {
+        let log = JsonLog::open(config, "audit");
+        let mut events = log.read::<AuditEvent>();
+        let skipped = events.len().saturating_sub(AUDIT_LOG_CAPACITY);
+        Self {
+            events: Arc::new(Mutex::new(events.drain(skipped..).collect())),
+            log,
+        }
+    }
+
+    pub fn record(
+        &self,
+        action: &str,
+        actor_id: i64,
+        actor_email: &str,
+        target_user_id: i64,
+        token_id: Option<String>,
+    ) {
+        info!(
+            "Audit: {} by user {} ({}) on user {}",
+            action, actor_id, actor_email, target_user_id
+        );
+        let event = AuditEvent {
+            action: action.to_string(),
+            actor_id,
+            actor_email: actor_email.to_string(),
+            target_user_id,
+            token_id,
+            occurred_at: OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize,
+        };
+        self.log.append(&event);
+
+        let mut events = self.events.lock().unwrap();
+        if events.len() == AUDIT_LOG_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Returns a page of events, newest first, with the total number of events.
+    pub fn list(&self, offset: usize, limit: usize) -> (Vec<AuditEvent>, usize) {
+        let events = self.events.lock().unwrap();
+        let page = events
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect::<Vec<AuditEvent>>();
+        (page, events.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let audit_log = AuditLog::open(&config);
+        audit_log.record("impersonation_started", 1, "admin@example.com", 2, None);
+        audit_log.record("impersonation_stopped", 1, "admin@example.com", 2, None);
+
+        let (events, total) = AuditLog::open(&config).list(0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(events[0].action, "impersonation_stopped");
+        assert_eq!(events[1].action, "impersonation_started");
+    }
+}
@@ -0,0 +1,263 @@
+use std::task::{Context, Poll};
+
+use axum::{
+    headers::{authorization::Bearer, Authorization, HeaderMapExt},
+    http::Request,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use tower::{Layer, Service};
+use tracing::info;
+
+use super::{
+    service_register::ServiceRegister, states::token_service::StateTokenService,
+    token::BearerClaims,
+};
+
+#[derive(Clone)]
+pub struct RequirePermissionLayer {
+    token_service: StateTokenService,
+    permission: &'static str,
+}
+
+impl RequirePermissionLayer {
+    pub fn new(service_register: &ServiceRegister, permission: &'static str) -> Self {
+        Self {
+            token_service: service_register.token_service.clone(),
+            permission,
+        }
+    }
+}
+
+impl<S> Layer<S> for RequirePermissionLayer {
+    type Service = RequirePermission<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequirePermission {
+            inner,
+            token_service: self.token_service.clone(),
+            permission: self.permission,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequirePermission<S> {
+    inner: S,
+    token_service: StateTokenService,
+    permission: &'static str,
+}
+
+impl<S> RequirePermission<S> {
+    fn authorize<B>(&self, request: &Request<B>) -> ServiceResult<BearerClaims> {
+        let authorization = request
+            .headers()
+            .typed_get::<Authorization<Bearer>>()
+            .ok_or(ServiceError::Unauthorized)?;
+        let bearer_claims = self
+            .token_service
+            .decode_authorized_token(authorization.token())?;
+
+        if !bearer_claims.grants(self.permission) {
+            info!(
+                "{} is missing the {:?} permission",
+                bearer_claims.sub, self.permission
+            );
+            return Err(ServiceError::Forbidden);
+        }
+
+        Ok(bearer_claims)
+    }
+}
+
+impl<S, B> Service<Request<B>> for RequirePermission<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let authorized = self.authorize(&request);
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            match authorized {
+                Ok(bearer_claims) => {
+                    request.extensions_mut().insert(bearer_claims);
+                    inner.call(request).await
+                }
+                Err(err) => Ok(err.into_response()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{Method, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use madtofan_microservice_common::user::Role;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        routes::templating::TemplatingRouter,
+        utilities::{
+            constants::{TEMPLATE_READ, USER_READ},
+            sessions::SessionClient,
+            test_support::{bearer_for, request, role, service_register},
+        },
+    };
+
+    fn protected(service_register: &ServiceRegister) -> Router {
+        Router::new().route(
+            "/",
+            get(|Extension(claims): Extension<BearerClaims>| async move { claims.sub })
+                .route_layer(RequirePermissionLayer::new(service_register, USER_READ)),
+        )
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_a_bearer_token() {
+        let service_register = service_register().await;
+        let response = protected(&service_register)
+            .oneshot(request(Method::GET, "/", None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_bearer_tokens() {
+        let service_register = service_register().await;
+        let response = protected(&service_register)
+            .oneshot(request(Method::GET, "/", Some("not-a-token"), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn forbids_tokens_missing_the_permission() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[TEMPLATE_READ]);
+        let response = protected(&service_register)
+            .oneshot(request(Method::GET, "/", Some(&bearer), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn passes_claims_to_the_handler_when_permitted() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[USER_READ]);
+        let response = protected(&service_register)
+            .oneshot(request(Method::GET, "/", Some(&bearer), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn guards_the_templating_routes() {
+        let service_register = service_register().await;
+        let bearer = bearer_for(&service_register, 1, &[USER_READ]);
+        let router = TemplatingRouter::new_router(service_register);
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/", None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = router
+            .oneshot(request(Method::GET, "/", Some(&bearer), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn removing_a_role_invalidates_bearer_and_personal_access_tokens() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let sign_in = |roles: &[Role]| {
+            token_service
+                .create_token(
+                    1,
+                    "user1@example.com",
+                    roles,
+                    None,
+                    &SessionClient::default(),
+                )
+                .unwrap()
+                .bearer
+        };
+        let bearer = sign_in(&[
+            role("reader", &[USER_READ]),
+            role("other", &[TEMPLATE_READ]),
+        ]);
+        let claims = token_service.decode_bearer_token(&bearer).unwrap();
+        let (access_token, _) = token_service
+            .create_personal_access_token(&claims, "ci", vec![USER_READ.to_string()], None)
+            .unwrap();
+        let status = |token: String| {
+            let router = protected(&service_register);
+            async move {
+                router
+                    .oneshot(request(Method::GET, "/", Some(&token), None))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(status(bearer.clone()).await, StatusCode::OK);
+        assert_eq!(status(access_token.clone()).await, StatusCode::OK);
+
+        token_service.bump_role_permissions("reader");
+        assert_eq!(status(bearer).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(access_token.clone()).await, StatusCode::UNAUTHORIZED);
+
+        let bearer = sign_in(&[role("other", &[TEMPLATE_READ])]);
+        assert_eq!(status(bearer).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(access_token).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_permission_invalidates_the_tokens_carrying_it() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let reader = bearer_for(&service_register, 1, &[USER_READ]);
+        let other = bearer_for(&service_register, 2, &[TEMPLATE_READ]);
+        let status = |token: String| {
+            let router = protected(&service_register);
+            async move {
+                router
+                    .oneshot(request(Method::GET, "/", Some(&token), None))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(status(reader.clone()).await, StatusCode::OK);
+
+        token_service.bump_permission_holders(USER_READ);
+        assert_eq!(status(reader).await, StatusCode::UNAUTHORIZED);
+        assert!(token_service.decode_bearer_token(&other).is_ok());
+    }
+}
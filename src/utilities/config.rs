@@ -1,11 +1,17 @@
-use clap::Parser;
+use clap::{ArgAction, Parser};
+
+use super::rate_limit::RateLimitPolicy;
 
 #[derive(Parser)]
 pub struct AppConfig {
     #[arg(long, env)]
     pub rust_log: String,
-    #[arg(long, env)]
-    pub bearer_secret: String,
+    #[arg(long, env, default_value = "RS256")]
+    pub jwt_algorithm: String,
+    #[arg(long, env, value_delimiter = ',')]
+    pub jwt_signing_keys: Vec<String>,
+    #[arg(long, env, value_delimiter = ',')]
+    pub jwt_verification_keys: Vec<String>,
     #[arg(long, env)]
     pub refresh_secret: String,
     #[arg(long, env)]
@@ -13,6 +19,94 @@ pub struct AppConfig {
     #[arg(long, env)]
     pub notification_sender_secret: String,
     #[arg(long, env)]
+    pub password_reset_secret: String,
+    #[arg(long, env)]
+    pub mfa_pending_secret: String,
+    #[arg(long, env)]
+    pub magic_link_secret: String,
+    #[arg(long, env)]
+    pub token_issuer: String,
+    #[arg(long, env)]
+    pub token_audience: String,
+    #[arg(long, env, default_value_t = 30)]
+    pub token_leeway_seconds: u64,
+    #[arg(long, env, default_value_t = 60)]
+    pub bearer_token_seconds: u64,
+    #[arg(long, env, default_value_t = 604800)]
+    pub refresh_token_seconds: u64,
+    #[arg(long, env, default_value_t = 86400)]
+    pub verify_registration_token_seconds: u64,
+    #[arg(long, env, default_value_t = 31622400)]
+    pub notification_sender_token_seconds: u64,
+    #[arg(long, env, default_value_t = 3600)]
+    pub password_reset_token_seconds: u64,
+    #[arg(long, env, default_value_t = 300)]
+    pub verification_resend_cooldown_seconds: i64,
+    #[arg(long, env, default_value_t = 300)]
+    pub mfa_pending_token_seconds: u64,
+    #[arg(long, env, default_value_t = 900)]
+    pub magic_link_token_seconds: u64,
+    #[arg(long, env, default_value = "madtofan")]
+    pub totp_issuer: String,
+    #[arg(long, env, default_value_t = 5)]
+    pub mfa_max_attempts: u32,
+    #[arg(long, env, value_delimiter = ',')]
+    pub oauth_providers: Vec<String>,
+    #[arg(long, env, default_value = "http://127.0.0.1/api/user/oauth")]
+    pub oauth_redirect_url: String,
+    #[arg(long, env, default_value = "openid email profile")]
+    pub oauth_scopes: String,
+    #[arg(long, env, default_value_t = 600)]
+    pub oauth_state_seconds: i64,
+    #[arg(long, env, default_value = "localhost")]
+    pub webauthn_rp_id: String,
+    #[arg(long, env, default_value = "madtofan")]
+    pub webauthn_rp_name: String,
+    #[arg(long, env, default_value = "http://localhost")]
+    pub webauthn_origin: String,
+    #[arg(long, env, default_value_t = 300)]
+    pub webauthn_challenge_seconds: i64,
+    #[arg(long, env, default_value_t = 7776000)]
+    pub personal_access_token_seconds: u64,
+    #[arg(long, env, default_value_t = true, action = ArgAction::Set)]
+    pub refresh_cookie_secure: bool,
+    #[arg(long, env, default_value = "Strict")]
+    pub refresh_cookie_same_site: String,
+    #[arg(long, env, default_value_t = 30)]
+    pub sse_ticket_seconds: u64,
+    #[arg(long, env, default_value_t = 900)]
+    pub impersonation_token_seconds: u64,
+    #[arg(long, env, default_value_t = 3600)]
+    pub client_token_seconds: u64,
+    #[arg(long, env, default_value_t = 5)]
+    pub login_max_failures: u32,
+    #[arg(long, env, default_value_t = 20)]
+    pub login_ip_max_failures: u32,
+    #[arg(long, env, default_value_t = 1)]
+    pub login_backoff_seconds: i64,
+    #[arg(long, env, default_value_t = 900)]
+    pub login_lockout_seconds: i64,
+    #[arg(long, env, default_value_t = 3600)]
+    pub login_failure_window_seconds: i64,
+    #[arg(long, env, default_value = "120/60")]
+    pub rate_limit_user: RateLimitPolicy,
+    #[arg(long, env, default_value = "60/60")]
+    pub rate_limit_templating: RateLimitPolicy,
+    #[arg(long, env, default_value = "120/60")]
+    pub rate_limit_notification: RateLimitPolicy,
+    #[arg(long, env, default_value = "30/60")]
+    pub rate_limit_auth: RateLimitPolicy,
+    #[arg(long, env, value_delimiter = ',')]
+    pub trusted_proxies: Vec<String>,
+    #[arg(long, env, value_delimiter = ',')]
+    pub service_clients: Vec<String>,
+    #[arg(long, env, value_delimiter = ',')]
+    pub notification_groups: Vec<String>,
+    #[arg(long, env, default_value = "data")]
+    pub data_dir: String,
+    #[arg(long, env, default_value_t = 3600)]
+    pub user_directory_sync_seconds: u64,
+    #[arg(long, env)]
     pub service_url: String,
     #[arg(long, env)]
     pub service_port: u32,
@@ -34,4 +128,53 @@ pub struct AppConfig {
     pub notification_host: String,
     #[arg(long, env)]
     pub notification_port: u32,
+    /// Temporary `data_dir` of a test configuration, removed when the configuration is dropped.
+    #[cfg(test)]
+    #[arg(skip)]
+    pub test_data_dir: Option<tempfile::TempDir>,
+}
+
+#[cfg(test)]
+impl AppConfig {
+    /// Configuration for unit tests, signing with the Ed25519 key under `tests/fixtures`.
+    pub fn for_tests() -> Self {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+        let data_dir = tempfile::Builder::new()
+            .prefix("api-endpoint-test-")
+            .tempdir()
+            .unwrap();
+        let mut config = AppConfig::parse_from([
+            "api-endpoint".to_string(),
+            "--rust-log=error".to_string(),
+            "--jwt-algorithm=EdDSA".to_string(),
+            format!("--jwt-signing-keys=test=0={}/jwt-ed25519.pem", fixtures),
+            format!(
+                "--jwt-verification-keys=test={}/jwt-ed25519.pub.pem",
+                fixtures
+            ),
+            "--refresh-secret=TestRefreshSecret".to_string(),
+            "--verify-registration-secret=TestVerifyRegistrationSecret".to_string(),
+            "--notification-sender-secret=TestNotificationSenderSecret".to_string(),
+            "--password-reset-secret=TestPasswordResetSecret".to_string(),
+            "--mfa-pending-secret=TestMfaPendingSecret".to_string(),
+            "--magic-link-secret=TestMagicLinkSecret".to_string(),
+            "--token-issuer=https://api.example.com".to_string(),
+            "--token-audience=example-api".to_string(),
+            "--service-clients=notification-service=TestServiceClientSecret".to_string(),
+            format!("--data-dir={}", data_dir.path().display()),
+            "--user-directory-sync-seconds=0".to_string(),
+            "--service-url=127.0.0.1".to_string(),
+            "--service-port=80".to_string(),
+            "--user-host=http://127.0.0.1".to_string(),
+            "--user-port=4001".to_string(),
+            "--email-host=http://127.0.0.1".to_string(),
+            "--email-port=4002".to_string(),
+            "--templating-host=http://127.0.0.1".to_string(),
+            "--templating-port=4003".to_string(),
+            "--notification-host=http://127.0.0.1".to_string(),
+            "--notification-port=4004".to_string(),
+        ]);
+        config.test_data_dir = Some(data_dir);
+        config
+    }
 }
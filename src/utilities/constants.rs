@@ -3,3 +3,21 @@ use lazy_static::lazy_static;
 lazy_static! {
     pub static ref PAGINATION_SIZE: i64 = 10;
 }
+
+pub const ROLE_READ: &str = "role:read";
+pub const ROLE_WRITE: &str = "role:write";
+pub const ROLE_DELETE: &str = "role:delete";
+pub const PERMISSION_READ: &str = "permission:read";
+pub const PERMISSION_WRITE: &str = "permission:write";
+pub const PERMISSION_DELETE: &str = "permission:delete";
+pub const USER_READ: &str = "user:read";
+pub const USER_WRITE: &str = "user:write";
+pub const IMPERSONATE: &str = "impersonate";
+pub const AUDIT_READ: &str = "audit:read";
+pub const CLIENT_READ: &str = "client:read";
+pub const CLIENT_WRITE: &str = "client:write";
+pub const TEMPLATE_READ: &str = "template:read";
+pub const TEMPLATE_WRITE: &str = "template:write";
+pub const TEMPLATE_DELETE: &str = "template:delete";
+pub const GROUP_READ: &str = "group:read";
+pub const GROUP_WRITE: &str = "group:write";
@@ -0,0 +1,59 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use madtofan_microservice_common::errors::ServiceResult;
+use time::OffsetDateTime;
+
+use super::{config::AppConfig, persistence::Persisted};
+
+#[derive(Clone, Default)]
+pub struct ConsumedTokens(Persisted<HashMap<String, usize>>);
+
+impl ConsumedTokens {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "consumed_tokens")?))
+    }
+
+    /// Records `jti` as used until `retained_until` and returns whether this was its first use.
+    pub fn consume(&self, jti: &str, retained_until: usize) -> bool {
+        let mut consumed = self.0.lock();
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
+        consumed.retain(|_, consumed_exp| *consumed_exp >= now);
+        consumed.insert(jti.to_string(), retained_until).is_none()
+    }
+
+    pub fn is_consumed(&self, jti: &str) -> bool {
+        let consumed = self.0.lock();
+        consumed.contains_key(jti)
+    }
+
+    /// Makes `jti` usable again, for when the action it was consumed for failed.
+    pub fn release(&self, jti: &str) {
+        let mut consumed = self.0.lock();
+        consumed.remove(jti);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_token_can_be_consumed_again() {
+        let consumed = ConsumedTokens::default();
+        let exp = OffsetDateTime::now_utc().unix_timestamp() as usize + 60;
+
+        assert!(consumed.consume("jti", exp));
+        assert!(!consumed.consume("jti", exp));
+        consumed.release("jti");
+        assert!(consumed.consume("jti", exp));
+    }
+
+    #[test]
+    fn consumed_tokens_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let exp = OffsetDateTime::now_utc().unix_timestamp() as usize + 60;
+        assert!(ConsumedTokens::open(&config).unwrap().consume("jti", exp));
+
+        assert!(!ConsumedTokens::open(&config).unwrap().consume("jti", exp));
+    }
+}
@@ -0,0 +1,213 @@
+use axum::{
+    headers::{Cookie, HeaderMapExt},
+    http::{header::SET_COOKIE, HeaderMap, HeaderValue},
+};
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+
+use super::{config::AppConfig, helpers::random_string};
+
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const DEVICE_COOKIE: &str = "device_id";
+pub const DEVICE_ID_LENGTH: usize = 32;
+const REFRESH_COOKIE_PATH: &str = "/api/user/refresh";
+const DEVICE_COOKIE_PATH: &str = "/api/user";
+/// Browsers cap cookie lifetimes at 400 days.
+const DEVICE_COOKIE_MAX_AGE: u64 = 400 * 24 * 60 * 60;
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// Carries the refresh token in an HttpOnly cookie, guarded by a double-submit CSRF token that
+/// the frontend reads from its own cookie and echoes back in the `x-csrf-token` header.
+pub struct RefreshCookies {
+    secure: bool,
+    same_site: String,
+    max_age: u64,
+}
+
+impl RefreshCookies {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            secure: config.refresh_cookie_secure,
+            same_site: config.refresh_cookie_same_site.clone(),
+            max_age: config.refresh_token_seconds,
+        }
+    }
+
+    /// Returns the `Set-Cookie` headers for `refresh_token` and the new CSRF token.
+    pub fn issue(&self, refresh_token: &str) -> ServiceResult<(HeaderMap, String)> {
+        let csrf_token = random_string(CSRF_TOKEN_LENGTH);
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE,
+            self.cookie(
+                REFRESH_COOKIE,
+                refresh_token,
+                REFRESH_COOKIE_PATH,
+                true,
+                self.max_age,
+            )?,
+        );
+        headers.append(
+            SET_COOKIE,
+            self.cookie(CSRF_COOKIE, &csrf_token, "/", false, self.max_age)?,
+        );
+
+        Ok((headers, csrf_token))
+    }
+
+    pub fn clear(&self) -> ServiceResult<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE,
+            self.cookie(REFRESH_COOKIE, "", REFRESH_COOKIE_PATH, true, 0)?,
+        );
+        headers.append(SET_COOKIE, self.cookie(CSRF_COOKIE, "", "/", false, 0)?);
+
+        Ok(headers)
+    }
+
+    /// Returns the `Set-Cookie` header that identifies the browser across logins, so new device
+    /// alerts do not depend on the user agent alone.
+    pub fn device(&self, device_id: &str) -> ServiceResult<HeaderValue> {
+        self.cookie(
+            DEVICE_COOKIE,
+            device_id,
+            DEVICE_COOKIE_PATH,
+            true,
+            DEVICE_COOKIE_MAX_AGE,
+        )
+    }
+
+    /// Reads the refresh token cookie once the CSRF header matches the CSRF cookie.
+    pub fn refresh_token(&self, headers: &HeaderMap) -> ServiceResult<String> {
+        let cookie = headers
+            .typed_get::<Cookie>()
+            .ok_or(ServiceError::Unauthorized)?;
+        let refresh_token = cookie
+            .get(REFRESH_COOKIE)
+            .filter(|refresh_token| !refresh_token.is_empty())
+            .ok_or(ServiceError::Unauthorized)?;
+
+        let csrf_header = headers
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok());
+        match (cookie.get(CSRF_COOKIE), csrf_header) {
+            (Some(csrf_cookie), Some(csrf_header))
+                if !csrf_cookie.is_empty() && csrf_cookie == csrf_header =>
+            {
+                Ok(refresh_token.to_string())
+            }
+            _ => Err(ServiceError::Forbidden),
+        }
+    }
+
+    fn cookie(
+        &self,
+        name: &str,
+        value: &str,
+        path: &str,
+        http_only: bool,
+        max_age: u64,
+    ) -> ServiceResult<HeaderValue> {
+        let mut cookie = format!(
+            "{}={}; Path={}; Max-Age={}; SameSite={}",
+            name, value, path, max_age, self.same_site
+        );
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        HeaderValue::from_str(&cookie)
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookies() -> RefreshCookies {
+        RefreshCookies::from_config(&AppConfig::for_tests())
+    }
+
+    fn set_cookies(headers: &HeaderMap) -> Vec<String> {
+        headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn request_headers(cookie: &str, csrf_header: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", HeaderValue::from_str(cookie).unwrap());
+        if let Some(csrf_header) = csrf_header {
+            headers.insert(CSRF_HEADER, HeaderValue::from_str(csrf_header).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn refresh_cookie_is_http_only_and_scoped_to_refresh() {
+        let (headers, csrf_token) = cookies().issue("refresh").unwrap();
+        let set_cookies = set_cookies(&headers);
+
+        let refresh_cookie = &set_cookies[0];
+        assert!(refresh_cookie.starts_with("refresh_token=refresh;"));
+        assert!(refresh_cookie.contains("Path=/api/user/refresh"));
+        assert!(refresh_cookie.contains("HttpOnly"));
+        assert!(refresh_cookie.contains("Secure"));
+
+        let csrf_cookie = &set_cookies[1];
+        assert!(csrf_cookie.starts_with(&format!("csrf_token={};", csrf_token)));
+        assert!(!csrf_cookie.contains("HttpOnly"));
+    }
+
+    #[test]
+    fn refresh_token_needs_the_matching_csrf_header() {
+        let cookies = cookies();
+        let cookie = "refresh_token=refresh; csrf_token=csrf";
+
+        assert_eq!(
+            cookies
+                .refresh_token(&request_headers(cookie, Some("csrf")))
+                .unwrap(),
+            "refresh"
+        );
+        assert!(matches!(
+            cookies.refresh_token(&request_headers(cookie, None)),
+            Err(ServiceError::Forbidden)
+        ));
+        assert!(matches!(
+            cookies.refresh_token(&request_headers(cookie, Some("other"))),
+            Err(ServiceError::Forbidden)
+        ));
+        assert!(matches!(
+            cookies.refresh_token(&request_headers("csrf_token=csrf", Some("csrf"))),
+            Err(ServiceError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn clearing_expires_both_cookies() {
+        let headers = cookies().clear().unwrap();
+        for cookie in set_cookies(&headers) {
+            assert!(cookie.contains("Max-Age=0"));
+        }
+    }
+
+    #[test]
+    fn device_cookie_is_long_lived_and_http_only() {
+        let cookie = cookies().device("device").unwrap();
+        let cookie = cookie.to_str().unwrap();
+
+        assert!(cookie.starts_with("device_id=device;"));
+        assert!(cookie.contains("Path=/api/user;"));
+        assert!(cookie.contains(&format!("Max-Age={}", DEVICE_COOKIE_MAX_AGE)));
+        assert!(cookie.contains("HttpOnly"));
+    }
+}
@@ -20,6 +20,18 @@ pub enum EventMessage {
     User(NotificationMessage),
     Channel(NotificationMessage),
     Broadcast(NotificationMessage),
+    #[serde(rename = "permissions_changed")]
+    PermissionsChanged(PermissionsChangedMessage),
+}
+
+impl EventMessage {
+    /// The SSE event name, `None` for plain notifications delivered as default messages.
+    pub fn event_name(&self) -> Option<&'static str> {
+        match self {
+            EventMessage::PermissionsChanged(_) => Some("permissions_changed"),
+            _ => None,
+        }
+    }
 }
 
 impl FromStr for ChannelTag {
@@ -69,6 +81,15 @@ pub struct NotificationMessage {
     pub message: String,
 }
 
+/// Tells the client its bearer token is stale and must be refreshed to pick up new permissions.
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export, export_to = "bindings/notification/")]
+pub struct PermissionsChangedMessage {
+    pub user_id: i64,
+    pub version: i64,
+    pub datetime: i64,
+}
+
 impl NotificationMessage {
     pub fn from_message_response(message_response: MessageResponse) -> Self {
         Self {
@@ -0,0 +1,10 @@
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Random alphanumeric string for tokens, secrets and one-time values.
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
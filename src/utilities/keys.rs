@@ -0,0 +1,270 @@
+use std::{collections::HashMap, fs, str::FromStr, time::SystemTime};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use rsa::{
+    pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey,
+};
+use time::OffsetDateTime;
+use tracing::info;
+
+use super::config::AppConfig;
+
+const ED25519_SPKI_LENGTH: usize = 44;
+
+struct SigningKey {
+    kid: String,
+    activates_at: i64,
+    key: EncodingKey,
+}
+
+pub struct KeyStore {
+    algorithm: Algorithm,
+    signing_keys: Vec<SigningKey>,
+    verification_keys: HashMap<String, DecodingKey>,
+    jwks: JwkSet,
+}
+
+impl KeyStore {
+    pub fn from_config(config: &AppConfig) -> ServiceResult<Self> {
+        let algorithm = Algorithm::from_str(&config.jwt_algorithm).map_err(|_| {
+            ServiceError::InternalServerErrorWithContext(format!(
+                "Unsupported JWT algorithm {}",
+                &config.jwt_algorithm
+            ))
+        })?;
+        if !matches!(algorithm, Algorithm::RS256 | Algorithm::EdDSA) {
+            return Err(ServiceError::InternalServerErrorWithContext(format!(
+                "JWT algorithm {:?} is not asymmetric, use RS256 or EdDSA",
+                algorithm
+            )));
+        }
+
+        let mut signing_keys = config
+            .jwt_signing_keys
+            .iter()
+            .map(|entry| {
+                let (kid, activates_at, path) = match entry.splitn(3, '=').collect::<Vec<_>>()[..] {
+                    [kid, activates_at, path] => Ok((kid, activates_at, path)),
+                    _ => Err(ServiceError::InternalServerErrorWithContext(format!(
+                        "Signing key {:?} must be formatted as kid=activates_at=path",
+                        entry
+                    ))),
+                }?;
+                let activates_at = activates_at.parse::<i64>().map_err(|_| {
+                    ServiceError::InternalServerErrorWithContext(format!(
+                        "Signing key {} has an invalid activation timestamp",
+                        kid
+                    ))
+                })?;
+                let pem = read_key_file(path)?;
+                let key = match algorithm {
+                    Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+                    _ => EncodingKey::from_rsa_pem(&pem),
+                }
+                .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+                Ok(SigningKey {
+                    kid: kid.to_string(),
+                    activates_at,
+                    key,
+                })
+            })
+            .collect::<ServiceResult<Vec<SigningKey>>>()?;
+        signing_keys.sort_by_key(|signing_key| signing_key.activates_at);
+
+        let mut verification_keys = HashMap::new();
+        let mut jwks = JwkSet { keys: Vec::new() };
+        for entry in config.jwt_verification_keys.iter() {
+            let (kid, path) = entry.split_once('=').ok_or_else(|| {
+                ServiceError::InternalServerErrorWithContext(format!(
+                    "Verification key {:?} must be formatted as kid=path",
+                    entry
+                ))
+            })?;
+            let pem = read_key_file(path)?;
+            let (key, parameters) = match algorithm {
+                Algorithm::EdDSA => (DecodingKey::from_ed_pem(&pem), ed_parameters(&pem)?),
+                _ => (DecodingKey::from_rsa_pem(&pem), rsa_parameters(&pem)?),
+            };
+            let key =
+                key.map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+            verification_keys.insert(kid.to_string(), key);
+            jwks.keys.push(Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    algorithm: Some(algorithm),
+                    key_id: Some(kid.to_string()),
+                    ..Default::default()
+                },
+                algorithm: parameters,
+            });
+        }
+
+        for signing_key in signing_keys.iter() {
+            if !verification_keys.contains_key(&signing_key.kid) {
+                return Err(ServiceError::InternalServerErrorWithContext(format!(
+                    "Signing key {} has no matching verification key",
+                    signing_key.kid
+                )));
+            }
+        }
+
+        info!(
+            "Loaded {} signing keys and {} verification keys",
+            signing_keys.len(),
+            verification_keys.len()
+        );
+        Ok(Self {
+            algorithm,
+            signing_keys,
+            verification_keys,
+            jwks,
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn signing_key(&self) -> ServiceResult<(&str, &EncodingKey)> {
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp();
+        self.signing_keys
+            .iter()
+            .rev()
+            .find(|signing_key| signing_key.activates_at <= now)
+            .map(|signing_key| (signing_key.kid.as_str(), &signing_key.key))
+            .ok_or_else(|| {
+                ServiceError::InternalServerErrorWithContext(
+                    "No active signing key available".to_string(),
+                )
+            })
+    }
+
+    pub fn verification_key(&self, kid: &str) -> ServiceResult<&DecodingKey> {
+        self.verification_keys
+            .get(kid)
+            .ok_or(ServiceError::Unauthorized)
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+fn read_key_file(path: &str) -> ServiceResult<Vec<u8>> {
+    fs::read(path).map_err(|err| {
+        ServiceError::InternalServerErrorWithContext(format!(
+            "Unable to read key file {}: {}",
+            path, err
+        ))
+    })
+}
+
+fn rsa_parameters(pem: &[u8]) -> ServiceResult<AlgorithmParameters> {
+    let pem = String::from_utf8_lossy(pem);
+    let public_key = RsaPublicKey::from_public_key_pem(&pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+    Ok(AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: RSAKeyType::RSA,
+        n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+        e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+    }))
+}
+
+fn ed_parameters(pem: &[u8]) -> ServiceResult<AlgorithmParameters> {
+    let der = pem::parse(pem)
+        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?
+        .contents;
+    if der.len() != ED25519_SPKI_LENGTH {
+        return Err(ServiceError::InternalServerErrorWithContext(
+            "Verification key is not an Ed25519 public key".to_string(),
+        ));
+    }
+
+    Ok(AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+        key_type: OctetKeyPairType::OctetKeyPair,
+        curve: EllipticCurve::Ed25519,
+        x: URL_SAFE_NO_PAD.encode(&der[ED25519_SPKI_LENGTH - 32..]),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{decode, encode, Header, Validation};
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn rejects_symmetric_algorithms() {
+        let mut config = AppConfig::for_tests();
+        config.jwt_algorithm = "HS256".to_string();
+        assert!(KeyStore::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn rejects_signing_keys_without_a_verification_key() {
+        let mut config = AppConfig::for_tests();
+        config.jwt_signing_keys = vec![format!("orphan=0={}", fixture("jwt-ed25519.pem"))];
+        assert!(KeyStore::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn signs_with_the_latest_active_key() {
+        let mut config = AppConfig::for_tests();
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp();
+        config.jwt_signing_keys = vec![
+            format!("old=0={}", fixture("jwt-ed25519.pem")),
+            format!("current={}={}", now - 60, fixture("jwt-ed25519.pem")),
+            format!("next={}={}", now + 3600, fixture("jwt-ed25519.pem")),
+        ];
+        config.jwt_verification_keys = ["old", "current", "next"]
+            .iter()
+            .map(|kid| format!("{}={}", kid, fixture("jwt-ed25519.pub.pem")))
+            .collect();
+
+        let keys = KeyStore::from_config(&config).unwrap();
+        let (kid, _) = keys.signing_key().unwrap();
+        assert_eq!(kid, "current");
+    }
+
+    #[test]
+    fn published_jwks_verifies_issued_tokens() {
+        let keys = KeyStore::from_config(&AppConfig::for_tests()).unwrap();
+        let (kid, encoding_key) = keys.signing_key().unwrap();
+        let mut header = Header::new(keys.algorithm());
+        header.kid = Some(kid.to_string());
+        let claims = Claims {
+            sub: "user@example.com".to_string(),
+            exp: OffsetDateTime::now_utc().unix_timestamp() as usize + 60,
+        };
+        let token = encode(&header, &claims, encoding_key).unwrap();
+
+        let jwk = keys.jwks().find(kid).unwrap();
+        let decoding_key = DecodingKey::from_jwk(jwk).unwrap();
+        let decoded =
+            decode::<Claims>(&token, &decoding_key, &Validation::new(Algorithm::EdDSA)).unwrap();
+        assert_eq!(decoded.claims.sub, "user@example.com");
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{self, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::SystemTime,
+};
+
+use lru::LruCache;
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use super::{config::AppConfig, sessions::SessionClient};
+
+const ATTEMPTS_PER_ACCOUNT: usize = 100;
+const DEVICES_PER_USER: usize = 50;
+/// Failed logins for emails without an account are only kept in memory, for at most this many
+/// emails with at most `ATTEMPTS_PER_EMAIL` attempts each.
+const UNRESOLVED_EMAIL_CAPACITY: usize = 10000;
+const ATTEMPTS_PER_EMAIL: usize = 20;
+/// A user's file is rewritten with only the retained entries once it holds this many lines.
+const COMPACTION_THRESHOLD: usize = 2 * (ATTEMPTS_PER_ACCOUNT + DEVICES_PER_USER);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginAttempt {
+    pub user_id: Option<i64>,
+    pub email: String,
+    pub method: String,
+    pub success: bool,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub occurred_at: usize,
+}
+
+/// One line of a user's history file, replayed in order when the history is opened.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum HistoryEntry {
+    Attempt(LoginAttempt),
+    Device(String),
+}
+
+#[derive(Default)]
+struct UserHistory {
+    /// Attempts of the user, newest last.
+    attempts: VecDeque<LoginAttempt>,
+    /// Devices the user signed in from, most recently seen last.
+    devices: VecDeque<String>,
+    /// Lines in the user's file, compacted once it reaches `COMPACTION_THRESHOLD`.
+    lines: usize,
+}
+
+impl UserHistory {
+    fn apply(&mut self, entry: HistoryEntry) {
+        match entry {
+            HistoryEntry::Attempt(attempt) => {
+                push_capped(&mut self.attempts, attempt, ATTEMPTS_PER_ACCOUNT)
+            }
+            HistoryEntry::Device(device) => {
+                self.devices.retain(|known| *known != device);
+                push_capped(&mut self.devices, device, DEVICES_PER_USER);
+            }
+        }
+        self.lines += 1;
+    }
+
+    fn entries(&self) -> Vec<HistoryEntry> {
+        let devices = self.devices.iter().cloned().map(HistoryEntry::Device);
+        let attempts = self.attempts.iter().cloned().map(HistoryEntry::Attempt);
+        devices.chain(attempts).collect()
+    }
+}
+
+enum WriteOp {
+    Append(PathBuf, Vec<HistoryEntry>),
+    Replace(PathBuf, Vec<HistoryEntry>),
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+struct LoginHistoryState {
+    users: HashMap<i64, UserHistory>,
+    /// Failed password logins by lowercase email, they do not know the user yet.
+    unresolved: LruCache<String, VecDeque<LoginAttempt>>,
+    directory: Option<PathBuf>,
+    writer: Option<mpsc::Sender<WriteOp>>,
+}
+
+impl Default for LoginHistoryState {
+    fn default() -> Self {
+        Self {
+            users: HashMap::new(),
+            unresolved: LruCache::new(NonZeroUsize::new(UNRESOLVED_EMAIL_CAPACITY).unwrap()),
+            directory: None,
+            writer: None,
+        }
+    }
+}
+
+impl LoginHistoryState {
+    /// Applies the entries in memory and queues them for the user's file. Queuing under the
+    /// lock keeps the file in the order the entries were applied.
+    fn apply(&mut self, user_id: i64, entries: Vec<HistoryEntry>) {
+        let history = self.users.entry(user_id).or_default();
+        for entry in entries.iter().cloned() {
+            history.apply(entry);
+        }
+
+        let (Some(directory), Some(writer)) = (&self.directory, &self.writer) else {
+            return;
+        };
+        let path = directory.join(format!("{}.jsonl", user_id));
+        let operation = match history.lines >= COMPACTION_THRESHOLD {
+            true => {
+                let retained = history.entries();
+                history.lines = retained.len();
+                WriteOp::Replace(path, retained)
+            }
+            false => WriteOp::Append(path, entries),
+        };
+        let _ = writer.send(operation);
+    }
+}
+
+/// Recent sign in attempts of every user and the devices each user signed in from, capped per
+/// user so a busy account cannot evict the history of the others. Each user's history is
+/// appended to `DATA_DIR/login_history/<user_id>.jsonl` by a background writer.
+#[derive(Clone, Default)]
+pub struct LoginHistory(Arc<Mutex<LoginHistoryState>>);
+
+impl LoginHistory {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        let directory = Path::new(&config.data_dir).join("login_history");
+        let mut users = HashMap::new();
+        load(&directory, &mut users).map_err(|err| {
+            ServiceError::InternalServerErrorWithContext(format!(
+                "Unable to load {}: {}",
+                directory.display(),
+                err
+            ))
+        })?;
+
+        Ok(Self(Arc::new(Mutex::new(LoginHistoryState {
+            users,
+            directory: Some(directory),
+            writer: Some(spawn_writer()),
+            ..LoginHistoryState::default()
+        }))))
+    }
+
+    /// Failed password logins do not know the user yet and are only kept in memory by email.
+    pub fn record(
+        &self,
+        user_id: Option<i64>,
+        email: &str,
+        method: &str,
+        success: bool,
+        client: &SessionClient,
+    ) {
+        let attempt = LoginAttempt {
+            user_id,
+            email: email.to_string(),
+            method: method.to_string(),
+            success,
+            ip_address: client.ip_address.clone(),
+            user_agent: client.user_agent.clone(),
+            occurred_at: OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize,
+        };
+
+        let mut state = self.0.lock().unwrap();
+        match user_id {
+            Some(user_id) => state.apply(user_id, vec![HistoryEntry::Attempt(attempt)]),
+            None => {
+                let attempts = state
+                    .unresolved
+                    .get_or_insert_mut(email.to_lowercase(), VecDeque::new);
+                push_capped(attempts, attempt, ATTEMPTS_PER_EMAIL);
+            }
+        }
+    }
+
+    /// Remembers the device of a successful login and returns whether the user had signed in
+    /// before, but never from this device. Devices are told apart by their `device_id` cookie,
+    /// clients that did not send one are matched on their user agent and address instead.
+    pub fn remember_device(&self, user_id: i64, client: &SessionClient, device_id: &str) -> bool {
+        let fingerprint = device_fingerprint(client);
+        let mut state = self.0.lock().unwrap();
+        let devices = state.users.get(&user_id).map(|history| &history.devices);
+        let first_login = devices.map(|devices| devices.is_empty()).unwrap_or(true);
+        let known = devices
+            .map(|devices| match &client.device_id {
+                Some(device_id) => devices.contains(device_id),
+                None => devices.contains(&fingerprint),
+            })
+            .unwrap_or_default();
+
+        state.apply(
+            user_id,
+            vec![
+                HistoryEntry::Device(fingerprint),
+                HistoryEntry::Device(device_id.to_string()),
+            ],
+        );
+        !known && !first_login
+    }
+
+    /// Returns a page of the user's attempts, newest first, with the total number of attempts.
+    pub fn list(
+        &self,
+        user_id: i64,
+        email: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<LoginAttempt>, usize) {
+        let state = self.0.lock().unwrap();
+        let mut attempts = state
+            .users
+            .get(&user_id)
+            .map(|history| &history.attempts)
+            .into_iter()
+            .chain(state.unresolved.peek(&email.to_lowercase()))
+            .flatten()
+            .collect::<Vec<&LoginAttempt>>();
+        attempts.sort_by_key(|attempt| std::cmp::Reverse(attempt.occurred_at));
+        let page = attempts
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|attempt| (*attempt).clone())
+            .collect::<Vec<LoginAttempt>>();
+        (page, attempts.len())
+    }
+
+    /// Waits until everything recorded so far was written.
+    #[cfg(test)]
+    fn flush(&self) {
+        let (sender, receiver) = mpsc::channel();
+        let state = self.0.lock().unwrap();
+        if let Some(writer) = &state.writer {
+            writer.send(WriteOp::Flush(sender)).unwrap();
+        }
+        drop(state);
+        let _ = receiver.recv();
+    }
+}
+
+/// Replays every user's file, creating the directory so the writer never has to. Writes that
+/// arrive after the directory was removed fail instead of recreating it.
+fn load(directory: &Path, users: &mut HashMap<i64, UserHistory>) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    for file in fs::read_dir(directory)? {
+        let path = file?.path();
+        let Some(user_id) = path
+            .file_stem()
+            .filter(|_| {
+                path.extension()
+                    .is_some_and(|extension| extension == "jsonl")
+            })
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<i64>().ok())
+        else {
+            continue;
+        };
+        let history = users.entry(user_id).or_default();
+        fs::read_to_string(&path)?
+            .lines()
+            .filter_map(|line| serde_json::from_str::<HistoryEntry>(line).ok())
+            .for_each(|entry| history.apply(entry));
+    }
+    Ok(())
+}
+
+fn spawn_writer() -> mpsc::Sender<WriteOp> {
+    let (sender, receiver) = mpsc::channel::<WriteOp>();
+    thread::spawn(move || {
+        for operation in receiver {
+            let (path, written) = match operation {
+                WriteOp::Append(path, entries) => {
+                    let written = fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .and_then(|mut file| write_entries(&mut file, &entries));
+                    (path, written)
+                }
+                WriteOp::Replace(path, entries) => {
+                    let temporary = path.with_extension("jsonl.tmp");
+                    let written = fs::File::create(&temporary)
+                        .and_then(|mut file| write_entries(&mut file, &entries))
+                        .and_then(|_| fs::rename(&temporary, &path));
+                    (path, written)
+                }
+                #[cfg(test)]
+                WriteOp::Flush(done) => {
+                    let _ = done.send(());
+                    continue;
+                }
+            };
+            if let Err(err) = written {
+                warn!("Unable to write {}: {}", path.display(), err);
+            }
+        }
+    });
+    sender
+}
+
+fn write_entries(file: &mut fs::File, entries: &[HistoryEntry]) -> io::Result<()> {
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&serde_json::to_string(entry)?);
+        lines.push('\n');
+    }
+    file.write_all(lines.as_bytes())
+}
+
+fn push_capped<T>(buffer: &mut VecDeque<T>, value: T, capacity: usize) {
+    if buffer.len() >= capacity {
+        buffer.pop_front();
+    }
+    buffer.push_back(value);
+}
+
+fn device_fingerprint(client: &SessionClient) -> String {
+    let user_agent = client.user_agent.as_deref().unwrap_or_default();
+    let ip_address = client.ip_address.as_deref().unwrap_or_default();
+    hex::encode(Sha256::digest(format!("{}|{}", user_agent, ip_address)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(user_agent: &str, ip_address: &str, device_id: Option<&str>) -> SessionClient {
+        SessionClient {
+            ip_address: Some(ip_address.to_string()),
+            user_agent: Some(user_agent.to_string()),
+            device_id: device_id.map(|device_id| device_id.to_string()),
+        }
+    }
+
+    fn history(config: &AppConfig) -> LoginHistory {
+        LoginHistory::open(config).unwrap()
+    }
+
+    fn lines(config: &AppConfig, user_id: i64) -> usize {
+        let path = Path::new(&config.data_dir).join(format!("login_history/{}.jsonl", user_id));
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn busy_accounts_do_not_evict_other_users() {
+        let config = AppConfig::for_tests();
+        let login_history = history(&config);
+        let client = SessionClient::default();
+        login_history.record(Some(2), "user2@example.com", "password", true, &client);
+        for _ in 0..ATTEMPTS_PER_ACCOUNT + 20 {
+            login_history.record(Some(1), "user1@example.com", "password", true, &client);
+        }
+
+        assert_eq!(
+            login_history.list(1, "user1@example.com", 0, 10).1,
+            ATTEMPTS_PER_ACCOUNT
+        );
+        assert_eq!(login_history.list(2, "user2@example.com", 0, 10).1, 1);
+        login_history.flush();
+    }
+
+    #[test]
+    fn failed_logins_are_listed_by_email() {
+        let config = AppConfig::for_tests();
+        let login_history = history(&config);
+        let client = SessionClient::default();
+        login_history.record(None, "User1@example.com", "password", false, &client);
+        login_history.record(Some(1), "user1@example.com", "password", true, &client);
+        login_history.record(None, "user2@example.com", "password", false, &client);
+
+        let (attempts, count) = login_history.list(1, "user1@example.com", 0, 10);
+        assert_eq!(count, 2);
+        assert!(attempts.iter().any(|attempt| !attempt.success));
+        login_history.flush();
+    }
+
+    #[test]
+    fn devices_are_told_apart_by_their_cookie() {
+        let config = AppConfig::for_tests();
+        let login_history = history(&config);
+        let browser = "Mozilla/5.0 Firefox/120.0";
+
+        assert!(!login_history.remember_device(1, &client(browser, "10.0.0.1", None), "first"));
+        assert!(!login_history.remember_device(
+            1,
+            &client("Mozilla/5.0 Firefox/121.0", "10.0.0.9", Some("first")),
+            "first"
+        ));
+        assert!(login_history.remember_device(
+            1,
+            &client(browser, "10.0.0.1", Some("second")),
+            "second"
+        ));
+        login_history.flush();
+    }
+
+    #[test]
+    fn clients_without_a_cookie_are_matched_on_agent_and_address() {
+        let config = AppConfig::for_tests();
+        let login_history = history(&config);
+        let browser = "Mozilla/5.0 Firefox/120.0";
+
+        assert!(!login_history.remember_device(1, &client(browser, "10.0.0.1", None), "first"));
+        assert!(!login_history.remember_device(1, &client(browser, "10.0.0.1", None), "second"));
+        assert!(login_history.remember_device(1, &client(browser, "10.0.0.2", None), "third"));
+        login_history.flush();
+    }
+
+    #[test]
+    fn history_survives_a_restart() {
+        let config = AppConfig::for_tests();
+        let login_history = LoginHistory::open(&config).unwrap();
+        let client = client("Mozilla/5.0 Firefox/120.0", "10.0.0.1", None);
+        login_history.record(Some(1), "user1@example.com", "password", true, &client);
+        login_history.record(None, "user2@example.com", "password", false, &client);
+        login_history.remember_device(1, &client, "first");
+        login_history.flush();
+
+        let reopened = LoginHistory::open(&config).unwrap();
+        assert_eq!(reopened.list(1, "user1@example.com", 0, 10).1, 1);
+        assert_eq!(reopened.list(2, "user2@example.com", 0, 10).1, 0);
+        assert!(!reopened.remember_device(
+            1,
+            &SessionClient {
+                device_id: Some("first".to_string()),
+                ..SessionClient::default()
+            },
+            "first"
+        ));
+        reopened.flush();
+    }
+
+    #[test]
+    fn user_files_are_compacted() {
+        let config = AppConfig::for_tests();
+        let login_history = history(&config);
+        let client = SessionClient::default();
+        for _ in 0..COMPACTION_THRESHOLD {
+            login_history.record(Some(1), "user1@example.com", "password", true, &client);
+        }
+        login_history.flush();
+        assert_eq!(lines(&config, 1), ATTEMPTS_PER_ACCOUNT);
+
+        let reopened = LoginHistory::open(&config).unwrap();
+        assert_eq!(
+            reopened.list(1, "user1@example.com", 0, 10).1,
+            ATTEMPTS_PER_ACCOUNT
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use time::OffsetDateTime;
+
+use super::config::AppConfig;
+
+#[derive(Default)]
+struct FailedAttempts {
+    failures: u32,
+    last_failure_at: i64,
+    blocked_until: i64,
+}
+
+#[derive(Default)]
+struct ThrottleEntries {
+    accounts: HashMap<String, FailedAttempts>,
+    addresses: HashMap<String, FailedAttempts>,
+}
+
+/// Tracks failed logins per account and per client address. Every failure blocks further
+/// attempts for an exponentially growing delay, and reaching the limit locks the key out.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    account_max_failures: u32,
+    address_max_failures: u32,
+    backoff_seconds: i64,
+    lockout_seconds: i64,
+    failure_window_seconds: i64,
+    entries: Arc<Mutex<ThrottleEntries>>,
+}
+
+impl LoginThrottle {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            account_max_failures: config.login_max_failures,
+            address_max_failures: config.login_ip_max_failures,
+            backoff_seconds: config.login_backoff_seconds,
+            lockout_seconds: config.login_lockout_seconds,
+            failure_window_seconds: config.login_failure_window_seconds,
+            entries: Arc::new(Mutex::new(ThrottleEntries::default())),
+        }
+    }
+
+    pub fn lockout_seconds(&self) -> i64 {
+        self.lockout_seconds
+    }
+
+    /// Returns the seconds to wait when either the account or the address is still blocked.
+    pub fn check(&self, email: &str, ip_address: Option<&str>) -> Result<(), i64> {
+        let entries = self.entries.lock().unwrap();
+        let now = now();
+        let account_blocked_until = entries
+            .accounts
+            .get(&email.to_lowercase())
+            .map(|attempts| attempts.blocked_until)
+            .unwrap_or_default();
+        let address_blocked_until = ip_address
+            .and_then(|ip_address| entries.addresses.get(ip_address))
+            .map(|attempts| attempts.blocked_until)
+            .unwrap_or_default();
+
+        match account_blocked_until.max(address_blocked_until) - now {
+            retry_after if retry_after > 0 => Err(retry_after),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a failed login and returns whether it just locked the account out.
+    pub fn record_failure(&self, email: &str, ip_address: Option<&str>) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let now = now();
+        let window = self.failure_window_seconds;
+        entries
+            .accounts
+            .retain(|_, attempts| attempts.last_failure_at + window > now);
+        entries
+            .addresses
+            .retain(|_, attempts| attempts.last_failure_at + window > now);
+
+        if let Some(ip_address) = ip_address {
+            let attempts = entries.addresses.entry(ip_address.to_string()).or_default();
+            self.fail(attempts, self.address_max_failures, now);
+        }
+        let attempts = entries.accounts.entry(email.to_lowercase()).or_default();
+        self.fail(attempts, self.account_max_failures, now)
+    }
+
+    pub fn record_success(&self, email: &str, ip_address: Option<&str>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.accounts.remove(&email.to_lowercase());
+        if let Some(ip_address) = ip_address {
+            entries.addresses.remove(ip_address);
+        }
+    }
+
+    fn fail(&self, attempts: &mut FailedAttempts, max_failures: u32, now: i64) -> bool {
+        attempts.failures += 1;
+        attempts.last_failure_at = now;
+        if attempts.failures >= max_failures {
+            attempts.failures = 0;
+            attempts.blocked_until = now + self.lockout_seconds;
+            return true;
+        }
+
+        let backoff = self
+            .backoff_seconds
+            .saturating_mul(1 << (attempts.failures - 1).min(30))
+            .min(self.lockout_seconds);
+        attempts.blocked_until = now + backoff;
+        false
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::from(SystemTime::now()).unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        let mut config = AppConfig::for_tests();
+        config.login_max_failures = 3;
+        config.login_ip_max_failures = 5;
+        config.login_backoff_seconds = 10;
+        config.login_lockout_seconds = 900;
+        LoginThrottle::from_config(&config)
+    }
+
+    #[test]
+    fn backoff_doubles_until_the_account_is_locked() {
+        let throttle = throttle();
+        assert!(throttle.check("user@example.com", None).is_ok());
+
+        assert!(!throttle.record_failure("user@example.com", None));
+        assert_eq!(throttle.check("user@example.com", None), Err(10));
+        assert!(!throttle.record_failure("user@example.com", None));
+        assert_eq!(throttle.check("USER@example.com", None), Err(20));
+        assert!(throttle.record_failure("user@example.com", None));
+        assert_eq!(throttle.check("user@example.com", None), Err(900));
+        assert!(throttle.check("other@example.com", None).is_ok());
+    }
+
+    #[test]
+    fn addresses_are_throttled_across_accounts() {
+        let throttle = throttle();
+        for account in 0..5 {
+            throttle.record_failure(&format!("user{}@example.com", account), Some("203.0.113.7"));
+        }
+
+        assert_eq!(
+            throttle.check("new@example.com", Some("203.0.113.7")),
+            Err(900)
+        );
+        assert!(throttle
+            .check("new@example.com", Some("198.51.100.1"))
+            .is_ok());
+    }
+
+    #[test]
+    fn success_clears_the_failures() {
+        let throttle = throttle();
+        throttle.record_failure("user@example.com", Some("203.0.113.7"));
+        throttle.record_success("user@example.com", Some("203.0.113.7"));
+
+        assert!(throttle
+            .check("user@example.com", Some("203.0.113.7"))
+            .is_ok());
+    }
+}
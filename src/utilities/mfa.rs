@@ -0,0 +1,255 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use super::{config::AppConfig, persistence::Persisted};
+
+const TOTP_DIGITS: usize = 6;
+/// Time steps either side of the current one whose codes are still accepted.
+const TOTP_SKEW: u64 = 1;
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+#[derive(Serialize, Deserialize)]
+struct TotpEnrollment {
+    secret: Vec<u8>,
+    email: String,
+    confirmed: bool,
+    recovery_codes: Vec<String>,
+    /// Time step of the last accepted code, codes of this or earlier steps are rejected.
+    #[serde(default)]
+    last_step: u64,
+}
+
+pub struct TotpSetup {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// TOTP enrollments, persisted by the gateway since the user service has no storage for them.
+#[derive(Clone)]
+pub struct MfaStore {
+    issuer: String,
+    enrollments: Persisted<HashMap<i64, TotpEnrollment>>,
+}
+
+impl MfaStore {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self {
+            issuer: config.totp_issuer.clone(),
+            enrollments: Persisted::open(config, "totp_enrollments")?,
+        })
+    }
+
+    pub fn is_enabled(&self, user_id: i64) -> bool {
+        let enrollments = self.enrollments.lock();
+        enrollments
+            .get(&user_id)
+            .map(|enrollment| enrollment.confirmed)
+            .unwrap_or_default()
+    }
+
+    pub fn begin_enrollment(&self, user_id: i64, email: &str) -> ServiceResult<TotpSetup> {
+        let mut enrollments = self.enrollments.lock();
+        if let Some(true) = enrollments.get(&user_id).map(|e| e.confirmed) {
+            return Err(ServiceError::BadRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        let secret = Secret::generate_secret();
+        let secret_bytes = secret
+            .to_bytes()
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(format!("{:?}", err)))?;
+        let totp = self.totp(secret_bytes.clone(), email)?;
+        enrollments.insert(
+            user_id,
+            TotpEnrollment {
+                secret: secret_bytes,
+                email: email.to_string(),
+                confirmed: false,
+                recovery_codes: Vec::new(),
+                last_step: 0,
+            },
+        );
+
+        Ok(TotpSetup {
+            secret: secret.to_encoded().to_string(),
+            otpauth_uri: totp.get_url(),
+        })
+    }
+
+    /// Enables the pending enrollment and returns its recovery codes, which are only shown once.
+    pub fn confirm_enrollment(&self, user_id: i64, code: &str) -> ServiceResult<Vec<String>> {
+        let mut enrollments = self.enrollments.lock();
+        let enrollment = enrollments
+            .get_mut(&user_id)
+            .filter(|enrollment| !enrollment.confirmed)
+            .ok_or_else(|| {
+                ServiceError::BadRequest("No pending two-factor enrollment".to_string())
+            })?;
+        if !self.check_code(enrollment, code)? {
+            return Err(ServiceError::BadRequest(
+                "Invalid authentication code".to_string(),
+            ));
+        }
+
+        let recovery_codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(RECOVERY_CODE_LENGTH)
+                    .map(char::from)
+                    .collect::<String>()
+                    .to_lowercase()
+            })
+            .collect::<Vec<String>>();
+        enrollment.recovery_codes = recovery_codes.iter().map(|c| hash_code(c)).collect();
+        enrollment.confirmed = true;
+
+        Ok(recovery_codes)
+    }
+
+    pub fn disable(&self, user_id: i64, code: &str) -> ServiceResult<()> {
+        self.verify(user_id, code)?;
+        self.enrollments.lock().remove(&user_id);
+        Ok(())
+    }
+
+    /// Accepts either a TOTP code newer than the last accepted one or an unused recovery code,
+    /// consuming the latter.
+    pub fn verify(&self, user_id: i64, code: &str) -> ServiceResult<()> {
+        let mut enrollments = self.enrollments.lock();
+        let enrollment = enrollments
+            .get_mut(&user_id)
+            .filter(|enrollment| enrollment.confirmed)
+            .ok_or_else(|| {
+                ServiceError::BadRequest("Two-factor authentication is not enabled".to_string())
+            })?;
+
+        if self.check_code(enrollment, code)? {
+            return Ok(());
+        }
+
+        let hashed_code = hash_code(&code.trim().to_lowercase());
+        match enrollment
+            .recovery_codes
+            .iter()
+            .position(|recovery_code| *recovery_code == hashed_code)
+        {
+            Some(index) => {
+                enrollment.recovery_codes.remove(index);
+                Ok(())
+            }
+            None => Err(ServiceError::Unauthorized),
+        }
+    }
+
+    /// Checks `code` against the steps around now and records the matching step, so a code is
+    /// only accepted once.
+    fn check_code(&self, enrollment: &mut TotpEnrollment, code: &str) -> ServiceResult<bool> {
+        let totp = self.totp(enrollment.secret.clone(), &enrollment.email)?;
+        let current_step =
+            OffsetDateTime::from(SystemTime::now()).unix_timestamp() as u64 / TOTP_STEP;
+        let matching_step = (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+            .filter(|step| *step > enrollment.last_step)
+            .find(|step| totp.check(code.trim(), step * TOTP_STEP));
+
+        match matching_step {
+            Some(step) => {
+                enrollment.last_step = step;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn totp(&self, secret: Vec<u8>, email: &str) -> ServiceResult<TOTP> {
+        TOTP::new(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            0,
+            TOTP_STEP,
+            secret,
+            Some(self.issuer.clone()),
+            email.to_string(),
+        )
+        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))
+    }
+}
+
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enrolled(config: &AppConfig) -> (MfaStore, TOTP) {
+        let mfa = MfaStore::open(config).unwrap();
+        let setup = mfa.begin_enrollment(1, "user@example.com").unwrap();
+        let secret = Secret::Encoded(setup.secret).to_bytes().unwrap();
+        let totp = mfa.totp(secret, "user@example.com").unwrap();
+        (mfa, totp)
+    }
+
+    fn code_at_step_offset(totp: &TOTP, offset: i64) -> String {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        totp.generate((now + offset * TOTP_STEP as i64) as u64)
+    }
+
+    #[test]
+    fn codes_cannot_be_replayed() {
+        let config = AppConfig::for_tests();
+        let (mfa, totp) = enrolled(&config);
+        mfa.confirm_enrollment(1, &code_at_step_offset(&totp, -1))
+            .unwrap();
+
+        let code = code_at_step_offset(&totp, 0);
+        assert!(mfa.verify(1, &code).is_ok());
+        assert!(mfa.verify(1, &code).is_err());
+        assert!(mfa.verify(1, &code_at_step_offset(&totp, -1)).is_err());
+        assert!(mfa.verify(1, &code_at_step_offset(&totp, 1)).is_ok());
+    }
+
+    #[test]
+    fn codes_outside_the_skew_are_rejected() {
+        let config = AppConfig::for_tests();
+        let (mfa, totp) = enrolled(&config);
+        mfa.confirm_enrollment(1, &code_at_step_offset(&totp, 0))
+            .unwrap();
+
+        assert!(mfa.verify(1, &code_at_step_offset(&totp, 3)).is_err());
+    }
+
+    #[test]
+    fn recovery_codes_work_once() {
+        let config = AppConfig::for_tests();
+        let (mfa, totp) = enrolled(&config);
+        let recovery_codes = mfa
+            .confirm_enrollment(1, &code_at_step_offset(&totp, 0))
+            .unwrap();
+
+        assert!(mfa.verify(1, &recovery_codes[0]).is_ok());
+        assert!(mfa.verify(1, &recovery_codes[0]).is_err());
+    }
+
+    #[test]
+    fn enrollments_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let (mfa, totp) = enrolled(&config);
+        mfa.confirm_enrollment(1, &code_at_step_offset(&totp, 0))
+            .unwrap();
+
+        let reopened = MfaStore::open(&config).unwrap();
+        assert!(reopened.is_enabled(1));
+        assert!(reopened.verify(1, &code_at_step_offset(&totp, 0)).is_err());
+    }
+}
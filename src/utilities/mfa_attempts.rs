@@ -0,0 +1,32 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use time::OffsetDateTime;
+
+struct FailedCodes {
+    failures: u32,
+    retained_until: usize,
+}
+
+/// Wrong authentication codes entered against each pending mfa token.
+#[derive(Clone, Default)]
+pub struct MfaAttempts(Arc<Mutex<HashMap<String, FailedCodes>>>);
+
+impl MfaAttempts {
+    /// Records a wrong code for `jti` and returns how many have been entered so far.
+    pub fn fail(&self, jti: &str, retained_until: usize) -> u32 {
+        let mut attempts = self.0.lock().unwrap();
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
+        attempts.retain(|_, failed| failed.retained_until >= now);
+
+        let failed = attempts.entry(jti.to_string()).or_insert(FailedCodes {
+            failures: 0,
+            retained_until,
+        });
+        failed.failures += 1;
+        failed.failures
+    }
+}
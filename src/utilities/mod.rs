@@ -1,6 +1,35 @@
+pub mod access_tokens;
+pub mod audit;
+pub mod authorization;
 pub mod config;
 pub mod constants;
+pub mod consumed_tokens;
+pub mod cookies;
 pub mod events;
+pub mod helpers;
+pub mod keys;
+pub mod login_history;
+pub mod login_throttle;
+pub mod mfa;
+pub mod mfa_attempts;
+pub mod notifications;
+pub mod oauth;
+pub mod oauth_clients;
+pub mod permission_versions;
+pub mod persistence;
+pub mod rate_limit;
+pub mod refresh_families;
+pub mod revocations;
+pub mod sender_tokens;
+pub mod service_clients;
 pub mod service_register;
+pub mod sessions;
+pub mod sse_tickets;
 pub mod states;
+#[cfg(test)]
+pub mod test_support;
 pub mod token;
+pub mod trusted_proxies;
+pub mod user_directory;
+pub mod verification;
+pub mod webauthn;
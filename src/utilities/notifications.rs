@@ -0,0 +1,52 @@
+use std::time::SystemTime;
+
+use madtofan_microservice_common::{errors::ServiceResult, notification::AddMessageRequest};
+use time::OffsetDateTime;
+
+use super::{
+    events::{ChannelTag, EventMessage, NotificationMessage, PermissionsChangedMessage},
+    states::{channels::StateChannelsService, notification_service::StateNotificationService},
+};
+
+pub async fn notify_user(
+    notification_service: &mut StateNotificationService,
+    channels_service: &StateChannelsService,
+    user_id: i64,
+    subject: &str,
+    message: &str,
+) -> ServiceResult<()> {
+    let tag = ChannelTag::UserId(user_id);
+    let notification = notification_service
+        .add_message(AddMessageRequest {
+            channel: tag.to_string(),
+            subject: subject.to_string(),
+            message: message.to_string(),
+        })
+        .await?
+        .into_inner();
+
+    let event_message = EventMessage::User(NotificationMessage {
+        id: notification.id,
+        channel: tag.to_string(),
+        subject: subject.to_string(),
+        message: message.to_string(),
+        datetime: notification.date,
+    });
+    channels_service.send_by_tag(&tag, event_message).await;
+
+    Ok(())
+}
+
+pub async fn notify_permissions_changed(
+    channels_service: &StateChannelsService,
+    user_id: i64,
+    version: u64,
+) {
+    let tag = ChannelTag::UserId(user_id);
+    let event_message = EventMessage::PermissionsChanged(PermissionsChangedMessage {
+        user_id,
+        version: version as i64,
+        datetime: OffsetDateTime::from(SystemTime::now()).unix_timestamp(),
+    });
+    channels_service.send_by_tag(&tag, event_message).await;
+}
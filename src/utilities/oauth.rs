@@ -0,0 +1,503 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use axum::{
+    headers::{Cookie, HeaderMapExt},
+    http::{header::SET_COOKIE, HeaderMap, HeaderValue},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use urlencoding::encode;
+
+use super::{config::AppConfig, helpers::random_string, persistence::Persisted};
+
+pub const STATE_COOKIE: &str = "oauth_state";
+const STATE_COOKIE_PATH: &str = "/api/user/oauth";
+const STATE_LENGTH: usize = 32;
+const CODE_VERIFIER_LENGTH: usize = 64;
+
+#[derive(Clone)]
+struct OAuthProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+}
+
+struct PendingAuthorization {
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+    expires_at: i64,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    given_name: Option<String>,
+    family_name: Option<String>,
+}
+
+/// Accounts linked to each provider, keyed by provider name and then by subject.
+#[derive(Default, Serialize, Deserialize)]
+struct OAuthLinks {
+    providers: HashMap<String, HashMap<String, i64>>,
+}
+
+pub struct OAuthIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct OAuthService {
+    providers: HashMap<String, OAuthProvider>,
+    redirect_url: String,
+    scopes: String,
+    state_seconds: i64,
+    secure_cookie: bool,
+    client: reqwest::Client,
+    pending: Arc<Mutex<HashMap<String, PendingAuthorization>>>,
+    links: Persisted<OAuthLinks>,
+}
+
+impl OAuthService {
+    pub fn from_config(config: &AppConfig) -> ServiceResult<Self> {
+        let mut providers = HashMap::new();
+        for entry in config.oauth_providers.iter() {
+            let parts = entry.splitn(4, '=').collect::<Vec<&str>>();
+            if parts.len() != 4 || parts.iter().any(|part| part.is_empty()) {
+                return Err(ServiceError::InternalServerErrorWithContext(format!(
+                    "Invalid oauth provider entry, expected name=issuer=client_id=client_secret: {}",
+                    entry
+                )));
+            }
+            providers.insert(
+                parts[0].to_string(),
+                OAuthProvider {
+                    issuer: parts[1].trim_end_matches('/').to_string(),
+                    client_id: parts[2].to_string(),
+                    client_secret: parts[3].to_string(),
+                },
+            );
+        }
+
+        Ok(Self {
+            providers,
+            redirect_url: config.oauth_redirect_url.trim_end_matches('/').to_string(),
+            scopes: config.oauth_scopes.clone(),
+            state_seconds: config.oauth_state_seconds,
+            secure_cookie: config.refresh_cookie_secure,
+            client: reqwest::Client::new(),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            links: Persisted::open(config, "oauth_links")?,
+        })
+    }
+
+    /// Starts an authorization code flow with PKCE and returns the provider URL to redirect to,
+    /// with the `Set-Cookie` header that binds the flow to the browser that started it.
+    pub async fn authorization_url(
+        &self,
+        provider_name: &str,
+    ) -> ServiceResult<(HeaderMap, String)> {
+        let provider = self.provider(provider_name)?;
+        let metadata = self.metadata(provider).await?;
+
+        let state = random_string(STATE_LENGTH);
+        let nonce = random_string(STATE_LENGTH);
+        let code_verifier = random_string(CODE_VERIFIER_LENGTH);
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let now = now();
+            pending.retain(|_, authorization| authorization.expires_at > now);
+            pending.insert(
+                state.clone(),
+                PendingAuthorization {
+                    provider: provider_name.to_string(),
+                    code_verifier,
+                    nonce: nonce.clone(),
+                    expires_at: now + self.state_seconds,
+                },
+            );
+        }
+
+        let separator = match metadata.authorization_endpoint.contains('?') {
+            true => '&',
+            false => '?',
+        };
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, self.state_cookie(&state, self.state_seconds)?);
+        let authorization_url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+            metadata.authorization_endpoint,
+            separator,
+            encode(&provider.client_id),
+            encode(&self.callback_url(provider_name)),
+            encode(&self.scopes),
+            state,
+            nonce,
+            code_challenge,
+        );
+
+        Ok((headers, authorization_url))
+    }
+
+    /// Exchanges the callback code for an id token and returns the verified identity it holds.
+    /// The `state` must match the cookie set when the flow was started in this browser.
+    pub async fn exchange_code(
+        &self,
+        provider_name: &str,
+        code: &str,
+        state: &str,
+        headers: &HeaderMap,
+    ) -> ServiceResult<OAuthIdentity> {
+        let browser_state = headers
+            .typed_get::<Cookie>()
+            .and_then(|cookie| cookie.get(STATE_COOKIE).map(str::to_string));
+        if browser_state.as_deref() != Some(state) {
+            return Err(ServiceError::BadRequest(
+                "Sign in was started from another browser".to_string(),
+            ));
+        }
+
+        let authorization = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|authorization| {
+                authorization.provider == provider_name && authorization.expires_at > now()
+            })
+            .ok_or_else(|| {
+                ServiceError::BadRequest("Sign in request is invalid or has expired".to_string())
+            })?;
+        let provider = self.provider(provider_name)?;
+        let metadata = self.metadata(provider).await?;
+
+        let callback_url = self.callback_url(provider_name);
+        let token_response = self
+            .client
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &callback_url),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+                ("code_verifier", &authorization.code_verifier),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?
+            .json::<TokenResponse>()
+            .await
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+        let claims = self
+            .decode_id_token(provider, &metadata, &token_response.id_token)
+            .await?;
+        if claims.nonce.as_deref() != Some(authorization.nonce.as_str()) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(OAuthIdentity {
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified.unwrap_or_default(),
+            first_name: claims.given_name,
+            last_name: claims.family_name,
+        })
+    }
+
+    pub fn linked_user(&self, provider_name: &str, subject: &str) -> Option<i64> {
+        let links = self.links.lock();
+        links.providers.get(provider_name)?.get(subject).copied()
+    }
+
+    pub fn link(&self, provider_name: &str, subject: &str, user_id: i64) {
+        let mut links = self.links.lock();
+        links
+            .providers
+            .entry(provider_name.to_string())
+            .or_default()
+            .insert(subject.to_string(), user_id);
+    }
+
+    /// Returns the `Set-Cookie` header that removes the state cookie once the flow is over.
+    pub fn clear_state_cookie(&self) -> ServiceResult<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, self.state_cookie("", 0)?);
+        Ok(headers)
+    }
+
+    /// The cookie is sent on the top-level redirect back from the provider, so it needs
+    /// `SameSite=Lax` whatever the refresh cookie uses.
+    fn state_cookie(&self, state: &str, max_age: i64) -> ServiceResult<HeaderValue> {
+        let mut cookie = format!(
+            "{}={}; Path={}; Max-Age={}; SameSite=Lax; HttpOnly",
+            STATE_COOKIE, state, STATE_COOKIE_PATH, max_age
+        );
+        if self.secure_cookie {
+            cookie.push_str("; Secure");
+        }
+
+        HeaderValue::from_str(&cookie)
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))
+    }
+
+    async fn decode_id_token(
+        &self,
+        provider: &OAuthProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> ServiceResult<IdTokenClaims> {
+        let header = decode_header(id_token).map_err(|_| ServiceError::Unauthorized)?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        let jwks = self
+            .client
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?
+            .json::<JwkSet>()
+            .await
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+        let jwk = match header.kid {
+            Some(kid) => jwks.find(&kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or(ServiceError::Unauthorized)?;
+        let decoding_key = DecodingKey::from_jwk(jwk).map_err(|_| ServiceError::Unauthorized)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        let decoded_token = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(|_| ServiceError::Unauthorized)?;
+
+        Ok(decoded_token.claims)
+    }
+
+    /// Fetches the discovery document, which must describe the configured issuer so a
+    /// compromised document cannot point id token validation at another issuer.
+    async fn metadata(&self, provider: &OAuthProvider) -> ServiceResult<ProviderMetadata> {
+        let metadata = self
+            .client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                provider.issuer
+            ))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            return Err(ServiceError::InternalServerErrorWithContext(format!(
+                "Provider metadata issuer {} does not match {}",
+                metadata.issuer, provider.issuer
+            )));
+        }
+
+        Ok(metadata)
+    }
+
+    fn provider(&self, provider_name: &str) -> ServiceResult<&OAuthProvider> {
+        self.providers
+            .get(provider_name)
+            .ok_or_else(|| ServiceError::NotFound(format!("Unknown provider {}", provider_name)))
+    }
+
+    fn callback_url(&self, provider_name: &str) -> String {
+        format!("{}/{}/callback", self.redirect_url, provider_name)
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::from(SystemTime::now()).unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use axum::{extract::State, routing::get, routing::post, Json, Router};
+    use jsonwebtoken::{encode, Header};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::utilities::keys::KeyStore;
+
+    #[derive(Clone)]
+    struct MockProvider {
+        issuer: String,
+        keys: Arc<KeyStore>,
+        nonce: Arc<Mutex<String>>,
+    }
+
+    async fn discovery(State(provider): State<MockProvider>) -> Json<Value> {
+        Json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn jwks(State(provider): State<MockProvider>) -> Json<JwkSet> {
+        Json(provider.keys.jwks().clone())
+    }
+
+    async fn token(State(provider): State<MockProvider>) -> Json<Value> {
+        let (kid, encoding_key) = provider.keys.signing_key().unwrap();
+        let mut header = Header::new(provider.keys.algorithm());
+        header.kid = Some(kid.to_string());
+        let claims = json!({
+            "iss": provider.issuer,
+            "aud": "api-endpoint",
+            "sub": "subject-1",
+            "exp": now() + 60,
+            "nonce": *provider.nonce.lock().unwrap(),
+            "email": "user@example.com",
+            "email_verified": true,
+        });
+        let id_token = encode(&header, &claims, encoding_key).unwrap();
+        Json(json!({ "id_token": id_token }))
+    }
+
+    /// Serves a provider on a local port whose discovery document claims `metadata_issuer`.
+    fn oauth_service(metadata_issuer: Option<&str>) -> (OAuthService, MockProvider) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let mut config = AppConfig::for_tests();
+        config.oauth_providers = vec![format!("mock={}=api-endpoint=ClientSecret", issuer)];
+        let provider = MockProvider {
+            issuer: metadata_issuer.map(str::to_string).unwrap_or(issuer),
+            keys: Arc::new(KeyStore::from_config(&config).unwrap()),
+            nonce: Arc::new(Mutex::new(String::new())),
+        };
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(provider.clone());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        (OAuthService::from_config(&config).unwrap(), provider)
+    }
+
+    fn query_value(url: &str, name: &str) -> String {
+        url.split(['?', '&'])
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+            .unwrap()
+            .to_string()
+    }
+
+    fn state_cookie(state: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "cookie",
+            HeaderValue::from_str(&format!("{}={}", STATE_COOKIE, state)).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn signs_in_with_the_provider_identity() {
+        let (oauth_service, provider) = oauth_service(None);
+        let (headers, url) = oauth_service.authorization_url("mock").await.unwrap();
+        let state = query_value(&url, "state");
+        *provider.nonce.lock().unwrap() = query_value(&url, "nonce");
+        let cookie = headers.get(SET_COOKIE).unwrap().to_str().unwrap();
+        assert!(cookie.starts_with(&format!("{}={};", STATE_COOKIE, state)));
+        assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Lax"));
+
+        let identity = oauth_service
+            .exchange_code("mock", "code", &state, &state_cookie(&state))
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.email.as_deref(), Some("user@example.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[tokio::test]
+    async fn callback_needs_the_state_cookie_of_the_browser() {
+        let (oauth_service, provider) = oauth_service(None);
+        let (_, url) = oauth_service.authorization_url("mock").await.unwrap();
+        let state = query_value(&url, "state");
+        *provider.nonce.lock().unwrap() = query_value(&url, "nonce");
+
+        assert!(oauth_service
+            .exchange_code("mock", "code", &state, &HeaderMap::new())
+            .await
+            .is_err());
+        assert!(oauth_service
+            .exchange_code("mock", "code", &state, &state_cookie("other"))
+            .await
+            .is_err());
+        assert!(oauth_service
+            .exchange_code("mock", "code", &state, &state_cookie(&state))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn metadata_for_another_issuer_is_rejected() {
+        let (oauth_service, _) = oauth_service(Some("https://attacker.example.com"));
+        assert!(oauth_service.authorization_url("mock").await.is_err());
+    }
+
+    #[test]
+    fn links_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        OAuthService::from_config(&config)
+            .unwrap()
+            .link("mock", "subject-1", 1);
+
+        let reopened = OAuthService::from_config(&config).unwrap();
+        assert_eq!(reopened.linked_user("mock", "subject-1"), Some(1));
+        assert_eq!(reopened.linked_user("other", "subject-1"), None);
+    }
+}
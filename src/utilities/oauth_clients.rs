@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use madtofan_microservice_common::errors::ServiceResult;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{config::AppConfig, persistence::Persisted};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OAuthClientRecord {
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_by: i64,
+    pub created_at: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegisteredClient {
+    secret_hash: String,
+    record: OAuthClientRecord,
+}
+
+/// Machine clients of the client credentials grant, only the sha256 of their secret is stored.
+#[derive(Clone, Default)]
+pub struct OAuthClientStore(Persisted<HashMap<String, RegisteredClient>>);
+
+impl OAuthClientStore {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "oauth_clients")?))
+    }
+
+    pub fn insert(&self, secret: &str, record: OAuthClientRecord) {
+        let mut clients = self.0.lock();
+        clients.insert(
+            record.client_id.clone(),
+            RegisteredClient {
+                secret_hash: hash_secret(secret),
+                record,
+            },
+        );
+    }
+
+    pub fn authenticate(&self, client_id: &str, secret: &str) -> Option<OAuthClientRecord> {
+        let clients = self.0.lock();
+        clients
+            .get(client_id)
+            .filter(|client| client.secret_hash == hash_secret(secret))
+            .map(|client| client.record.clone())
+    }
+
+    pub fn contains(&self, client_id: &str) -> bool {
+        self.0.lock().contains_key(client_id)
+    }
+
+    pub fn list(&self) -> Vec<OAuthClientRecord> {
+        let clients = self.0.lock();
+        let mut records = clients
+            .values()
+            .map(|client| client.record.clone())
+            .collect::<Vec<OAuthClientRecord>>();
+        records.sort_by_key(|record| record.created_at);
+        records
+    }
+
+    pub fn remove(&self, client_id: &str) -> Option<OAuthClientRecord> {
+        let mut clients = self.0.lock();
+        clients.remove(client_id).map(|client| client.record)
+    }
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+
+use madtofan_microservice_common::{errors::ServiceResult, user::Role};
+use serde::{Deserialize, Serialize};
+
+use super::{config::AppConfig, persistence::Persisted};
+
+#[derive(Default, Serialize, Deserialize)]
+struct PermissionState {
+    versions: HashMap<i64, u64>,
+    role_holders: HashMap<String, HashSet<i64>>,
+    /// Permissions of the user's latest token, with the version they were granted at.
+    permissions: HashMap<i64, (u64, HashSet<String>)>,
+}
+
+impl PermissionState {
+    fn bump(&mut self, user_id: i64) -> u64 {
+        let version = self.versions.entry(user_id).or_default();
+        *version += 1;
+        *version
+    }
+}
+
+/// Per-user counters embedded in bearer tokens, bumped whenever the permissions a user holds
+/// change so tokens issued before the change are rejected and have to be refreshed.
+#[derive(Clone, Default)]
+pub struct PermissionVersions(Persisted<PermissionState>);
+
+impl PermissionVersions {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "permission_versions")?))
+    }
+
+    pub fn current(&self, user_id: i64) -> u64 {
+        let state = self.0.lock();
+        state.versions.get(&user_id).copied().unwrap_or_default()
+    }
+
+    /// Remembers which roles the user's new token was built from and returns the version to embed.
+    pub fn track_roles(&self, user_id: i64, roles: &[Role]) -> u64 {
+        let mut state = self.0.lock();
+        state.role_holders.retain(|_, holders| {
+            holders.remove(&user_id);
+            !holders.is_empty()
+        });
+        for role in roles {
+            state
+                .role_holders
+                .entry(role.name.clone())
+                .or_default()
+                .insert(user_id);
+        }
+        let version = state.versions.get(&user_id).copied().unwrap_or_default();
+        let permissions = roles
+            .iter()
+            .flat_map(|role| role.permissions.iter().cloned())
+            .collect();
+        state.permissions.insert(user_id, (version, permissions));
+        version
+    }
+
+    /// Permissions the user holds at the current version, unknown once they changed until the
+    /// user obtains a new token.
+    pub fn current_permissions(&self, user_id: i64) -> Option<HashSet<String>> {
+        let state = self.0.lock();
+        let current = state.versions.get(&user_id).copied().unwrap_or_default();
+        state
+            .permissions
+            .get(&user_id)
+            .filter(|(version, _)| *version == current)
+            .map(|(_, permissions)| permissions.clone())
+    }
+
+    pub fn bump_user(&self, user_id: i64) -> u64 {
+        let mut state = self.0.lock();
+        state.bump(user_id)
+    }
+
+    /// Bumps every user holding a token built from `role_name` and returns their new versions.
+    pub fn bump_role(&self, role_name: &str) -> Vec<(i64, u64)> {
+        let mut state = self.0.lock();
+        let holders = state
+            .role_holders
+            .get(role_name)
+            .map(|holders| holders.iter().copied().collect::<Vec<i64>>())
+            .unwrap_or_default();
+        holders
+            .into_iter()
+            .map(|user_id| (user_id, state.bump(user_id)))
+            .collect()
+    }
+
+    /// Bumps every user whose latest token carries `permission` and returns their new versions.
+    pub fn bump_permission(&self, permission: &str) -> Vec<(i64, u64)> {
+        let mut state = self.0.lock();
+        let holders = state
+            .permissions
+            .iter()
+            .filter(|(_, (_, permissions))| permissions.contains(permission))
+            .map(|(user_id, _)| *user_id)
+            .collect::<Vec<i64>>();
+        holders
+            .into_iter()
+            .map(|user_id| (user_id, state.bump(user_id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::test_support::role;
+
+    #[test]
+    fn versions_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let permission_versions = PermissionVersions::open(&config).unwrap();
+        permission_versions.track_roles(1, &[role("reader", &["user:read"])]);
+        assert_eq!(permission_versions.bump_role("reader"), vec![(1, 1)]);
+
+        let reopened = PermissionVersions::open(&config).unwrap();
+        assert_eq!(reopened.current(1), 1);
+        assert_eq!(reopened.bump_role("reader"), vec![(1, 2)]);
+    }
+}
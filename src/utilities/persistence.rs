@@ -0,0 +1,237 @@
+use std::{
+    fs,
+    io::{self, Write},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::warn;
+
+use super::config::AppConfig;
+
+/// State kept in memory and written to `DATA_DIR/<name>.json` whenever it is changed, so it
+/// survives a restart. The default value is only kept in memory.
+pub struct Persisted<T> {
+    state: Arc<Mutex<T>>,
+    path: Option<Arc<PathBuf>>,
+}
+
+impl<T> Clone for Persisted<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+impl<T: Default> Default for Persisted<T> {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(T::default())),
+            path: None,
+        }
+    }
+}
+
+impl<T: Default + Serialize + DeserializeOwned> Persisted<T> {
+    /// Loads the state saved under `name`, starting empty when nothing was saved yet.
+    pub fn open(config: &AppConfig, name: &str) -> ServiceResult<Self> {
+        let path = Path::new(&config.data_dir).join(format!("{}.json", name));
+        let state = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(|err| {
+                ServiceError::InternalServerErrorWithContext(format!(
+                    "Unable to load {}: {}",
+                    path.display(),
+                    err
+                ))
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => T::default(),
+            Err(err) => {
+                return Err(ServiceError::InternalServerErrorWithContext(format!(
+                    "Unable to read {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+        };
+
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+            path: Some(Arc::new(path)),
+        })
+    }
+}
+
+impl<T: Serialize> Persisted<T> {
+    /// Locks the state, it is saved when the guard is dropped if it was borrowed mutably.
+    pub fn lock(&self) -> PersistedGuard<'_, T> {
+        PersistedGuard {
+            guard: self.state.lock().unwrap(),
+            path: self.path.as_deref(),
+            changed: false,
+        }
+    }
+}
+
+pub struct PersistedGuard<'a, T: Serialize> {
+    guard: MutexGuard<'a, T>,
+    path: Option<&'a PathBuf>,
+    changed: bool,
+}
+
+impl<T: Serialize> Deref for PersistedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T: Serialize> DerefMut for PersistedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
+        &mut self.guard
+    }
+}
+
+impl<T: Serialize> Drop for PersistedGuard<'_, T> {
+    fn drop(&mut self) {
+        let Some(path) = self.path.filter(|_| self.changed) else {
+            return;
+        };
+        if let Err(err) = save(path, &*self.guard) {
+            warn!("Unable to save {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn save<T: Serialize>(path: &Path, state: &T) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents = serde_json::to_vec(state)?;
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)
+}
+
+/// Append only log of JSON lines under `DATA_DIR/<name>.jsonl`, for records that are never
+/// changed once written.
+#[derive(Clone)]
+pub struct JsonLog {
+    path: Option<Arc<PathBuf>>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Default for JsonLog {
+    fn default() -> Self {
+        Self {
+            path: None,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+}
+
+impl JsonLog {
+    pub fn open(config: &AppConfig, name: &str) -> Self {
+        Self {
+            path: Some(Arc::new(
+                Path::new(&config.data_dir).join(format!("{}.jsonl", name)),
+            )),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn append<T: Serialize>(&self, record: &T) {
+        let Some(path) = self.path.as_deref() else {
+            return;
+        };
+        let _guard = self.lock.lock().unwrap();
+        let appended = serde_json::to_string(record)
+            .map_err(io::Error::from)
+            .and_then(|line| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                writeln!(file, "{}", line)
+            });
+        if let Err(err) = appended {
+            warn!("Unable to append to {}: {}", path.display(), err);
+        }
+    }
+
+    /// Reads every record back, skipping lines that cannot be parsed.
+    pub fn read<T: DeserializeOwned>(&self) -> Vec<T> {
+        let Some(path) = self.path.as_deref() else {
+            return Vec::new();
+        };
+        let _guard = self.lock.lock().unwrap();
+        fs::read_to_string(path)
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        id: i64,
+    }
+
+    #[test]
+    fn changes_survive_reopening() {
+        let config = AppConfig::for_tests();
+        let store = Persisted::<HashMap<String, i64>>::open(&config, "counts").unwrap();
+        store.lock().insert("first".to_string(), 1);
+
+        let reopened = Persisted::<HashMap<String, i64>>::open(&config, "counts").unwrap();
+        assert_eq!(reopened.lock().get("first"), Some(&1));
+    }
+
+    #[test]
+    fn reads_do_not_save() {
+        let config = AppConfig::for_tests();
+        let store = Persisted::<HashMap<String, i64>>::open(&config, "counts").unwrap();
+        assert!(store.lock().is_empty());
+        assert!(!Path::new(&config.data_dir).join("counts.json").exists());
+    }
+
+    #[test]
+    fn corrupt_state_is_an_error() {
+        let config = AppConfig::for_tests();
+        fs::create_dir_all(&config.data_dir).unwrap();
+        fs::write(Path::new(&config.data_dir).join("counts.json"), "{").unwrap();
+        assert!(Persisted::<HashMap<String, i64>>::open(&config, "counts").is_err());
+    }
+
+    #[test]
+    fn log_appends_records() {
+        let config = AppConfig::for_tests();
+        let log = JsonLog::open(&config, "entries");
+        log.append(&Entry { id: 1 });
+        log.append(&Entry { id: 2 });
+
+        let entries: Vec<Entry> = JsonLog::open(&config, "entries").read();
+        assert_eq!(entries, vec![Entry { id: 1 }, Entry { id: 2 }]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    headers::{authorization::Bearer, Authorization, HeaderMapExt},
+    http::{HeaderValue, Request},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use lru::LruCache;
+use tower::{Layer, Service};
+
+use crate::response::StatusMessageResponse;
+
+use super::{
+    service_register::ServiceRegister,
+    states::{token_service::StateTokenService, trusted_proxies::StateTrustedProxies},
+    token::BearerIdentity,
+};
+
+/// Buckets kept per route group, the least recently used one is dropped beyond this.
+const BUCKET_CAPACITY: usize = 10000;
+/// Verified tokens whose key is remembered, so their signature is not checked on every request.
+const KEY_CACHE_CAPACITY: usize = 10000;
+
+/// A token bucket holding `capacity` requests that refills completely every `period_seconds`,
+/// written as `capacity/period_seconds` in the configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitPolicy {
+    capacity: u32,
+    period_seconds: u32,
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid rate limit {}, expected capacity/period_seconds",
+                value
+            )
+        };
+        let (capacity, period_seconds) = value.split_once('/').ok_or_else(invalid)?;
+        let capacity = capacity.trim().parse::<u32>().map_err(|_| invalid())?;
+        let period_seconds = period_seconds
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| invalid())?;
+        if capacity == 0 || period_seconds == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            capacity,
+            period_seconds,
+        })
+    }
+}
+
+impl RateLimitPolicy {
+    fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period_seconds as f64
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+struct RateLimitDecision {
+    allowed: bool,
+    remaining: u32,
+    reset_seconds: u64,
+    retry_after_seconds: u64,
+}
+
+/// In-memory buckets for one route group, keyed by user, sender token or client address.
+#[derive(Clone)]
+struct RateLimiter {
+    policy: RateLimitPolicy,
+    buckets: Arc<Mutex<LruCache<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            policy,
+            buckets: Arc::new(Mutex::new(LruCache::new(non_zero(BUCKET_CAPACITY)))),
+        }
+    }
+
+    fn acquire(&self, key: String) -> RateLimitDecision {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        let capacity = self.policy.capacity as f64;
+        let refill_per_second = self.policy.refill_per_second();
+
+        let bucket = buckets.get_or_insert_mut(key, || Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated_at).as_secs_f64() * refill_per_second)
+            .min(capacity);
+        bucket.updated_at = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        RateLimitDecision {
+            allowed,
+            remaining: bucket.tokens.floor() as u32,
+            reset_seconds: ((capacity - bucket.tokens) / refill_per_second).ceil() as u64,
+            retry_after_seconds: ((1.0 - bucket.tokens).max(0.0) / refill_per_second).ceil() as u64,
+        }
+    }
+}
+
+fn non_zero(capacity: usize) -> NonZeroUsize {
+    NonZeroUsize::new(capacity).unwrap()
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    token_service: StateTokenService,
+    trusted_proxies: StateTrustedProxies,
+    limiter: RateLimiter,
+    keys: Arc<Mutex<LruCache<String, String>>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(service_register: &ServiceRegister, policy: RateLimitPolicy) -> Self {
+        Self {
+            token_service: service_register.token_service.clone(),
+            trusted_proxies: service_register.trusted_proxies.clone(),
+            limiter: RateLimiter::new(policy),
+            keys: Arc::new(Mutex::new(LruCache::new(non_zero(KEY_CACHE_CAPACITY)))),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            token_service: self.token_service.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            limiter: self.limiter.clone(),
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    token_service: StateTokenService,
+    trusted_proxies: StateTrustedProxies,
+    limiter: RateLimiter,
+    keys: Arc<Mutex<LruCache<String, String>>>,
+}
+
+impl<S> RateLimit<S> {
+    /// Limits authenticated users, clients and notification senders by identity, everyone else
+    /// by address.
+    fn key<B>(&self, request: &Request<B>) -> String {
+        if let Some(authorization) = request.headers().typed_get::<Authorization<Bearer>>() {
+            if let Some(key) = self.token_key(authorization.token()) {
+                return key;
+            }
+        }
+
+        match self
+            .trusted_proxies
+            .client_ip(request.headers(), request.extensions())
+        {
+            Some(ip_address) => format!("ip:{}", ip_address),
+            None => "ip:unknown".to_string(),
+        }
+    }
+
+    /// Only tokens that were verified are cached, so a forged token cannot take over the bucket
+    /// of someone else.
+    fn token_key(&self, token: &str) -> Option<String> {
+        if let Some(key) = self.keys.lock().unwrap().get(token) {
+            return Some(key.clone());
+        }
+
+        let key = match self.token_service.bearer_identity(token)? {
+            BearerIdentity::User(user_id) => format!("user:{}", user_id),
+            BearerIdentity::Client(client_id) => format!("client:{}", client_id),
+            BearerIdentity::Sender(jti) => format!("sender:{}", jti),
+        };
+        self.keys
+            .lock()
+            .unwrap()
+            .put(token.to_string(), key.clone());
+        Some(key)
+    }
+}
+
+impl<S, B> Service<Request<B>> for RateLimit<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let decision = self.limiter.acquire(self.key(&request));
+        let limit = self.limiter.policy.capacity;
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let mut response = match decision.allowed {
+                true => inner.call(request).await?,
+                false => StatusMessageResponse::too_many_requests(
+                    "Too many requests, please slow down".to_string(),
+                    decision.retry_after_seconds as i64,
+                ),
+            };
+
+            let headers = response.headers_mut();
+            headers.insert("ratelimit-limit", HeaderValue::from(limit));
+            headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+            headers.insert("ratelimit-reset", HeaderValue::from(decision.reset_seconds));
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{Method, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::utilities::test_support::{bearer_for, request, service_register};
+
+    fn limited_router(service_register: &ServiceRegister, policy: &str) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(RateLimitLayer::new(
+                service_register,
+                policy.parse().unwrap(),
+            ))
+    }
+
+    #[test]
+    fn policies_are_parsed_from_capacity_and_period() {
+        let policy = "30/60".parse::<RateLimitPolicy>().unwrap();
+        assert_eq!(policy.capacity, 30);
+        assert_eq!(policy.period_seconds, 60);
+        assert!("30".parse::<RateLimitPolicy>().is_err());
+        assert!("0/60".parse::<RateLimitPolicy>().is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_rejected_per_user() {
+        let service_register = service_register().await;
+        let first = bearer_for(&service_register, 1, &[]);
+        let second = bearer_for(&service_register, 2, &[]);
+        let router = limited_router(&service_register, "1/60");
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/", Some(&first), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::GET, "/", Some(&first), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+
+        let response = router
+            .oneshot(request(Method::GET, "/", Some(&second), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn least_recently_used_buckets_are_evicted() {
+        let limiter = RateLimiter::new("1/60".parse().unwrap());
+        assert!(limiter.acquire("first".to_string()).allowed);
+        assert!(!limiter.acquire("first".to_string()).allowed);
+
+        for key in 0..BUCKET_CAPACITY {
+            limiter.acquire(key.to_string());
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), BUCKET_CAPACITY);
+        assert!(limiter.acquire("first".to_string()).allowed);
+    }
+
+    #[tokio::test]
+    async fn sender_tokens_are_limited_per_token() {
+        let service_register = service_register().await;
+        let sender = service_register
+            .token_service
+            .create_notification_sender_token("group", "admin@example.com")
+            .unwrap();
+        let router = limited_router(&service_register, "1/60");
+
+        for status in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+            let response = router
+                .clone()
+                .oneshot(request(Method::GET, "/", Some(&sender), None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        let response = router
+            .oneshot(request(Method::GET, "/", None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn keying_a_personal_access_token_does_not_mark_it_used() {
+        let service_register = service_register().await;
+        let token_service = service_register.token_service.clone();
+        let claims = token_service
+            .decode_bearer_token(&bearer_for(&service_register, 1, &[]))
+            .unwrap();
+        let (token, _) = token_service
+            .create_personal_access_token(&claims, "ci", vec![], None)
+            .unwrap();
+
+        let response = limited_router(&service_register, "10/60")
+            .oneshot(request(Method::GET, "/", Some(&token), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let records = token_service.list_personal_access_tokens(1);
+        assert_eq!(records[0].last_used_at, None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use madtofan_microservice_common::errors::ServiceResult;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{config::AppConfig, persistence::Persisted};
+
+#[derive(Serialize, Deserialize)]
+struct RefreshFamily {
+    current_jti: String,
+    revoked: bool,
+    exp: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct RefreshFamilies(Persisted<HashMap<String, RefreshFamily>>);
+
+impl RefreshFamilies {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "refresh_families")?))
+    }
+
+    pub fn start(&self, family_id: &str, jti: &str, exp: usize) {
+        let mut families = self.0.lock();
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
+        families.retain(|_, family| family.exp >= now);
+        families.insert(
+            family_id.to_string(),
+            RefreshFamily {
+                current_jti: jti.to_string(),
+                revoked: false,
+                exp,
+            },
+        );
+    }
+
+    /// Replaces `previous_jti` with `jti` as the current token of the family in one step. When
+    /// `previous_jti` is no longer current it was already rotated, by a replay or a concurrent
+    /// refresh, so the family is revoked and `false` is returned.
+    pub fn rotate(&self, family_id: &str, previous_jti: &str, jti: &str, exp: usize) -> bool {
+        let mut families = self.0.lock();
+        match families.get_mut(family_id) {
+            Some(family) if !family.revoked && family.current_jti == previous_jti => {
+                family.current_jti = jti.to_string();
+                family.exp = exp;
+                true
+            }
+            Some(family) => {
+                family.revoked = true;
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn revoke(&self, family_id: &str) {
+        let mut families = self.0.lock();
+        if !families.contains_key(family_id) {
+            return;
+        }
+        if let Some(family) = families.get_mut(family_id) {
+            family.revoked = true;
+        }
+    }
+
+    pub fn is_revoked(&self, family_id: &str) -> bool {
+        let families = self.0.lock();
+        families
+            .get(family_id)
+            .map(|family| family.revoked)
+            .unwrap_or_default()
+    }
+
+    /// Revokes the family when `jti` has already been rotated out of it.
+    pub fn detect_reuse(&self, family_id: &str, jti: &str) -> bool {
+        let mut families = self.0.lock();
+        let reused = families
+            .get(family_id)
+            .map(|family| family.current_jti != jti)
+            .unwrap_or_default();
+        if reused {
+            if let Some(family) = families.get_mut(family_id) {
+                family.revoked = true;
+            }
+        }
+        reused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exp() -> usize {
+        OffsetDateTime::now_utc().unix_timestamp() as usize + 60
+    }
+
+    #[test]
+    fn current_token_is_not_reuse() {
+        let families = RefreshFamilies::default();
+        families.start("family", "first", exp());
+        assert!(!families.detect_reuse("family", "first"));
+        assert!(!families.is_revoked("family"));
+    }
+
+    #[test]
+    fn rotated_out_token_revokes_the_family() {
+        let families = RefreshFamilies::default();
+        families.start("family", "first", exp());
+        assert!(families.rotate("family", "first", "second", exp()));
+
+        assert!(families.detect_reuse("family", "first"));
+        assert!(families.is_revoked("family"));
+    }
+
+    #[test]
+    fn families_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let families = RefreshFamilies::open(&config).unwrap();
+        families.start("family", "first", exp());
+        assert!(families.rotate("family", "first", "second", exp()));
+
+        let reopened = RefreshFamilies::open(&config).unwrap();
+        assert!(reopened.detect_reuse("family", "first"));
+        assert!(reopened.is_revoked("family"));
+    }
+
+    #[test]
+    fn only_one_rotation_of_a_token_succeeds() {
+        let families = RefreshFamilies::default();
+        families.start("family", "first", exp());
+
+        assert!(families.rotate("family", "first", "second", exp()));
+        assert!(!families.rotate("family", "first", "third", exp()));
+        assert!(families.is_revoked("family"));
+        assert!(!families.rotate("family", "second", "fourth", exp()));
+    }
+}
@@ -0,0 +1,179 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use madtofan_microservice_common::errors::ServiceResult;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+
+use super::{config::AppConfig, persistence::Persisted};
+
+const REVOCATION_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug)]
+pub enum Revocation {
+    Token(String),
+    User(i64),
+}
+
+impl Revocation {
+    pub fn matches(&self, jti: &str, user_id: i64) -> bool {
+        match self {
+            Revocation::Token(revoked_jti) => revoked_jti == jti,
+            Revocation::User(revoked_user_id) => *revoked_user_id == user_id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserRevocation {
+    revoked_at: usize,
+    expires_at: usize,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RevokedEntries {
+    tokens: HashMap<String, usize>,
+    users: HashMap<i64, UserRevocation>,
+}
+
+impl RevokedEntries {
+    fn prune(&mut self) {
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
+        self.tokens.retain(|_, exp| *exp >= now);
+        self.users
+            .retain(|_, revocation| revocation.expires_at >= now);
+    }
+}
+
+#[derive(Clone)]
+pub struct RevocationList {
+    entries: Persisted<RevokedEntries>,
+    sender: broadcast::Sender<Revocation>,
+}
+
+impl Default for RevocationList {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(REVOCATION_CHANNEL_CAPACITY);
+        Self {
+            entries: Persisted::default(),
+            sender,
+        }
+    }
+}
+
+impl RevocationList {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self {
+            entries: Persisted::open(config, "revocations")?,
+            ..Self::default()
+        })
+    }
+
+    /// Keeps `jti` denied until `retained_until`, after which the token is rejected as expired
+    /// anyway.
+    pub fn revoke_token(&self, jti: &str, retained_until: usize) {
+        let mut entries = self.entries.lock();
+        entries.prune();
+        entries.tokens.insert(jti.to_string(), retained_until);
+        let _ = self.sender.send(Revocation::Token(jti.to_string()));
+    }
+
+    /// Denies every token of the user issued before `revoked_at`. Session bound tokens issued in
+    /// the same second stay valid so that signing in again right after a sign out works; callers
+    /// end the sessions being signed out to cover bearer tokens from that second.
+    pub fn revoke_user(&self, user_id: i64, revoked_at: usize, expires_at: usize) {
+        let mut entries = self.entries.lock();
+        entries.prune();
+        entries.users.insert(
+            user_id,
+            UserRevocation {
+                revoked_at,
+                expires_at,
+            },
+        );
+        let _ = self.sender.send(Revocation::User(user_id));
+    }
+
+    /// Tokens without a session, such as impersonation tokens, are also denied when issued in the
+    /// second of a user revocation since no session end covers them.
+    pub fn is_revoked(&self, jti: &str, user_id: i64, iat: usize, session_bound: bool) -> bool {
+        let entries = self.entries.lock();
+        entries.tokens.contains_key(jti)
+            || entries
+                .users
+                .get(&user_id)
+                .map(|revocation| {
+                    iat < revocation.revoked_at || (!session_bound && iat == revocation.revoked_at)
+                })
+                .unwrap_or_default()
+    }
+
+    /// Only checks `jti`, for tokens that outlive a user wide sign out such as personal access tokens.
+    pub fn is_token_revoked(&self, jti: &str) -> bool {
+        let entries = self.entries.lock();
+        entries.tokens.contains_key(jti)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Revocation> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> usize {
+        OffsetDateTime::now_utc().unix_timestamp() as usize
+    }
+
+    #[test]
+    fn revoked_token_is_denied_until_expiry() {
+        let revocations = RevocationList::default();
+        revocations.revoke_token("jti", now() + 60);
+        assert!(revocations.is_revoked("jti", 1, now(), true));
+        assert!(revocations.is_token_revoked("jti"));
+        assert!(!revocations.is_revoked("other", 1, now(), true));
+    }
+
+    #[test]
+    fn user_revocation_spares_tokens_issued_in_the_same_second() {
+        let revocations = RevocationList::default();
+        let revoked_at = now();
+        revocations.revoke_user(1, revoked_at, revoked_at + 60);
+
+        assert!(revocations.is_revoked("earlier", 1, revoked_at - 1, true));
+        assert!(!revocations.is_revoked("same-second", 1, revoked_at, true));
+        assert!(!revocations.is_revoked("other-user", 2, revoked_at - 1, true));
+    }
+
+    #[test]
+    fn revocations_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let revocations = RevocationList::open(&config).unwrap();
+        revocations.revoke_token("jti", now() + 60);
+        revocations.revoke_user(1, now(), now() + 60);
+
+        let reopened = RevocationList::open(&config).unwrap();
+        assert!(reopened.is_token_revoked("jti"));
+        assert!(reopened.is_revoked("earlier", 1, now() - 1, true));
+    }
+
+    #[test]
+    fn user_revocation_denies_sessionless_tokens_from_the_same_second() {
+        let revocations = RevocationList::default();
+        let revoked_at = now();
+        revocations.revoke_user(1, revoked_at, revoked_at + 60);
+
+        assert!(revocations.is_revoked("same-second", 1, revoked_at, false));
+        assert!(!revocations.is_revoked("later", 1, revoked_at + 1, false));
+    }
+
+    #[test]
+    fn revocations_are_broadcast() {
+        let revocations = RevocationList::default();
+        let mut receiver = revocations.subscribe();
+        revocations.revoke_user(7, now(), now() + 60);
+        assert!(receiver.try_recv().unwrap().matches("any", 7));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use madtofan_microservice_common::errors::ServiceResult;
+use serde::{Deserialize, Serialize};
+
+use super::{config::AppConfig, persistence::Persisted};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SenderTokenRecord {
+    pub jti: String,
+    pub admin_email: String,
+    pub issued_at: usize,
+    pub expires_at: usize,
+}
+
+/// Every group created through the gateway, with its active sender token unless it was revoked.
+#[derive(Default, Serialize, Deserialize)]
+struct SenderTokenEntries {
+    groups: HashMap<String, Option<SenderTokenRecord>>,
+}
+
+#[derive(Clone, Default)]
+pub struct SenderTokenRegistry(Persisted<SenderTokenEntries>);
+
+impl SenderTokenRegistry {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "sender_tokens")?))
+    }
+
+    /// Makes `record` the only accepted sender token of the group.
+    pub fn activate(&self, group: &str, record: SenderTokenRecord) {
+        let mut entries = self.0.lock();
+        entries.groups.insert(group.to_string(), Some(record));
+    }
+
+    /// Registers a group created before the registry existed, without an active token until it
+    /// is rotated.
+    pub fn adopt(&self, group: &str) {
+        if self.is_known(group) {
+            return;
+        }
+        let mut entries = self.0.lock();
+        entries.groups.entry(group.to_string()).or_insert(None);
+    }
+
+    pub fn revoke(&self, group: &str) -> Option<SenderTokenRecord> {
+        let mut entries = self.0.lock();
+        entries.groups.get_mut(group)?.take()
+    }
+
+    /// Forgets the group so its sender tokens are rejected and it can no longer be rotated.
+    pub fn remove(&self, group: &str) {
+        let mut entries = self.0.lock();
+        entries.groups.remove(group);
+    }
+
+    pub fn is_known(&self, group: &str) -> bool {
+        let entries = self.0.lock();
+        entries.groups.contains_key(group)
+    }
+
+    pub fn active(&self, group: &str) -> Option<SenderTokenRecord> {
+        let entries = self.0.lock();
+        entries.groups.get(group).cloned().flatten()
+    }
+
+    /// Only the active token of a known group is accepted.
+    pub fn is_accepted(&self, group: &str, jti: &str) -> bool {
+        self.active(group)
+            .map(|record| record.jti == jti)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(jti: &str) -> SenderTokenRecord {
+        SenderTokenRecord {
+            jti: jti.to_string(),
+            admin_email: "admin@example.com".to_string(),
+            issued_at: 0,
+            expires_at: usize::MAX,
+        }
+    }
+
+    #[test]
+    fn unknown_groups_accept_nothing() {
+        let registry = SenderTokenRegistry::default();
+        assert!(!registry.is_accepted("group", "jti"));
+        assert!(!registry.is_known("group"));
+    }
+
+    #[test]
+    fn rotation_rejects_the_previous_token() {
+        let registry = SenderTokenRegistry::default();
+        registry.activate("group", record("first"));
+        registry.activate("group", record("second"));
+
+        assert!(!registry.is_accepted("group", "first"));
+        assert!(registry.is_accepted("group", "second"));
+        assert!(!registry.is_accepted("other", "second"));
+    }
+
+    #[test]
+    fn revoked_group_stays_known_but_accepts_nothing() {
+        let registry = SenderTokenRegistry::default();
+        registry.activate("group", record("first"));
+
+        assert_eq!(registry.revoke("group").unwrap().jti, "first");
+        assert!(!registry.is_accepted("group", "first"));
+        assert!(registry.is_known("group"));
+
+        registry.remove("group");
+        assert!(!registry.is_known("group"));
+    }
+
+    #[test]
+    fn adopting_keeps_the_active_token() {
+        let registry = SenderTokenRegistry::default();
+        registry.adopt("existing");
+        assert!(registry.is_known("existing"));
+        assert!(registry.active("existing").is_none());
+
+        registry.activate("group", record("current"));
+        registry.adopt("group");
+        assert!(registry.is_accepted("group", "current"));
+    }
+
+    #[test]
+    fn active_tokens_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        SenderTokenRegistry::open(&config)
+            .unwrap()
+            .activate("group", record("first"));
+
+        let reopened = SenderTokenRegistry::open(&config).unwrap();
+        assert!(reopened.is_accepted("group", "first"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::{collections::HashMap, sync::Arc};
+
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use sha2::{Digest, Sha256};
+
+use super::config::AppConfig;
+
+/// Credentials of the internal services allowed to call the `/api/auth` endpoints.
+#[derive(Clone)]
+pub struct ServiceClients {
+    secrets: Arc<HashMap<String, String>>,
+}
+
+impl ServiceClients {
+    pub fn from_config(config: &AppConfig) -> ServiceResult<Self> {
+        let mut secrets = HashMap::new();
+        for entry in config.service_clients.iter() {
+            match entry.split_once('=') {
+                Some((client_id, secret)) if !client_id.is_empty() && !secret.is_empty() => {
+                    secrets.insert(client_id.to_string(), hash_secret(secret));
+                }
+                _ => {
+                    return Err(ServiceError::InternalServerErrorWithContext(format!(
+                        "Invalid service client entry, expected client_id=secret: {}",
+                        entry
+                    )))
+                }
+            }
+        }
+
+        Ok(Self {
+            secrets: Arc::new(secrets),
+        })
+    }
+
+    pub fn authenticate(&self, client_id: &str, secret: &str) -> bool {
+        self.secrets
+            .get(client_id)
+            .map(|hashed_secret| *hashed_secret == hash_secret(secret))
+            .unwrap_or_default()
+    }
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_configured_secret_authenticates() {
+        let service_clients = ServiceClients::from_config(&AppConfig::for_tests()).unwrap();
+
+        assert!(service_clients.authenticate("notification-service", "TestServiceClientSecret"));
+        assert!(!service_clients.authenticate("notification-service", "WrongSecret"));
+        assert!(!service_clients.authenticate("unknown-service", "TestServiceClientSecret"));
+    }
+}
@@ -8,14 +8,34 @@ use tagged_channels::TaggedChannels;
 use tonic::transport::Endpoint;
 use tracing::info;
 
+use super::audit::AuditLog;
 use super::config::AppConfig;
+use super::login_history::LoginHistory;
+use super::login_throttle::LoginThrottle;
+use super::mfa::MfaStore;
+use super::oauth::OAuthService;
+use super::service_clients::ServiceClients;
+use super::states::audit_service::StateAuditService;
 use super::states::channels::StateChannelsService;
 use super::states::email_service::StateEmailService;
+use super::states::login_history::StateLoginHistory;
+use super::states::login_throttle::StateLoginThrottle;
+use super::states::mfa_service::StateMfaService;
 use super::states::notification_service::StateNotificationService;
+use super::states::oauth_service::StateOAuthService;
+use super::states::service_clients::StateServiceClients;
 use super::states::templating_service::StateTemplatingService;
 use super::states::token_service::StateTokenService;
+use super::states::trusted_proxies::StateTrustedProxies;
+use super::states::user_directory::StateUserDirectory;
 use super::states::user_service::StateUserService;
+use super::states::verification_service::StateVerificationService;
+use super::states::webauthn_service::StateWebauthnService;
 use super::token::JwtService;
+use super::trusted_proxies::TrustedProxies;
+use super::user_directory::UserDirectory;
+use super::verification::VerificationTracker;
+use super::webauthn::WebauthnService;
 
 #[derive(Clone)]
 pub struct ServiceRegister {
@@ -25,6 +45,16 @@ pub struct ServiceRegister {
     pub notification_service: StateNotificationService,
     pub token_service: StateTokenService,
     pub channel_service: StateChannelsService,
+    pub verification_service: StateVerificationService,
+    pub mfa_service: StateMfaService,
+    pub oauth_service: StateOAuthService,
+    pub audit_service: StateAuditService,
+    pub login_throttle: StateLoginThrottle,
+    pub login_history: StateLoginHistory,
+    pub service_clients: StateServiceClients,
+    pub webauthn_service: StateWebauthnService,
+    pub user_directory: StateUserDirectory,
+    pub trusted_proxies: StateTrustedProxies,
 }
 
 impl ServiceRegister {
@@ -62,7 +92,18 @@ impl ServiceRegister {
             Box::leak(notification_service_address_string.into_boxed_str());
 
         info!("initializing utility services...");
-        let token_service = JwtService::new(config);
+        let verification_service =
+            VerificationTracker::new(config.verification_resend_cooldown_seconds);
+        let mfa_service = MfaStore::open(&config)?;
+        let oauth_service = OAuthService::from_config(&config)?;
+        let login_throttle = LoginThrottle::from_config(&config);
+        let service_clients = ServiceClients::from_config(&config)?;
+        let webauthn_service = WebauthnService::open(&config)?;
+        let user_directory = UserDirectory::open(&config)?;
+        let login_history = LoginHistory::open(&config)?;
+        let trusted_proxies = TrustedProxies::from_config(&config)?;
+        let audit_service = AuditLog::open(&config);
+        let token_service = JwtService::new(config)?;
         let channel_service = TaggedChannels::new();
 
         info!("utility services initialized, building feature services...");
@@ -84,6 +125,16 @@ impl ServiceRegister {
             notification_service: StateNotificationService::new(notification_service),
             token_service: StateTokenService::new(token_service),
             channel_service: StateChannelsService::new(channel_service),
+            verification_service: StateVerificationService::new(verification_service),
+            mfa_service: StateMfaService::new(mfa_service),
+            oauth_service: StateOAuthService::new(oauth_service),
+            audit_service: StateAuditService::new(audit_service),
+            login_throttle: StateLoginThrottle::new(login_throttle),
+            login_history: StateLoginHistory::new(login_history),
+            service_clients: StateServiceClients::new(service_clients),
+            webauthn_service: StateWebauthnService::new(webauthn_service),
+            user_directory: StateUserDirectory::new(user_directory),
+            trusted_proxies: StateTrustedProxies::new(trusted_proxies),
         })
     }
 }
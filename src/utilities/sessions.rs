@@ -0,0 +1,140 @@
+use std::{collections::HashMap, convert::Infallible, time::SystemTime};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    headers::{Cookie, HeaderMapExt},
+    http::{header::USER_AGENT, request::Parts},
+};
+use madtofan_microservice_common::errors::ServiceResult;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{
+    config::AppConfig,
+    cookies::{DEVICE_COOKIE, DEVICE_ID_LENGTH},
+    persistence::Persisted,
+    states::trusted_proxies::StateTrustedProxies,
+};
+
+/// Where a login or refresh request came from, recorded on the session it creates or renews.
+#[derive(Clone, Debug, Default)]
+pub struct SessionClient {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// The `device_id` cookie of a browser that signed in before.
+    pub device_id: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionClient
+where
+    S: Send + Sync,
+    StateTrustedProxies: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ip_address =
+            StateTrustedProxies::from_ref(state).client_ip(&parts.headers, &parts.extensions);
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let device_id = parts
+            .headers
+            .typed_get::<Cookie>()
+            .and_then(|cookie| cookie.get(DEVICE_COOKIE).map(|value| value.to_string()))
+            .filter(|value| {
+                value.len() == DEVICE_ID_LENGTH && value.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+
+        Ok(Self {
+            ip_address,
+            user_agent,
+            device_id,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub id: String,
+    pub user_id: i64,
+    pub device_label: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: usize,
+    pub last_used_at: usize,
+    pub expires_at: usize,
+}
+
+/// Sessions keyed by the refresh token family they were started with.
+#[derive(Clone, Default)]
+pub struct SessionStore(Persisted<HashMap<String, SessionRecord>>);
+
+impl SessionStore {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "sessions")?))
+    }
+
+    pub fn start(&self, record: SessionRecord) {
+        let mut sessions = self.0.lock();
+        let now = now();
+        sessions.retain(|_, session| session.expires_at >= now);
+        sessions.insert(record.id.clone(), record);
+    }
+
+    pub fn touch(&self, id: &str, client: &SessionClient, expires_at: usize) {
+        let mut sessions = self.0.lock();
+        if let Some(session) = sessions.get_mut(id) {
+            session.ip_address = client.ip_address.clone().or(session.ip_address.take());
+            session.user_agent = client.user_agent.clone().or(session.user_agent.take());
+            session.last_used_at = now();
+            session.expires_at = expires_at;
+        }
+    }
+
+    pub fn is_active(&self, id: &str) -> bool {
+        let sessions = self.0.lock();
+        sessions
+            .get(id)
+            .map(|session| session.expires_at >= now())
+            .unwrap_or_default()
+    }
+
+    pub fn list(&self, user_id: i64) -> Vec<SessionRecord> {
+        let sessions = self.0.lock();
+        let now = now();
+        let mut records = sessions
+            .values()
+            .filter(|session| session.user_id == user_id && session.expires_at >= now)
+            .cloned()
+            .collect::<Vec<SessionRecord>>();
+        records.sort_by_key(|session| session.created_at);
+        records
+    }
+
+    pub fn remove(&self, user_id: i64, id: &str) -> Option<SessionRecord> {
+        let mut sessions = self.0.lock();
+        match sessions.get(id) {
+            Some(session) if session.user_id == user_id => sessions.remove(id),
+            _ => None,
+        }
+    }
+
+    pub fn remove_user(&self, user_id: i64) -> Vec<SessionRecord> {
+        let mut sessions = self.0.lock();
+        let ids = sessions
+            .values()
+            .filter(|session| session.user_id == user_id)
+            .map(|session| session.id.clone())
+            .collect::<Vec<String>>();
+        ids.iter().filter_map(|id| sessions.remove(id)).collect()
+    }
+}
+
+fn now() -> usize {
+    OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use time::OffsetDateTime;
+
+use super::token::BearerClaims;
+
+struct SseTicket {
+    claims: BearerClaims,
+    expires_at: usize,
+}
+
+/// Single-use tickets that stand in for a bearer token when opening an event stream.
+#[derive(Clone, Default)]
+pub struct SseTicketStore(Arc<Mutex<HashMap<String, SseTicket>>>);
+
+impl SseTicketStore {
+    pub fn insert(&self, ticket: &str, claims: BearerClaims, expires_at: usize) {
+        let mut tickets = self.0.lock().unwrap();
+        let now = now();
+        tickets.retain(|_, ticket| ticket.expires_at >= now);
+        tickets.insert(ticket.to_string(), SseTicket { claims, expires_at });
+    }
+
+    pub fn consume(&self, ticket: &str) -> Option<BearerClaims> {
+        let mut tickets = self.0.lock().unwrap();
+        tickets
+            .remove(ticket)
+            .filter(|ticket| ticket.expires_at >= now())
+            .map(|ticket| ticket.claims)
+    }
+}
+
+fn now() -> usize {
+    OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::{
+        config::AppConfig, sessions::SessionClient, test_support::role, token::JwtService,
+    };
+
+    fn claims() -> BearerClaims {
+        let token_service = JwtService::new(Arc::new(AppConfig::for_tests())).unwrap();
+        let tokens = token_service
+            .create_token(
+                1,
+                "user@example.com",
+                &[role("tester", &[])],
+                None,
+                &SessionClient::default(),
+            )
+            .unwrap();
+        token_service.decode_bearer_token(&tokens.bearer).unwrap()
+    }
+
+    #[test]
+    fn tickets_can_only_be_used_once() {
+        let tickets = SseTicketStore::default();
+        tickets.insert("ticket", claims(), now() + 30);
+        assert_eq!(tickets.consume("ticket").unwrap().user_id, 1);
+        assert!(tickets.consume("ticket").is_none());
+    }
+
+    #[test]
+    fn expired_tickets_are_rejected() {
+        let tickets = SseTicketStore::default();
+        tickets.insert("ticket", claims(), now() - 1);
+        assert!(tickets.consume("ticket").is_none());
+    }
+}
@@ -0,0 +1,28 @@
+use axum::extract::FromRef;
+
+use crate::utilities::service_register::ServiceRegister;
+
+use super::{
+    channels::StateChannelsService, email_service::StateEmailService,
+    notification_service::StateNotificationService, templating_service::StateTemplatingService,
+};
+
+/// Services used to alert a user by email and through their notification channels.
+#[derive(Clone)]
+pub struct StateAlertServices {
+    pub email_service: StateEmailService,
+    pub templating_service: StateTemplatingService,
+    pub notification_service: StateNotificationService,
+    pub channels_service: StateChannelsService,
+}
+
+impl FromRef<ServiceRegister> for StateAlertServices {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        Self {
+            email_service: input.email_service.clone(),
+            templating_service: input.templating_service.clone(),
+            notification_service: input.notification_service.clone(),
+            channels_service: input.channel_service.clone(),
+        }
+    }
+}
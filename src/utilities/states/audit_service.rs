@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{audit::AuditLog, service_register::ServiceRegister};
+
+#[derive(Clone)]
+pub struct StateAuditService(pub AuditLog);
+
+impl FromRef<ServiceRegister> for StateAuditService {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.audit_service.clone()
+    }
+}
+
+impl StateAuditService {
+    pub fn new(audit_service: AuditLog) -> Self {
+        Self(audit_service)
+    }
+}
+
+impl Deref for StateAuditService {
+    type Target = AuditLog;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateAuditService {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
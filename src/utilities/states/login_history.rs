@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{login_history::LoginHistory, service_register::ServiceRegister};
+
+#[derive(Clone)]
+pub struct StateLoginHistory(pub LoginHistory);
+
+impl FromRef<ServiceRegister> for StateLoginHistory {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.login_history.clone()
+    }
+}
+
+impl StateLoginHistory {
+    pub fn new(login_history: LoginHistory) -> Self {
+        Self(login_history)
+    }
+}
+
+impl Deref for StateLoginHistory {
+    type Target = LoginHistory;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateLoginHistory {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,33 @@
+use axum::extract::FromRef;
+
+use crate::utilities::service_register::ServiceRegister;
+
+use super::{
+    login_history::StateLoginHistory, login_throttle::StateLoginThrottle,
+    mfa_service::StateMfaService, token_service::StateTokenService,
+    user_directory::StateUserDirectory, user_service::StateUserService,
+};
+
+/// Services used to authenticate a user and complete the login.
+#[derive(Clone)]
+pub struct StateLoginServices {
+    pub user_service: StateUserService,
+    pub user_directory: StateUserDirectory,
+    pub token_service: StateTokenService,
+    pub mfa_service: StateMfaService,
+    pub login_throttle: StateLoginThrottle,
+    pub login_history: StateLoginHistory,
+}
+
+impl FromRef<ServiceRegister> for StateLoginServices {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        Self {
+            user_service: input.user_service.clone(),
+            user_directory: input.user_directory.clone(),
+            token_service: input.token_service.clone(),
+            mfa_service: input.mfa_service.clone(),
+            login_throttle: input.login_throttle.clone(),
+            login_history: input.login_history.clone(),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{login_throttle::LoginThrottle, service_register::ServiceRegister};
+
+#[derive(Clone)]
+pub struct StateLoginThrottle(pub LoginThrottle);
+
+impl FromRef<ServiceRegister> for StateLoginThrottle {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.login_throttle.clone()
+    }
+}
+
+impl StateLoginThrottle {
+    pub fn new(login_throttle: LoginThrottle) -> Self {
+        Self(login_throttle)
+    }
+}
+
+impl Deref for StateLoginThrottle {
+    type Target = LoginThrottle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateLoginThrottle {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
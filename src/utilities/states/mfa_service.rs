@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{mfa::MfaStore, service_register::ServiceRegister};
+
+#[derive(Clone)]
+pub struct StateMfaService(pub MfaStore);
+
+impl FromRef<ServiceRegister> for StateMfaService {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.mfa_service.clone()
+    }
+}
+
+impl StateMfaService {
+    pub fn new(mfa_service: MfaStore) -> Self {
+        Self(mfa_service)
+    }
+}
+
+impl Deref for StateMfaService {
+    type Target = MfaStore;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateMfaService {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -1,6 +1,18 @@
+pub mod alert_services;
+pub mod audit_service;
 pub mod channels;
 pub mod email_service;
+pub mod login_history;
+pub mod login_services;
+pub mod login_throttle;
+pub mod mfa_service;
 pub mod notification_service;
+pub mod oauth_service;
+pub mod service_clients;
 pub mod templating_service;
 pub mod token_service;
+pub mod trusted_proxies;
+pub mod user_directory;
 pub mod user_service;
+pub mod verification_service;
+pub mod webauthn_service;
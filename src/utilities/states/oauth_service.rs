@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{oauth::OAuthService, service_register::ServiceRegister};
+
+#[derive(Clone)]
+pub struct StateOAuthService(pub OAuthService);
+
+impl FromRef<ServiceRegister> for StateOAuthService {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.oauth_service.clone()
+    }
+}
+
+impl StateOAuthService {
+    pub fn new(oauth_service: OAuthService) -> Self {
+        Self(oauth_service)
+    }
+}
+
+impl Deref for StateOAuthService {
+    type Target = OAuthService;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateOAuthService {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
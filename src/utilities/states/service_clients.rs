@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{service_clients::ServiceClients, service_register::ServiceRegister};
+
+#[derive(Clone)]
+pub struct StateServiceClients(pub ServiceClients);
+
+impl FromRef<ServiceRegister> for StateServiceClients {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.service_clients.clone()
+    }
+}
+
+impl StateServiceClients {
+    pub fn new(service_clients: ServiceClients) -> Self {
+        Self(service_clients)
+    }
+}
+
+impl Deref for StateServiceClients {
+    type Target = ServiceClients;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateServiceClients {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{service_register::ServiceRegister, trusted_proxies::TrustedProxies};
+
+#[derive(Clone)]
+pub struct StateTrustedProxies(pub TrustedProxies);
+
+impl FromRef<ServiceRegister> for StateTrustedProxies {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.trusted_proxies.clone()
+    }
+}
+
+impl StateTrustedProxies {
+    pub fn new(trusted_proxies: TrustedProxies) -> Self {
+        Self(trusted_proxies)
+    }
+}
+
+impl Deref for StateTrustedProxies {
+    type Target = TrustedProxies;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateTrustedProxies {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
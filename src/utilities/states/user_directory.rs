@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{service_register::ServiceRegister, user_directory::UserDirectory};
+
+#[derive(Clone)]
+pub struct StateUserDirectory(pub UserDirectory);
+
+impl FromRef<ServiceRegister> for StateUserDirectory {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.user_directory.clone()
+    }
+}
+
+impl StateUserDirectory {
+    pub fn new(user_directory: UserDirectory) -> Self {
+        Self(user_directory)
+    }
+}
+
+impl Deref for StateUserDirectory {
+    type Target = UserDirectory;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateUserDirectory {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{service_register::ServiceRegister, verification::VerificationTracker};
+
+#[derive(Clone)]
+pub struct StateVerificationService(pub VerificationTracker);
+
+impl FromRef<ServiceRegister> for StateVerificationService {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.verification_service.clone()
+    }
+}
+
+impl StateVerificationService {
+    pub fn new(verification_service: VerificationTracker) -> Self {
+        Self(verification_service)
+    }
+}
+
+impl Deref for StateVerificationService {
+    type Target = VerificationTracker;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateVerificationService {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -0,0 +1,34 @@
+use std::ops::{Deref, DerefMut};
+
+use axum::extract::FromRef;
+
+use crate::utilities::{service_register::ServiceRegister, webauthn::WebauthnService};
+
+#[derive(Clone)]
+pub struct StateWebauthnService(pub WebauthnService);
+
+impl FromRef<ServiceRegister> for StateWebauthnService {
+    fn from_ref(input: &ServiceRegister) -> Self {
+        input.webauthn_service.clone()
+    }
+}
+
+impl StateWebauthnService {
+    pub fn new(webauthn_service: WebauthnService) -> Self {
+        Self(webauthn_service)
+    }
+}
+
+impl Deref for StateWebauthnService {
+    type Target = WebauthnService;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for StateWebauthnService {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
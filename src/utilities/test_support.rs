@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, HttpBody},
+    http::{header::AUTHORIZATION, Method, Request},
+    response::Response,
+};
+use madtofan_microservice_common::user::Role;
+
+use super::{config::AppConfig, service_register::ServiceRegister, sessions::SessionClient};
+
+pub async fn service_register() -> ServiceRegister {
+    service_register_with(AppConfig::for_tests()).await
+}
+
+pub async fn service_register_with(config: AppConfig) -> ServiceRegister {
+    ServiceRegister::new(Arc::new(config)).await.unwrap()
+}
+
+pub fn role(name: &str, permissions: &[&str]) -> Role {
+    Role {
+        name: name.to_string(),
+        permissions: permissions.iter().map(|p| p.to_string()).collect(),
+    }
+}
+
+/// Signs in `user_id` with a single role holding `permissions` and returns the bearer token.
+pub fn bearer_for(
+    service_register: &ServiceRegister,
+    user_id: i64,
+    permissions: &[&str],
+) -> String {
+    service_register
+        .token_service
+        .create_token(
+            user_id,
+            &format!("user{}@example.com", user_id),
+            &[role("tester", permissions)],
+            None,
+            &SessionClient::default(),
+        )
+        .unwrap()
+        .bearer
+}
+
+pub fn request(
+    method: Method,
+    uri: &str,
+    bearer: Option<&str>,
+    body: Option<&str>,
+) -> Request<Body> {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(bearer) = bearer {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", bearer));
+    }
+    match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap()
+}
+
+pub async fn json_body(response: Response) -> serde_json::Value {
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+    serde_json::from_slice(&bytes).unwrap()
+}
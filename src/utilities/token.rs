@@ -1,4 +1,7 @@
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode, jwk::JwkSet, Algorithm, DecodingKey, EncodingKey, Header,
+    Validation,
+};
 use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
 use madtofan_microservice_common::user::Role;
 use serde::{Deserialize, Serialize};
@@ -8,35 +11,148 @@ use std::{
     time::{Duration, SystemTime},
 };
 use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use uuid::Uuid;
 
-use super::config::AppConfig;
+use super::{
+    access_tokens::{AccessTokenRecord, AccessTokenStore, ACCESS_TOKEN_PREFIX},
+    config::AppConfig,
+    consumed_tokens::ConsumedTokens,
+    cookies::RefreshCookies,
+    helpers::random_string,
+    keys::KeyStore,
+    mfa_attempts::MfaAttempts,
+    oauth_clients::{OAuthClientRecord, OAuthClientStore},
+    permission_versions::PermissionVersions,
+    refresh_families::RefreshFamilies,
+    revocations::{Revocation, RevocationList},
+    sender_tokens::{SenderTokenRecord, SenderTokenRegistry},
+    sessions::{SessionClient, SessionRecord, SessionStore},
+    sse_tickets::SseTicketStore,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredClaims {
+    pub iss: String,
+    pub aud: String,
+    pub iat: usize,
+    pub nbf: usize,
+    pub exp: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BearerClaims {
     pub sub: String,
     pub user_id: i64,
     pub permissions: Vec<String>,
-    exp: usize,
+    pub jti: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActorClaim>,
+    /// Permission version of the user when the token was issued.
+    #[serde(default)]
+    pub pver: u64,
+    /// Set on client credentials tokens, which have no user and carry `scope` instead of
+    /// `permissions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+}
+
+impl BearerClaims {
+    pub fn is_impersonated(&self) -> bool {
+        self.act.is_some()
+    }
+
+    pub fn is_client(&self) -> bool {
+        self.client_id.is_some()
+    }
+
+    /// Rejects impersonation and client tokens for actions that change how the account is
+    /// secured or signed in, which only the user themselves may take.
+    pub fn require_own_account(&self) -> ServiceResult<()> {
+        match self.is_impersonated() || self.is_client() {
+            true => Err(ServiceError::Forbidden),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether the user holds `permission`, or the client was granted it as a scope.
+    pub fn grants(&self, permission: &str) -> bool {
+        match &self.scope {
+            Some(scope) => scope.split(' ').any(|granted| granted == permission),
+            None => self.permissions.iter().any(|granted| granted == permission),
+        }
+    }
+}
+
+/// The admin acting on behalf of the subject of an impersonation token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorClaim {
+    pub sub: String,
+    pub user_id: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefreshClaims {
     pub user_id: i64,
     pub user_email: String,
-    pub exp: usize,
+    pub family_id: String,
+    pub jti: String,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct VerifyRegistrationClaim {
     user_id: i64,
-    exp: usize,
+    jti: String,
+    #[serde(flatten)]
+    registered: RegisteredClaims,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NotificationSenderClaim {
-    channel: String,
-    email: String,
-    exp: usize,
+    pub channel: String,
+    pub email: String,
+    pub jti: String,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PasswordResetClaim {
+    user_id: i64,
+    jti: String,
+    #[serde(flatten)]
+    registered: RegisteredClaims,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MagicLinkClaim {
+    user_id: i64,
+    jti: String,
+    #[serde(flatten)]
+    registered: RegisteredClaims,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaPendingClaim {
+    pub user_id: i64,
+    pub jti: String,
+    #[serde(flatten)]
+    pub registered: RegisteredClaims,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BearerIdentity {
+    User(i64),
+    Client(String),
+    Sender(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -48,39 +164,197 @@ pub struct Tokens {
 #[derive(Clone)]
 pub struct JwtService {
     config: Arc<AppConfig>,
+    keys: Arc<KeyStore>,
+    refresh_families: RefreshFamilies,
+    revocations: RevocationList,
+    sender_tokens: SenderTokenRegistry,
+    consumed_tokens: ConsumedTokens,
+    mfa_attempts: MfaAttempts,
+    access_tokens: AccessTokenStore,
+    sessions: SessionStore,
+    refresh_cookies: Arc<RefreshCookies>,
+    sse_tickets: SseTicketStore,
+    permission_versions: PermissionVersions,
+    oauth_clients: OAuthClientStore,
 }
 
 impl JwtService {
-    pub fn new(config: Arc<AppConfig>) -> Self {
-        Self { config }
+    pub fn new(config: Arc<AppConfig>) -> ServiceResult<Self> {
+        let keys = Arc::new(KeyStore::from_config(&config)?);
+        let refresh_cookies = Arc::new(RefreshCookies::from_config(&config));
+        let sender_tokens = SenderTokenRegistry::open(&config)?;
+        for group in config
+            .notification_groups
+            .iter()
+            .filter(|group| !group.is_empty())
+        {
+            sender_tokens.adopt(group);
+        }
+        let consumed_tokens = ConsumedTokens::open(&config)?;
+        let access_tokens = AccessTokenStore::open(&config)?;
+        let oauth_clients = OAuthClientStore::open(&config)?;
+        let permission_versions = PermissionVersions::open(&config)?;
+        let refresh_families = RefreshFamilies::open(&config)?;
+        let revocations = RevocationList::open(&config)?;
+        let sessions = SessionStore::open(&config)?;
+        Ok(Self {
+            config,
+            keys,
+            refresh_families,
+            revocations,
+            sender_tokens,
+            consumed_tokens,
+            mfa_attempts: MfaAttempts::default(),
+            access_tokens,
+            sessions,
+            refresh_cookies,
+            sse_tickets: SseTicketStore::default(),
+            permission_versions,
+            oauth_clients,
+        })
+    }
+
+    /// Starts a new session for `client`, identified by the refresh token family.
+    pub fn create_token(
+        &self,
+        user_id: i64,
+        email: &str,
+        roles: &[Role],
+        device_label: Option<String>,
+        client: &SessionClient,
+    ) -> ServiceResult<Tokens> {
+        let family_id = Uuid::new_v4().to_string();
+        let (tokens, refresh_claims) =
+            self.create_token_in_family(user_id, email, roles, family_id.clone())?;
+        let expires_at = refresh_claims.registered.exp;
+        self.refresh_families
+            .start(&family_id, &refresh_claims.jti, expires_at);
+
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
+        self.sessions.start(SessionRecord {
+            id: family_id,
+            user_id,
+            device_label,
+            ip_address: client.ip_address.clone(),
+            user_agent: client.user_agent.clone(),
+            created_at: now,
+            last_used_at: now,
+            expires_at,
+        });
+
+        Ok(tokens)
+    }
+
+    /// Issues the next tokens of the session, or `None` when the refresh token was already
+    /// rotated, in which case the session's refresh tokens are revoked.
+    pub fn rotate_token(
+        &self,
+        claims: &RefreshClaims,
+        roles: &[Role],
+        client: &SessionClient,
+    ) -> ServiceResult<Option<Tokens>> {
+        let (tokens, refresh_claims) = self.create_token_in_family(
+            claims.user_id,
+            &claims.user_email,
+            roles,
+            claims.family_id.clone(),
+        )?;
+        let expires_at = refresh_claims.registered.exp;
+        if !self.refresh_families.rotate(
+            &claims.family_id,
+            &claims.jti,
+            &refresh_claims.jti,
+            expires_at,
+        ) {
+            return Ok(None);
+        }
+        self.sessions.touch(&claims.family_id, client, expires_at);
+
+        Ok(Some(tokens))
     }
 
-    pub fn create_token(&self, user_id: i64, email: &str, roles: &[Role]) -> ServiceResult<Tokens> {
-        let from_now = Duration::from_secs(60);
-        let expired_future_time = SystemTime::now().add(from_now);
-        let exp = OffsetDateTime::from(expired_future_time);
+    pub fn is_refresh_token_reused(&self, claims: &RefreshClaims) -> bool {
+        self.refresh_families
+            .detect_reuse(&claims.family_id, &claims.jti)
+    }
+
+    /// Issues a bearer token for the target user that records `actor` in its `act` claim. It
+    /// has no session, so it cannot be refreshed and ends when it expires or is revoked. The
+    /// actor must hold every permission of the target, so impersonation cannot escalate.
+    pub fn create_impersonation_token(
+        &self,
+        actor: &BearerClaims,
+        user_id: i64,
+        email: &str,
+        roles: &[Role],
+    ) -> ServiceResult<(String, BearerClaims)> {
+        actor.require_own_account()?;
+        let escalates = roles
+            .iter()
+            .flat_map(|role| role.permissions.iter())
+            .any(|permission| !actor.grants(permission));
+        if escalates {
+            return Err(ServiceError::Forbidden);
+        }
 
         let bearer_claims = BearerClaims {
             sub: String::from(email),
-            exp: exp.unix_timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            sid: None,
+            act: Some(ActorClaim {
+                sub: actor.sub.clone(),
+                user_id: actor.user_id,
+            }),
+            pver: self.permission_versions.track_roles(user_id, roles),
+            client_id: None,
+            scope: None,
+            registered: self.registered_claims(self.config.impersonation_token_seconds),
             permissions: roles.iter().flat_map(|r| r.permissions.clone()).collect(),
             user_id,
         };
+        let bearer = self.sign_bearer_token(&bearer_claims)?;
 
-        let bearer = encode(
-            &Header::default(),
-            &bearer_claims,
-            &EncodingKey::from_secret(self.config.bearer_secret.as_bytes()),
-        )
-        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+        Ok((bearer, bearer_claims))
+    }
+
+    pub fn list_sessions(&self, user_id: i64) -> Vec<SessionRecord> {
+        self.sessions.list(user_id)
+    }
 
-        let from_now = Duration::from_secs(604800);
-        let expired_future_time = SystemTime::now().add(from_now);
-        let exp = OffsetDateTime::from(expired_future_time);
+    /// Ends the session so its refresh token is rejected and its bearer tokens are revoked.
+    pub fn revoke_session(&self, user_id: i64, session_id: &str) -> Option<SessionRecord> {
+        let session = self.sessions.remove(user_id, session_id)?;
+        self.end_session(&session.id);
+        Some(session)
+    }
+
+    fn create_token_in_family(
+        &self,
+        user_id: i64,
+        email: &str,
+        roles: &[Role],
+        family_id: String,
+    ) -> ServiceResult<(Tokens, RefreshClaims)> {
+        let bearer_claims = BearerClaims {
+            sub: String::from(email),
+            jti: Uuid::new_v4().to_string(),
+            sid: Some(family_id.clone()),
+            act: None,
+            pver: self.permission_versions.track_roles(user_id, roles),
+            client_id: None,
+            scope: None,
+            registered: self.registered_claims(self.config.bearer_token_seconds),
+            permissions: roles.iter().flat_map(|r| r.permissions.clone()).collect(),
+            user_id,
+        };
+
+        let bearer = self.sign_bearer_token(&bearer_claims)?;
 
         let refresh_claims = RefreshClaims {
-            exp: exp.unix_timestamp() as usize,
+            registered: self.registered_claims(self.config.refresh_token_seconds),
             user_email: String::from(email),
+            jti: Uuid::new_v4().to_string(),
+            family_id,
             user_id,
         };
 
@@ -91,38 +365,249 @@ impl JwtService {
         )
         .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
 
-        Ok(Tokens { bearer, refresh })
+        Ok((Tokens { bearer, refresh }, refresh_claims))
     }
 
+    /// Decodes a token acting for a signed in user. Client tokens are forbidden, they have no
+    /// account for the handlers to act on.
     pub fn decode_bearer_token(&self, token: &str) -> ServiceResult<BearerClaims> {
-        let decoded_token = decode::<BearerClaims>(
-            token,
-            &DecodingKey::from_secret(self.config.bearer_secret.as_bytes()),
-            &Validation::new(Algorithm::HS256),
-        )
-        .map_err(|_| ServiceError::Unauthorized)?;
+        let claims = self.decode_authorized_token(token)?;
+        match claims.is_client() {
+            true => Err(ServiceError::Forbidden),
+            false => Ok(claims),
+        }
+    }
 
-        Ok(decoded_token.claims)
+    /// Decodes a token of a user or of a machine client, for routes that only check the
+    /// permission or scope the token grants.
+    pub fn decode_authorized_token(&self, token: &str) -> ServiceResult<BearerClaims> {
+        if token.starts_with(ACCESS_TOKEN_PREFIX) {
+            return self.decode_personal_access_token(token);
+        }
+
+        let claims = self.verify_bearer_signature(token)?;
+        if self.is_bearer_revoked(&claims) || self.is_bearer_stale(&claims) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(claims)
+    }
+
+    /// Identifies who sent `token` without the side effects of `decode_bearer_token`, such as
+    /// marking a personal access token as used. Revocations are not checked. Only the signature
+    /// of the token type named by its header is verified: bearer tokens carry a `kid`, sender
+    /// tokens do not.
+    pub fn bearer_identity(&self, token: &str) -> Option<BearerIdentity> {
+        if token.starts_with(ACCESS_TOKEN_PREFIX) {
+            let record = self.access_tokens.find(token)?;
+            return Some(BearerIdentity::User(record.user_id));
+        }
+
+        if decode_header(token).ok()?.kid.is_none() {
+            let claims = self.decode_notification_sender_token(token).ok()?;
+            return Some(BearerIdentity::Sender(claims.jti));
+        }
+        let claims = self.verify_bearer_signature(token).ok()?;
+        Some(match claims.client_id {
+            Some(client_id) => BearerIdentity::Client(client_id),
+            None => BearerIdentity::User(claims.user_id),
+        })
+    }
+
+    /// Invalidates the user's outstanding bearer tokens after their roles changed.
+    pub fn bump_user_permissions(&self, user_id: i64) -> u64 {
+        self.permission_versions.bump_user(user_id)
+    }
+
+    /// Invalidates the bearer tokens built from `role_name` and returns the affected users.
+    pub fn bump_role_permissions(&self, role_name: &str) -> Vec<(i64, u64)> {
+        self.permission_versions.bump_role(role_name)
+    }
+
+    /// Invalidates the bearer tokens carrying `permission` and returns the affected users.
+    pub fn bump_permission_holders(&self, permission: &str) -> Vec<(i64, u64)> {
+        self.permission_versions.bump_permission(permission)
+    }
+
+    /// Creates a personal access token limited to `permissions`, which must be a subset of the
+    /// permissions the creating bearer token carries.
+    pub fn create_personal_access_token(
+        &self,
+        claims: &BearerClaims,
+        name: &str,
+        permissions: Vec<String>,
+        lifetime_seconds: Option<u64>,
+    ) -> ServiceResult<(String, AccessTokenRecord)> {
+        if claims.is_client() {
+            return Err(ServiceError::Forbidden);
+        }
+        if let Some(permission) = permissions
+            .iter()
+            .find(|permission| !claims.permissions.contains(permission))
+        {
+            return Err(ServiceError::BadRequest(format!(
+                "Cannot grant permission {} that you do not have",
+                permission
+            )));
+        }
+
+        let registered = self.registered_claims(
+            lifetime_seconds.unwrap_or(self.config.personal_access_token_seconds),
+        );
+        let record = AccessTokenRecord {
+            id: Uuid::new_v4().to_string(),
+            user_id: claims.user_id,
+            email: claims.sub.clone(),
+            name: name.to_string(),
+            permissions,
+            pver: claims.pver,
+            created_at: registered.iat,
+            expires_at: registered.exp,
+            last_used_at: None,
+        };
+        let token = format!("{}{}", ACCESS_TOKEN_PREFIX, random_string(40));
+        self.access_tokens.insert(&token, record.clone());
+
+        Ok((token, record))
+    }
+
+    pub fn list_personal_access_tokens(&self, user_id: i64) -> Vec<AccessTokenRecord> {
+        self.access_tokens.list(user_id)
+    }
+
+    pub fn revoke_personal_access_token(
+        &self,
+        user_id: i64,
+        id: &str,
+    ) -> Option<AccessTokenRecord> {
+        self.access_tokens.revoke(user_id, id)
+    }
+
+    /// Registers a machine client limited to `scopes`, which must be a subset of the permissions
+    /// of the registering bearer token, and returns its secret once.
+    pub fn register_client(
+        &self,
+        claims: &BearerClaims,
+        name: &str,
+        scopes: Vec<String>,
+    ) -> ServiceResult<(String, OAuthClientRecord)> {
+        claims.require_own_account()?;
+        if let Some(scope) = scopes.iter().find(|scope| !claims.grants(scope)) {
+            return Err(ServiceError::BadRequest(format!(
+                "Cannot grant scope {} that you do not have",
+                scope
+            )));
+        }
+
+        let record = OAuthClientRecord {
+            client_id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            scopes,
+            created_by: claims.user_id,
+            created_at: OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize,
+        };
+        let secret = random_string(40);
+        self.oauth_clients.insert(&secret, record.clone());
+
+        Ok((secret, record))
+    }
+
+    pub fn list_clients(&self) -> Vec<OAuthClientRecord> {
+        self.oauth_clients.list()
+    }
+
+    /// Removes the client, which revokes the tokens it was issued.
+    pub fn remove_client(&self, client_id: &str) -> Option<OAuthClientRecord> {
+        self.oauth_clients.remove(client_id)
+    }
+
+    /// Client credentials grant, issues a bearer token for the requested scopes or every scope
+    /// the client was registered with when none are requested.
+    pub fn create_client_token(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        scope: Option<&str>,
+    ) -> ServiceResult<(String, BearerClaims)> {
+        let client = self
+            .oauth_clients
+            .authenticate(client_id, client_secret)
+            .ok_or(ServiceError::Unauthorized)?;
+        let scopes = match scope {
+            Some(scope) => scope
+                .split(' ')
+                .filter(|requested| !requested.is_empty())
+                .map(
+                    |requested| match client.scopes.iter().any(|s| s == requested) {
+                        true => Ok(requested.to_string()),
+                        false => Err(ServiceError::BadRequest(format!(
+                            "Scope {} is not allowed for this client",
+                            requested
+                        ))),
+                    },
+                )
+                .collect::<ServiceResult<Vec<String>>>()?,
+            None => client.scopes,
+        };
+
+        let bearer_claims = BearerClaims {
+            sub: client.client_id.clone(),
+            user_id: 0,
+            permissions: Vec::new(),
+            jti: Uuid::new_v4().to_string(),
+            sid: None,
+            act: None,
+            pver: 0,
+            client_id: Some(client.client_id),
+            scope: Some(scopes.join(" ")),
+            registered: self.registered_claims(self.config.client_token_seconds),
+        };
+        let bearer = self.sign_bearer_token(&bearer_claims)?;
+
+        Ok((bearer, bearer_claims))
+    }
+
+    pub fn revoke_bearer_token(&self, claims: &BearerClaims) {
+        self.revocations
+            .revoke_token(&claims.jti, self.retained_until(claims.registered.exp));
+    }
+
+    pub fn revoke_user_tokens(&self, user_id: i64) {
+        for session in self.sessions.remove_user(user_id) {
+            self.end_session(&session.id);
+        }
+        self.access_tokens.revoke_user(user_id);
+        self.revocations.revoke_user(
+            user_id,
+            self.expires_after(0),
+            self.retained_until(self.expires_after(self.config.bearer_token_seconds)),
+        );
+    }
+
+    pub fn subscribe_revocations(&self) -> broadcast::Receiver<Revocation> {
+        self.revocations.subscribe()
     }
 
     pub fn decode_refresh_token(&self, refresh: &str) -> ServiceResult<RefreshClaims> {
         let decoded_token = decode::<RefreshClaims>(
             refresh,
             &DecodingKey::from_secret(self.config.refresh_secret.as_bytes()),
-            &Validation::new(Algorithm::HS256),
+            &self.validation(Algorithm::HS256),
         )
         .map_err(|_| ServiceError::Unauthorized)?;
 
+        let family_id = &decoded_token.claims.family_id;
+        if self.refresh_families.is_revoked(family_id) || !self.sessions.is_active(family_id) {
+            return Err(ServiceError::Unauthorized);
+        }
+
         Ok(decoded_token.claims)
     }
 
     pub fn create_verify_registration_token(&self, user_id: i64) -> ServiceResult<String> {
-        let from_now = Duration::from_secs(86400);
-        let expired_future_time = SystemTime::now().add(from_now);
-        let exp = OffsetDateTime::from(expired_future_time);
-
         let verify_registration_claim = VerifyRegistrationClaim {
-            exp: exp.unix_timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            registered: self.registered_claims(self.config.verify_registration_token_seconds),
             user_id,
         };
 
@@ -136,20 +621,36 @@ impl JwtService {
         Ok(token)
     }
 
-    pub fn decode_verify_registration_token(&self, token: &str) -> ServiceResult<i64> {
+    pub fn consume_verify_registration_token(&self, token: &str) -> ServiceResult<i64> {
+        let claims = self.decode_verify_registration_token(token)?;
+        self.consume_once(
+            &claims.jti,
+            claims.registered.exp,
+            "Account is already verified, this verification link has already been used",
+        )?;
+
+        Ok(claims.user_id)
+    }
+
+    /// Makes a consumed verification token usable again after the verification failed.
+    pub fn restore_verify_registration_token(&self, token: &str) {
+        if let Ok(claims) = self.decode_verify_registration_token(token) {
+            self.consumed_tokens.release(&claims.jti);
+        }
+    }
+
+    fn decode_verify_registration_token(
+        &self,
+        token: &str,
+    ) -> ServiceResult<VerifyRegistrationClaim> {
         let decoded_token = decode::<VerifyRegistrationClaim>(
             token,
             &DecodingKey::from_secret(self.config.verify_registration_secret.as_bytes()),
-            &Validation::new(Algorithm::HS256),
+            &self.validation(Algorithm::HS256),
         )
         .map_err(|_| ServiceError::Unauthorized)?;
 
-        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
-        if decoded_token.claims.exp < now {
-            return Err(ServiceError::Unauthorized);
-        }
-
-        Ok(decoded_token.claims.user_id)
+        Ok(decoded_token.claims)
     }
 
     pub fn create_notification_sender_token(
@@ -157,22 +658,28 @@ impl JwtService {
         channel: &str,
         email: &str,
     ) -> ServiceResult<String> {
-        let one_year_from_now = Duration::from_secs(31622400);
-        let expired_future_time = SystemTime::now().add(one_year_from_now);
-        let exp = OffsetDateTime::from(expired_future_time);
-
         let notification_sender_claim = NotificationSenderClaim {
             email: email.to_string(),
             channel: channel.to_string(),
-            exp: exp.unix_timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            registered: self.registered_claims(self.config.notification_sender_token_seconds),
         };
 
         let token = encode(
             &Header::default(),
             &notification_sender_claim,
-            &EncodingKey::from_secret(self.config.verify_registration_secret.as_bytes()),
+            &EncodingKey::from_secret(self.config.notification_sender_secret.as_bytes()),
         )
         .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+        self.sender_tokens.activate(
+            channel,
+            SenderTokenRecord {
+                jti: notification_sender_claim.jti,
+                admin_email: notification_sender_claim.email,
+                issued_at: notification_sender_claim.registered.iat,
+                expires_at: notification_sender_claim.registered.exp,
+            },
+        );
 
         Ok(token)
     }
@@ -183,16 +690,773 @@ impl JwtService {
     ) -> ServiceResult<NotificationSenderClaim> {
         let decoded_token = decode::<NotificationSenderClaim>(
             token,
-            &DecodingKey::from_secret(self.config.verify_registration_secret.as_bytes()),
-            &Validation::new(Algorithm::HS256),
+            &DecodingKey::from_secret(self.config.notification_sender_secret.as_bytes()),
+            &self.validation(Algorithm::HS256),
         )
         .map_err(|_| ServiceError::Unauthorized)?;
 
-        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp() as usize;
-        if decoded_token.claims.exp < now {
+        let claims = decoded_token.claims;
+        if !self.sender_tokens.is_accepted(&claims.channel, &claims.jti) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(claims)
+    }
+
+    pub fn revoke_notification_sender_token(&self, channel: &str) -> Option<SenderTokenRecord> {
+        self.sender_tokens.revoke(channel)
+    }
+
+    pub fn active_notification_sender_token(&self, channel: &str) -> Option<SenderTokenRecord> {
+        self.sender_tokens.active(channel)
+    }
+
+    pub fn is_notification_group_known(&self, channel: &str) -> bool {
+        self.sender_tokens.is_known(channel)
+    }
+
+    pub fn remove_notification_group(&self, channel: &str) {
+        self.sender_tokens.remove(channel)
+    }
+
+    pub fn create_password_reset_token(&self, user_id: i64) -> ServiceResult<String> {
+        let password_reset_claim = PasswordResetClaim {
+            jti: Uuid::new_v4().to_string(),
+            registered: self.registered_claims(self.config.password_reset_token_seconds),
+            user_id,
+        };
+
+        let token = encode(
+            &Header::default(),
+            &password_reset_claim,
+            &EncodingKey::from_secret(self.config.password_reset_secret.as_bytes()),
+        )
+        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+        Ok(token)
+    }
+
+    pub fn consume_password_reset_token(&self, token: &str) -> ServiceResult<i64> {
+        let claims = self.decode_password_reset_token(token)?;
+        self.consume_once(
+            &claims.jti,
+            claims.registered.exp,
+            "This password reset link has already been used",
+        )?;
+
+        Ok(claims.user_id)
+    }
+
+    /// Makes a consumed password reset token usable again after the reset failed.
+    pub fn restore_password_reset_token(&self, token: &str) {
+        if let Ok(claims) = self.decode_password_reset_token(token) {
+            self.consumed_tokens.release(&claims.jti);
+        }
+    }
+
+    fn decode_password_reset_token(&self, token: &str) -> ServiceResult<PasswordResetClaim> {
+        let decoded_token = decode::<PasswordResetClaim>(
+            token,
+            &DecodingKey::from_secret(self.config.password_reset_secret.as_bytes()),
+            &self.validation(Algorithm::HS256),
+        )
+        .map_err(|_| ServiceError::Unauthorized)?;
+
+        Ok(decoded_token.claims)
+    }
+
+    pub fn create_magic_link_token(&self, user_id: i64) -> ServiceResult<String> {
+        let magic_link_claim = MagicLinkClaim {
+            jti: Uuid::new_v4().to_string(),
+            registered: self.registered_claims(self.config.magic_link_token_seconds),
+            user_id,
+        };
+
+        let token = encode(
+            &Header::default(),
+            &magic_link_claim,
+            &EncodingKey::from_secret(self.config.magic_link_secret.as_bytes()),
+        )
+        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+        Ok(token)
+    }
+
+    pub fn consume_magic_link_token(&self, token: &str) -> ServiceResult<i64> {
+        let claims = self.decode_magic_link_token(token)?;
+        self.consume_once(
+            &claims.jti,
+            claims.registered.exp,
+            "This sign in link has already been used",
+        )?;
+
+        Ok(claims.user_id)
+    }
+
+    /// Checks the sign in link without using it up.
+    pub fn check_magic_link_token(&self, token: &str) -> ServiceResult<()> {
+        let claims = self.decode_magic_link_token(token)?;
+        match self.consumed_tokens.is_consumed(&claims.jti) {
+            true => Err(ServiceError::BadRequest(
+                "This sign in link has already been used".to_string(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Makes a consumed sign in link usable again after the login failed.
+    pub fn restore_magic_link_token(&self, token: &str) {
+        if let Ok(claims) = self.decode_magic_link_token(token) {
+            self.consumed_tokens.release(&claims.jti);
+        }
+    }
+
+    fn decode_magic_link_token(&self, token: &str) -> ServiceResult<MagicLinkClaim> {
+        let decoded_token = decode::<MagicLinkClaim>(
+            token,
+            &DecodingKey::from_secret(self.config.magic_link_secret.as_bytes()),
+            &self.validation(Algorithm::HS256),
+        )
+        .map_err(|_| ServiceError::Unauthorized)?;
+
+        Ok(decoded_token.claims)
+    }
+
+    pub fn create_mfa_pending_token(&self, user_id: i64) -> ServiceResult<String> {
+        let mfa_pending_claim = MfaPendingClaim {
+            jti: Uuid::new_v4().to_string(),
+            registered: self.registered_claims(self.config.mfa_pending_token_seconds),
+            user_id,
+        };
+
+        let token = encode(
+            &Header::default(),
+            &mfa_pending_claim,
+            &EncodingKey::from_secret(self.config.mfa_pending_secret.as_bytes()),
+        )
+        .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))?;
+
+        Ok(token)
+    }
+
+    pub fn decode_mfa_pending_token(&self, token: &str) -> ServiceResult<MfaPendingClaim> {
+        let decoded_token = decode::<MfaPendingClaim>(
+            token,
+            &DecodingKey::from_secret(self.config.mfa_pending_secret.as_bytes()),
+            &self.validation(Algorithm::HS256),
+        )
+        .map_err(|_| ServiceError::Unauthorized)?;
+
+        let claims = decoded_token.claims;
+        if self.consumed_tokens.is_consumed(&claims.jti) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(claims)
+    }
+
+    /// Counts a wrong code against the pending login and burns its mfa token once
+    /// `mfa_max_attempts` is reached, returning whether it was burned.
+    pub fn record_mfa_failure(&self, claims: &MfaPendingClaim) -> bool {
+        let retained_until = self.retained_until(claims.registered.exp);
+        let failures = self.mfa_attempts.fail(&claims.jti, retained_until);
+        if failures < self.config.mfa_max_attempts {
+            return false;
+        }
+
+        self.consumed_tokens.consume(&claims.jti, retained_until);
+        true
+    }
+
+    /// Marks the pending login as completed so the same mfa token cannot be exchanged twice.
+    pub fn consume_mfa_pending_token(&self, claims: &MfaPendingClaim) -> ServiceResult<()> {
+        self.consume_once(
+            &claims.jti,
+            claims.registered.exp,
+            "This login has already been completed",
+        )
+    }
+
+    /// Exchanges verified bearer claims for a short-lived ticket and returns it with its expiry.
+    pub fn create_sse_ticket(&self, claims: BearerClaims) -> (String, usize) {
+        let ticket = random_string(32);
+        let expires_at = self.registered_claims(self.config.sse_ticket_seconds).exp;
+        self.sse_tickets.insert(&ticket, claims, expires_at);
+        (ticket, expires_at)
+    }
+
+    /// Redeems a ticket once, rejecting it if the session behind it was revoked in the meantime.
+    pub fn consume_sse_ticket(&self, ticket: &str) -> ServiceResult<BearerClaims> {
+        let claims = self
+            .sse_tickets
+            .consume(ticket)
+            .ok_or(ServiceError::Unauthorized)?;
+        if self.is_bearer_revoked(&claims) || self.is_bearer_stale(&claims) {
             return Err(ServiceError::Unauthorized);
         }
 
+        Ok(claims)
+    }
+
+    pub fn refresh_cookies(&self) -> &RefreshCookies {
+        &self.refresh_cookies
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        self.keys.jwks()
+    }
+
+    fn registered_claims(&self, lifetime_seconds: u64) -> RegisteredClaims {
+        let now = SystemTime::now();
+        let issued_at = OffsetDateTime::from(now).unix_timestamp() as usize;
+        let exp = OffsetDateTime::from(now.add(Duration::from_secs(lifetime_seconds)));
+
+        RegisteredClaims {
+            iss: self.config.token_issuer.clone(),
+            aud: self.config.token_audience.clone(),
+            iat: issued_at,
+            nbf: issued_at,
+            exp: exp.unix_timestamp() as usize,
+        }
+    }
+
+    fn sign_bearer_token(&self, claims: &BearerClaims) -> ServiceResult<String> {
+        let (kid, signing_key) = self.keys.signing_key()?;
+        let bearer_header = Header {
+            kid: Some(kid.to_string()),
+            ..Header::new(self.keys.algorithm())
+        };
+        encode(&bearer_header, claims, signing_key)
+            .map_err(|err| ServiceError::InternalServerErrorWithContext(err.to_string()))
+    }
+
+    fn verify_bearer_signature(&self, token: &str) -> ServiceResult<BearerClaims> {
+        let kid = decode_header(token)
+            .map_err(|_| ServiceError::Unauthorized)?
+            .kid
+            .ok_or(ServiceError::Unauthorized)?;
+        let decoded_token = decode::<BearerClaims>(
+            token,
+            self.keys.verification_key(&kid)?,
+            &self.validation(self.keys.algorithm()),
+        )
+        .map_err(|_| ServiceError::Unauthorized)?;
+
         Ok(decoded_token.claims)
     }
+
+    /// Client tokens are revoked with their client, they share no user with the denylist.
+    fn is_bearer_revoked(&self, claims: &BearerClaims) -> bool {
+        if let Some(client_id) = &claims.client_id {
+            return !self.oauth_clients.contains(client_id)
+                || self.revocations.is_token_revoked(&claims.jti);
+        }
+
+        // Signing out everywhere also ends the impersonations the user started.
+        let session_bound = claims.sid.is_some();
+        let actor_revoked = claims.act.as_ref().is_some_and(|actor| {
+            self.revocations
+                .is_revoked(&claims.jti, actor.user_id, claims.registered.iat, false)
+        });
+        let session_revoked = claims
+            .sid
+            .iter()
+            .any(|sid| self.revocations.is_token_revoked(sid));
+        session_revoked
+            || actor_revoked
+            || self.revocations.is_revoked(
+                &claims.jti,
+                claims.user_id,
+                claims.registered.iat,
+                session_bound,
+            )
+    }
+
+    /// Client scopes only change by removing the client, which revokes its tokens.
+    fn is_bearer_stale(&self, claims: &BearerClaims) -> bool {
+        !claims.is_client() && claims.pver < self.permission_versions.current(claims.user_id)
+    }
+
+    fn end_session(&self, session_id: &str) {
+        self.refresh_families.revoke(session_id);
+        self.revocations.revoke_token(
+            session_id,
+            self.retained_until(self.expires_after(self.config.bearer_token_seconds)),
+        );
+    }
+
+    fn decode_personal_access_token(&self, token: &str) -> ServiceResult<BearerClaims> {
+        let record = self
+            .access_tokens
+            .authenticate(token)
+            .ok_or(ServiceError::Unauthorized)?;
+        if self.revocations.is_token_revoked(&record.id) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        let pver = self.permission_versions.current(record.user_id);
+        let permissions = match record.pver == pver {
+            true => record.permissions,
+            false => {
+                let current_permissions = self
+                    .permission_versions
+                    .current_permissions(record.user_id)
+                    .ok_or(ServiceError::Unauthorized)?;
+                record
+                    .permissions
+                    .into_iter()
+                    .filter(|permission| current_permissions.contains(permission))
+                    .collect()
+            }
+        };
+
+        Ok(BearerClaims {
+            sub: record.email,
+            user_id: record.user_id,
+            permissions,
+            jti: record.id,
+            sid: None,
+            act: None,
+            pver,
+            client_id: None,
+            scope: None,
+            registered: RegisteredClaims {
+                iss: self.config.token_issuer.clone(),
+                aud: self.config.token_audience.clone(),
+                iat: record.created_at,
+                nbf: record.created_at,
+                exp: record.expires_at,
+            },
+        })
+    }
+
+    fn consume_once(&self, jti: &str, exp: usize, used_message: &str) -> ServiceResult<()> {
+        match self.consumed_tokens.consume(jti, self.retained_until(exp)) {
+            true => Ok(()),
+            false => Err(ServiceError::BadRequest(used_message.to_string())),
+        }
+    }
+
+    fn expires_after(&self, lifetime_seconds: u64) -> usize {
+        let expires_at = SystemTime::now().add(Duration::from_secs(lifetime_seconds));
+        OffsetDateTime::from(expires_at).unix_timestamp() as usize
+    }
+
+    /// Until when a denylist or consumed token entry for a token expiring at `exp` has to be
+    /// kept, since validation still accepts the token for the leeway past its expiry.
+    fn retained_until(&self, exp: usize) -> usize {
+        exp + self.config.token_leeway_seconds as usize
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&self.config.token_issuer]);
+        validation.set_audience(&[&self.config.token_audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+        validation.validate_nbf = true;
+        validation.leeway = self.config.token_leeway_seconds;
+        validation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::test_support::role;
+
+    fn service() -> JwtService {
+        JwtService::new(Arc::new(AppConfig::for_tests())).unwrap()
+    }
+
+    fn sign_in(token_service: &JwtService, user_id: i64) -> Tokens {
+        token_service
+            .create_token(
+                user_id,
+                "user@example.com",
+                &[role("tester", &["user:read"])],
+                None,
+                &SessionClient::default(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn logout_all_revokes_tokens_issued_in_the_same_second() {
+        let token_service = service();
+        let tokens = sign_in(&token_service, 1);
+        let other_user = sign_in(&token_service, 2);
+
+        token_service.revoke_user_tokens(1);
+
+        assert!(token_service.decode_bearer_token(&tokens.bearer).is_err());
+        assert!(token_service.decode_refresh_token(&tokens.refresh).is_err());
+        assert!(token_service
+            .decode_bearer_token(&other_user.bearer)
+            .is_ok());
+    }
+
+    #[test]
+    fn signing_in_right_after_logout_all_works() {
+        let token_service = service();
+        sign_in(&token_service, 1);
+        token_service.revoke_user_tokens(1);
+
+        let tokens = sign_in(&token_service, 1);
+        assert!(token_service.decode_bearer_token(&tokens.bearer).is_ok());
+        assert!(token_service.decode_refresh_token(&tokens.refresh).is_ok());
+    }
+
+    #[test]
+    fn sessions_survive_a_restart() {
+        let config = Arc::new(AppConfig::for_tests());
+        let token_service = JwtService::new(config.clone()).unwrap();
+        let tokens = sign_in(&token_service, 1);
+        let ended = sign_in(&token_service, 1);
+        let ended_claims = token_service.decode_bearer_token(&ended.bearer).unwrap();
+        token_service.revoke_session(1, ended_claims.sid.as_deref().unwrap());
+
+        let restarted = JwtService::new(config).unwrap();
+        let claims = restarted.decode_refresh_token(&tokens.refresh).unwrap();
+        assert!(restarted
+            .rotate_token(&claims, &[], &SessionClient::default())
+            .unwrap()
+            .is_some());
+        assert!(restarted.decode_refresh_token(&ended.refresh).is_err());
+        assert!(restarted.decode_bearer_token(&ended.bearer).is_err());
+    }
+
+    #[test]
+    fn logout_all_revokes_impersonation_tokens_issued_in_the_same_second() {
+        let token_service = service();
+        let actor = token_service
+            .decode_bearer_token(&sign_in(&token_service, 1).bearer)
+            .unwrap();
+        let impersonate = || {
+            token_service
+                .create_impersonation_token(&actor, 2, "user2@example.com", &[])
+                .unwrap()
+                .0
+        };
+        let target_token = impersonate();
+        let actor_token = impersonate();
+
+        token_service.revoke_user_tokens(2);
+        assert!(token_service.decode_bearer_token(&target_token).is_err());
+        assert!(token_service.decode_bearer_token(&actor_token).is_err());
+
+        let actor_token = impersonate();
+        token_service.revoke_user_tokens(1);
+        assert!(token_service.decode_bearer_token(&actor_token).is_err());
+    }
+
+    #[test]
+    fn logout_revokes_only_the_bearer_token() {
+        let token_service = service();
+        let first = sign_in(&token_service, 1);
+        let second = sign_in(&token_service, 1);
+        let claims = token_service.decode_bearer_token(&first.bearer).unwrap();
+
+        token_service.revoke_bearer_token(&claims);
+
+        assert!(token_service.decode_bearer_token(&first.bearer).is_err());
+        assert!(token_service.decode_bearer_token(&second.bearer).is_ok());
+    }
+
+    #[test]
+    fn concurrent_refreshes_rotate_the_token_once() {
+        let token_service = service();
+        let tokens = sign_in(&token_service, 1);
+        let claims = token_service.decode_refresh_token(&tokens.refresh).unwrap();
+        let roles = [role("tester", &["user:read"])];
+        let barrier = std::sync::Barrier::new(2);
+
+        let rotated = std::thread::scope(|scope| {
+            let refreshes = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        token_service
+                            .rotate_token(&claims, &roles, &SessionClient::default())
+                            .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            refreshes
+                .into_iter()
+                .map(|refresh| refresh.join().unwrap())
+                .collect::<Vec<Option<Tokens>>>()
+        });
+
+        assert_eq!(rotated.iter().filter(|tokens| tokens.is_some()).count(), 1);
+        let winner = rotated.into_iter().flatten().next().unwrap();
+        assert!(token_service.decode_refresh_token(&winner.refresh).is_err());
+    }
+
+    fn now() -> usize {
+        OffsetDateTime::now_utc().unix_timestamp() as usize
+    }
+
+    fn resign(
+        token_service: &JwtService,
+        bearer: &str,
+        edit: impl Fn(&mut BearerClaims),
+    ) -> String {
+        let mut claims = token_service.decode_bearer_token(bearer).unwrap();
+        edit(&mut claims);
+        token_service.sign_bearer_token(&claims).unwrap()
+    }
+
+    #[test]
+    fn rejects_tokens_for_another_issuer_or_audience() {
+        let token_service = service();
+        let tokens = sign_in(&token_service, 1);
+
+        let foreign_issuer = resign(&token_service, &tokens.bearer, |claims| {
+            claims.registered.iss = "https://other.example.com".to_string()
+        });
+        let foreign_audience = resign(&token_service, &tokens.bearer, |claims| {
+            claims.registered.aud = "other-api".to_string()
+        });
+        let not_yet_valid = resign(&token_service, &tokens.bearer, |claims| {
+            claims.registered.nbf = now() + 3600
+        });
+
+        assert!(token_service.decode_bearer_token(&foreign_issuer).is_err());
+        assert!(token_service
+            .decode_bearer_token(&foreign_audience)
+            .is_err());
+        assert!(token_service.decode_bearer_token(&not_yet_valid).is_err());
+    }
+
+    #[test]
+    fn accepts_expired_tokens_within_the_leeway() {
+        let token_service = service();
+        let tokens = sign_in(&token_service, 1);
+        let within_leeway = resign(&token_service, &tokens.bearer, |claims| {
+            claims.registered.exp = now() - 5
+        });
+        let past_leeway = resign(&token_service, &tokens.bearer, |claims| {
+            claims.registered.exp = now() - 120
+        });
+
+        assert!(token_service.decode_bearer_token(&within_leeway).is_ok());
+        assert!(token_service.decode_bearer_token(&past_leeway).is_err());
+    }
+
+    #[test]
+    fn revoked_token_stays_denied_for_the_leeway_after_expiry() {
+        let token_service = service();
+        let tokens = sign_in(&token_service, 1);
+        let expired = resign(&token_service, &tokens.bearer, |claims| {
+            claims.registered.exp = now() - 5
+        });
+        let claims = token_service.decode_bearer_token(&expired).unwrap();
+        token_service.revoke_bearer_token(&claims);
+
+        // Revoking another token prunes the denylist.
+        let other = sign_in(&token_service, 2);
+        let other_claims = token_service.decode_bearer_token(&other.bearer).unwrap();
+        token_service.revoke_bearer_token(&other_claims);
+
+        assert!(token_service.decode_bearer_token(&expired).is_err());
+    }
+
+    #[test]
+    fn consumed_token_stays_used_for_the_leeway_after_expiry() {
+        let token_service = service();
+        let reset_token = |jti: &str| {
+            let claims = PasswordResetClaim {
+                user_id: 1,
+                jti: jti.to_string(),
+                registered: RegisteredClaims {
+                    exp: now() - 5,
+                    ..token_service.registered_claims(0)
+                },
+            };
+            encode(
+                &Header::default(),
+                &claims,
+                &EncodingKey::from_secret(token_service.config.password_reset_secret.as_bytes()),
+            )
+            .unwrap()
+        };
+        let token = reset_token("first");
+        assert_eq!(
+            token_service.consume_password_reset_token(&token).unwrap(),
+            1
+        );
+
+        // Consuming another token prunes the consumed list.
+        token_service
+            .consume_password_reset_token(&reset_token("second"))
+            .unwrap();
+
+        assert!(token_service.consume_password_reset_token(&token).is_err());
+    }
+
+    #[test]
+    fn restored_password_reset_token_can_be_used_again() {
+        let token_service = service();
+        let token = token_service.create_password_reset_token(1).unwrap();
+
+        assert_eq!(
+            token_service.consume_password_reset_token(&token).unwrap(),
+            1
+        );
+        assert!(token_service.consume_password_reset_token(&token).is_err());
+        token_service.restore_password_reset_token(&token);
+        assert_eq!(
+            token_service.consume_password_reset_token(&token).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn mfa_token_is_burned_after_too_many_wrong_codes() {
+        let token_service = service();
+        let token = token_service.create_mfa_pending_token(1).unwrap();
+        let claims = token_service.decode_mfa_pending_token(&token).unwrap();
+
+        for _ in 1..token_service.config.mfa_max_attempts {
+            assert!(!token_service.record_mfa_failure(&claims));
+        }
+        assert!(token_service.decode_mfa_pending_token(&token).is_ok());
+        assert!(token_service.record_mfa_failure(&claims));
+        assert!(token_service.decode_mfa_pending_token(&token).is_err());
+    }
+
+    fn personal_access_token(token_service: &JwtService, permissions: &[&str]) -> String {
+        let bearer = token_service
+            .create_token(
+                1,
+                "user@example.com",
+                &[role("tester", &["user:read", "user:write"])],
+                None,
+                &SessionClient::default(),
+            )
+            .unwrap()
+            .bearer;
+        let claims = token_service.decode_bearer_token(&bearer).unwrap();
+        let permissions = permissions.iter().map(|p| p.to_string()).collect();
+        let (token, record) = token_service
+            .create_personal_access_token(&claims, "ci", permissions, None)
+            .unwrap();
+        assert_eq!(record.pver, claims.pver);
+        token
+    }
+
+    #[test]
+    fn personal_access_token_is_narrowed_to_the_current_permissions() {
+        let token_service = service();
+        let token = personal_access_token(&token_service, &["user:read", "user:write"]);
+
+        token_service.bump_user_permissions(1);
+        assert!(token_service.decode_bearer_token(&token).is_err());
+
+        token_service
+            .create_token(
+                1,
+                "user@example.com",
+                &[role("reader", &["user:read"])],
+                None,
+                &SessionClient::default(),
+            )
+            .unwrap();
+        let claims = token_service.decode_bearer_token(&token).unwrap();
+        assert_eq!(claims.permissions, vec!["user:read".to_string()]);
+    }
+
+    #[test]
+    fn logout_all_revokes_personal_access_tokens() {
+        let token_service = service();
+        let token = personal_access_token(&token_service, &["user:read"]);
+
+        token_service.revoke_user_tokens(1);
+        assert!(token_service.decode_bearer_token(&token).is_err());
+        assert!(token_service.list_personal_access_tokens(1).is_empty());
+    }
+
+    #[test]
+    fn impersonation_cannot_escalate_permissions() {
+        let token_service = service();
+        let bearer = sign_in(&token_service, 1);
+        let actor = token_service.decode_bearer_token(&bearer.bearer).unwrap();
+
+        let admin = [role("admin", &["user:read", "user:write"])];
+        assert!(matches!(
+            token_service.create_impersonation_token(&actor, 2, "user2@example.com", &admin),
+            Err(ServiceError::Forbidden)
+        ));
+
+        let reader = [role("reader", &["user:read"])];
+        let (token, claims) = token_service
+            .create_impersonation_token(&actor, 2, "user2@example.com", &reader)
+            .unwrap();
+        assert_eq!(claims.act.unwrap().user_id, 1);
+
+        let impersonated = token_service.decode_bearer_token(&token).unwrap();
+        assert!(token_service
+            .create_impersonation_token(&impersonated, 3, "user3@example.com", &[])
+            .is_err());
+    }
+
+    fn register_client(token_service: &JwtService) -> (String, String) {
+        let bearer = sign_in(token_service, 1).bearer;
+        let claims = token_service.decode_bearer_token(&bearer).unwrap();
+        let (secret, record) = token_service
+            .register_client(&claims, "jobs", vec!["user:read".to_string()])
+            .unwrap();
+        (record.client_id, secret)
+    }
+
+    #[test]
+    fn client_tokens_cannot_act_for_a_user() {
+        let token_service = service();
+        let (client_id, secret) = register_client(&token_service);
+        let (token, _) = token_service
+            .create_client_token(&client_id, &secret, None)
+            .unwrap();
+
+        let claims = token_service.decode_authorized_token(&token).unwrap();
+        assert!(claims.grants("user:read"));
+        assert!(matches!(
+            token_service.decode_bearer_token(&token),
+            Err(ServiceError::Forbidden)
+        ));
+        assert!(token_service
+            .register_client(&claims, "nested", vec!["user:read".to_string()])
+            .is_err());
+    }
+
+    #[test]
+    fn removing_a_client_revokes_only_its_tokens() {
+        let token_service = service();
+        let (first_id, first_secret) = register_client(&token_service);
+        let (second_id, second_secret) = register_client(&token_service);
+        let (first, _) = token_service
+            .create_client_token(&first_id, &first_secret, None)
+            .unwrap();
+        let (second, _) = token_service
+            .create_client_token(&second_id, &second_secret, None)
+            .unwrap();
+        let user = sign_in(&token_service, 1);
+
+        assert!(token_service.remove_client(&first_id).is_some());
+        assert!(token_service.decode_authorized_token(&first).is_err());
+        assert!(token_service.decode_authorized_token(&second).is_ok());
+        assert!(token_service.decode_bearer_token(&user.bearer).is_ok());
+    }
+
+    #[test]
+    fn clients_survive_a_restart() {
+        let config = Arc::new(AppConfig::for_tests());
+        let token_service = JwtService::new(config.clone()).unwrap();
+        let (client_id, secret) = register_client(&token_service);
+
+        let restarted = JwtService::new(config).unwrap();
+        let (token, _) = restarted
+            .create_client_token(&client_id, &secret, None)
+            .unwrap();
+        assert!(restarted.decode_authorized_token(&token).is_ok());
+    }
 }
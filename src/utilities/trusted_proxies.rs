@@ -0,0 +1,137 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{Extensions, HeaderMap},
+};
+use ipnet::IpNet;
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+
+use super::config::AppConfig;
+
+/// Reverse proxies allowed to report the client address through `x-forwarded-for`.
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+    pub fn from_config(config: &AppConfig) -> ServiceResult<Self> {
+        let networks = config
+            .trusted_proxies
+            .iter()
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| {
+                        ServiceError::InternalServerErrorWithContext(format!(
+                            "Invalid trusted proxy, expected an address or a network: {}",
+                            entry
+                        ))
+                    })
+            })
+            .collect::<ServiceResult<Vec<IpNet>>>()?;
+
+        Ok(Self(Arc::new(networks)))
+    }
+
+    fn is_trusted(&self, address: &IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(address))
+    }
+
+    /// The connected peer, or when the peer is a trusted proxy the right-most `x-forwarded-for`
+    /// hop that is not one, since every hop left of it could have been set by the client.
+    pub fn client_ip(&self, headers: &HeaderMap, extensions: &Extensions) -> Option<String> {
+        let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+        let mut client = peer.ip();
+        if !self.is_trusted(&client) {
+            return Some(client.to_string());
+        }
+
+        let forwarded_for = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<&str>>();
+        for hop in forwarded_for.into_iter().rev() {
+            let Ok(address) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = address;
+            if !self.is_trusted(&client) {
+                break;
+            }
+        }
+
+        Some(client.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted_proxies(entries: &[&str]) -> TrustedProxies {
+        let mut config = AppConfig::for_tests();
+        config.trusted_proxies = entries.iter().map(|entry| entry.to_string()).collect();
+        TrustedProxies::from_config(&config).unwrap()
+    }
+
+    fn client_ip(
+        trusted_proxies: &TrustedProxies,
+        peer: Option<&str>,
+        forwarded_for: Option<&str>,
+    ) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        if let Some(forwarded_for) = forwarded_for {
+            headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        }
+        let mut extensions = Extensions::new();
+        if let Some(peer) = peer {
+            extensions.insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        }
+        trusted_proxies.client_ip(&headers, &extensions)
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_spoof_their_address() {
+        let proxies = trusted_proxies(&[]);
+        assert_eq!(
+            client_ip(&proxies, Some("203.0.113.7:4000"), Some("198.51.100.1")),
+            Some("203.0.113.7".to_string())
+        );
+        assert_eq!(client_ip(&proxies, None, Some("198.51.100.1")), None);
+    }
+
+    #[test]
+    fn trusted_proxies_report_the_right_most_untrusted_hop() {
+        let proxies = trusted_proxies(&["10.0.0.0/8", "192.168.1.1"]);
+        assert_eq!(
+            client_ip(
+                &proxies,
+                Some("10.0.0.2:4000"),
+                Some("1.1.1.1, 203.0.113.7, 192.168.1.1")
+            ),
+            Some("203.0.113.7".to_string())
+        );
+        assert_eq!(
+            client_ip(&proxies, Some("10.0.0.2:4000"), Some("10.0.0.3")),
+            Some("10.0.0.3".to_string())
+        );
+        assert_eq!(
+            client_ip(&proxies, Some("10.0.0.2:4000"), Some("not-an-address")),
+            Some("10.0.0.2".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let mut config = AppConfig::for_tests();
+        config.trusted_proxies = vec!["proxy.internal".to_string()];
+        assert!(TrustedProxies::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{collections::HashMap, time::Duration};
+
+use madtofan_microservice_common::{errors::ServiceResult, user::GetListRequest};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    config::AppConfig, constants::PAGINATION_SIZE, persistence::Persisted,
+    states::user_service::StateUserService,
+};
+
+#[derive(Default, Serialize, Deserialize)]
+struct DirectoryEntries {
+    emails: HashMap<String, i64>,
+}
+
+/// Index from account email to user id. The user service can only look users up by id, so the
+/// index is fed by the users this gateway sees and rebuilt from the user list in the background,
+/// which keeps anonymous endpoints from paging through every user.
+#[derive(Clone, Default)]
+pub struct UserDirectory(Persisted<DirectoryEntries>);
+
+impl UserDirectory {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self(Persisted::open(config, "user_directory")?))
+    }
+
+    pub fn record(&self, email: &str, user_id: i64) {
+        let key = email.to_lowercase();
+        if self.find(&key) == Some(user_id) {
+            return;
+        }
+        let mut entries = self.0.lock();
+        entries.emails.insert(key, user_id);
+    }
+
+    pub fn forget(&self, email: &str) {
+        let mut entries = self.0.lock();
+        entries.emails.remove(&email.to_lowercase());
+    }
+
+    pub fn find(&self, email: &str) -> Option<i64> {
+        let entries = self.0.lock();
+        entries.emails.get(&email.to_lowercase()).copied()
+    }
+
+    /// Rebuilds the index from the user list every `interval_seconds`.
+    pub fn spawn_synchronisation(&self, user_service: StateUserService, interval_seconds: u64) {
+        let directory = self.clone();
+        tokio::spawn(async move {
+            let mut user_service = user_service;
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+            loop {
+                interval.tick().await;
+                match directory.synchronise(&mut user_service).await {
+                    Ok(count) => info!("User directory synchronised with {} accounts", count),
+                    Err(err) => warn!("Unable to synchronise user directory: {}", err),
+                }
+            }
+        });
+    }
+
+    async fn synchronise(&self, user_service: &mut StateUserService) -> ServiceResult<usize> {
+        let mut emails = HashMap::new();
+        let mut offset = 0;
+        loop {
+            let user_list_response = user_service
+                .get_user_list(GetListRequest {
+                    offset,
+                    limit: *PAGINATION_SIZE,
+                })
+                .await?
+                .into_inner();
+            let fetched = user_list_response.users.len();
+            for user in user_list_response.users {
+                emails.insert(user.email.to_lowercase(), user.id);
+            }
+
+            offset += *PAGINATION_SIZE;
+            if fetched == 0 || offset >= user_list_response.count {
+                break;
+            }
+        }
+
+        let count = emails.len();
+        let mut entries = self.0.lock();
+        entries.emails = emails;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookups_ignore_case() {
+        let directory = UserDirectory::default();
+        directory.record("User@Example.com", 1);
+
+        assert_eq!(directory.find("user@example.com"), Some(1));
+        directory.forget("USER@example.com");
+        assert_eq!(directory.find("user@example.com"), None);
+    }
+
+    #[test]
+    fn entries_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        UserDirectory::open(&config)
+            .unwrap()
+            .record("user@example.com", 1);
+
+        let reopened = UserDirectory::open(&config).unwrap();
+        assert_eq!(reopened.find("user@example.com"), Some(1));
+    }
+}
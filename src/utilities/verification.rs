@@ -0,0 +1,52 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use time::OffsetDateTime;
+
+#[derive(Clone)]
+pub struct VerificationTracker {
+    resend_cooldown_seconds: i64,
+    resends: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl VerificationTracker {
+    pub fn new(resend_cooldown_seconds: i64) -> Self {
+        Self {
+            resend_cooldown_seconds,
+            resends: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records a resend for `email`, or returns the seconds left until the next one is allowed.
+    pub fn try_resend(&self, email: &str) -> Result<(), i64> {
+        let mut resends = self.resends.lock().unwrap();
+        let now = OffsetDateTime::from(SystemTime::now()).unix_timestamp();
+        resends.retain(|_, sent_at| *sent_at + self.resend_cooldown_seconds > now);
+
+        let email = email.to_lowercase();
+        if let Some(sent_at) = resends.get(&email) {
+            return Err(*sent_at + self.resend_cooldown_seconds - now);
+        }
+        resends.insert(email, now);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resends_are_limited_per_email() {
+        let tracker = VerificationTracker::new(300);
+
+        assert!(tracker.try_resend("user@example.com").is_ok());
+        let retry_after = tracker.try_resend("USER@example.com").unwrap_err();
+        assert!(retry_after > 0 && retry_after <= 300);
+        assert!(tracker.try_resend("other@example.com").is_ok());
+    }
+}
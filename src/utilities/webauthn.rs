@@ -0,0 +1,620 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use madtofan_microservice_common::errors::{ServiceError, ServiceResult};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use super::{config::AppConfig, persistence::Persisted};
+
+const CHALLENGE_LENGTH: usize = 32;
+const COSE_ALGORITHM_ES256: i64 = -7;
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PasskeyRecord {
+    pub id: String,
+    pub user_id: i64,
+    pub name: String,
+    pub created_at: usize,
+    pub last_used_at: Option<usize>,
+    public_key: Vec<u8>,
+    sign_count: u32,
+}
+
+pub struct RegistrationOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub rp_name: String,
+    pub user_handle: String,
+    pub algorithms: Vec<i64>,
+    pub exclude_credentials: Vec<String>,
+    pub timeout_seconds: i64,
+}
+
+pub struct LoginOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<String>,
+    pub timeout_seconds: i64,
+}
+
+struct PendingCeremony {
+    ceremony_type: &'static str,
+    user_id: Option<i64>,
+    expires_at: i64,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    attested_credential: Option<(Vec<u8>, &'a [u8])>,
+}
+
+/// Passkeys registered per user and the challenges of ceremonies in progress. Only ES256
+/// credentials are accepted and attestation statements are not verified.
+#[derive(Clone)]
+pub struct WebauthnService {
+    rp_id: String,
+    rp_name: String,
+    origin: String,
+    challenge_seconds: i64,
+    pending: Arc<Mutex<HashMap<String, PendingCeremony>>>,
+    credentials: Persisted<HashMap<String, PasskeyRecord>>,
+}
+
+impl WebauthnService {
+    pub fn open(config: &AppConfig) -> ServiceResult<Self> {
+        Ok(Self {
+            rp_id: config.webauthn_rp_id.clone(),
+            rp_name: config.webauthn_rp_name.clone(),
+            origin: config.webauthn_origin.trim_end_matches('/').to_string(),
+            challenge_seconds: config.webauthn_challenge_seconds,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            credentials: Persisted::open(config, "passkeys")?,
+        })
+    }
+
+    pub fn begin_registration(&self, user_id: i64) -> RegistrationOptions {
+        RegistrationOptions {
+            challenge: self.new_challenge("webauthn.create", Some(user_id)),
+            rp_id: self.rp_id.clone(),
+            rp_name: self.rp_name.clone(),
+            user_handle: URL_SAFE_NO_PAD.encode(user_id.to_be_bytes()),
+            algorithms: vec![COSE_ALGORITHM_ES256],
+            exclude_credentials: self.credential_ids(user_id),
+            timeout_seconds: self.challenge_seconds,
+        }
+    }
+
+    /// Verifies the attestation returned by `navigator.credentials.create` and stores the
+    /// credential public key for the user.
+    pub fn finish_registration(
+        &self,
+        user_id: i64,
+        name: &str,
+        client_data_json: &str,
+        attestation_object: &str,
+    ) -> ServiceResult<PasskeyRecord> {
+        self.verify_client_data(
+            &decode_base64(client_data_json)?,
+            "webauthn.create",
+            Some(user_id),
+        )?;
+
+        let attestation =
+            ciborium::de::from_reader::<Value, _>(decode_base64(attestation_object)?.as_slice())
+                .map_err(|_| invalid_credential())?;
+        let auth_data = map_entry(&attestation, &Value::Text("authData".to_string()))
+            .and_then(Value::as_bytes)
+            .ok_or_else(invalid_credential)?;
+        let authenticator_data = self.parse_authenticator_data(auth_data)?;
+        let (credential_id, public_key) = authenticator_data
+            .attested_credential
+            .ok_or_else(invalid_credential)?;
+        let public_key = cose_es256_key(public_key)?;
+
+        let id = URL_SAFE_NO_PAD.encode(credential_id);
+        let mut credentials = self.credentials.lock();
+        if credentials.contains_key(&id) {
+            return Err(ServiceError::BadRequest(
+                "This passkey is already registered".to_string(),
+            ));
+        }
+        let record = PasskeyRecord {
+            id: id.clone(),
+            user_id,
+            name: name.to_string(),
+            created_at: now() as usize,
+            last_used_at: None,
+            public_key,
+            sign_count: authenticator_data.sign_count,
+        };
+        credentials.insert(id, record.clone());
+
+        Ok(record)
+    }
+
+    /// Starts an authentication ceremony, limited to the user's passkeys when the user is known
+    /// and open to any discoverable credential otherwise.
+    pub fn begin_login(&self, user_id: Option<i64>) -> LoginOptions {
+        LoginOptions {
+            challenge: self.new_challenge("webauthn.get", None),
+            rp_id: self.rp_id.clone(),
+            allow_credentials: user_id
+                .map(|user_id| self.credential_ids(user_id))
+                .unwrap_or_default(),
+            timeout_seconds: self.challenge_seconds,
+        }
+    }
+
+    /// Verifies the assertion returned by `navigator.credentials.get` and returns the user the
+    /// passkey belongs to.
+    pub fn finish_login(
+        &self,
+        credential_id: &str,
+        client_data_json: &str,
+        authenticator_data: &str,
+        signature: &str,
+    ) -> ServiceResult<i64> {
+        let client_data_json = decode_base64(client_data_json)?;
+        self.verify_client_data(&client_data_json, "webauthn.get", None)?;
+
+        let auth_data = decode_base64(authenticator_data)?;
+        let parsed_auth_data = self.parse_authenticator_data(&auth_data)?;
+        if parsed_auth_data.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        let credential_id = credential_id.trim_end_matches('=');
+        let mut credentials = self.credentials.lock();
+        let credential = credentials
+            .get(credential_id)
+            .ok_or(ServiceError::Unauthorized)?;
+        let verifying_key = VerifyingKey::from_sec1_bytes(&credential.public_key)
+            .map_err(|_| ServiceError::Unauthorized)?;
+        let signature = Signature::from_der(&decode_base64(signature)?)
+            .map_err(|_| ServiceError::Unauthorized)?;
+        let mut signed_data = auth_data.clone();
+        signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+        verifying_key
+            .verify(&signed_data, &signature)
+            .map_err(|_| ServiceError::Unauthorized)?;
+
+        // Authenticators that count signatures must always increase it, a lower value means the
+        // credential was cloned.
+        let sign_count = parsed_auth_data.sign_count;
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            return Err(ServiceError::Unauthorized);
+        }
+        let user_id = credential.user_id;
+
+        // Only a verified assertion changes the credential, so failed attempts are not saved.
+        if let Some(credential) = credentials.get_mut(credential_id) {
+            credential.sign_count = sign_count;
+            credential.last_used_at = Some(now() as usize);
+        }
+
+        Ok(user_id)
+    }
+
+    pub fn list(&self, user_id: i64) -> Vec<PasskeyRecord> {
+        let credentials = self.credentials.lock();
+        let mut records = credentials
+            .values()
+            .filter(|record| record.user_id == user_id)
+            .cloned()
+            .collect::<Vec<PasskeyRecord>>();
+        records.sort_by_key(|record| record.created_at);
+        records
+    }
+
+    pub fn remove(&self, user_id: i64, id: &str) -> Option<PasskeyRecord> {
+        let mut credentials = self.credentials.lock();
+        match credentials.get(id) {
+            Some(record) if record.user_id == user_id => credentials.remove(id),
+            _ => None,
+        }
+    }
+
+    fn credential_ids(&self, user_id: i64) -> Vec<String> {
+        self.list(user_id)
+            .into_iter()
+            .map(|record| record.id)
+            .collect()
+    }
+
+    fn new_challenge(&self, ceremony_type: &'static str, user_id: Option<i64>) -> String {
+        let mut challenge = [0u8; CHALLENGE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        let challenge = URL_SAFE_NO_PAD.encode(challenge);
+
+        let mut pending = self.pending.lock().unwrap();
+        let now = now();
+        pending.retain(|_, ceremony| ceremony.expires_at > now);
+        pending.insert(
+            challenge.clone(),
+            PendingCeremony {
+                ceremony_type,
+                user_id,
+                expires_at: now + self.challenge_seconds,
+            },
+        );
+        challenge
+    }
+
+    /// Consumes the challenge the client data was signed over and checks it came from our origin.
+    fn verify_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony_type: &str,
+        user_id: Option<i64>,
+    ) -> ServiceResult<()> {
+        let client_data = serde_json::from_slice::<ClientData>(client_data_json)
+            .map_err(|_| invalid_credential())?;
+        let ceremony = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(client_data.challenge.trim_end_matches('='))
+            .filter(|ceremony| ceremony.expires_at > now())
+            .ok_or_else(|| {
+                ServiceError::BadRequest("Passkey request is invalid or has expired".to_string())
+            })?;
+
+        if client_data.ceremony_type != ceremony_type
+            || ceremony.ceremony_type != ceremony_type
+            || ceremony.user_id != user_id
+            || client_data.origin.trim_end_matches('/') != self.origin
+        {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(())
+    }
+
+    fn parse_authenticator_data<'a>(
+        &self,
+        auth_data: &'a [u8],
+    ) -> ServiceResult<AuthenticatorData<'a>> {
+        if auth_data.len() < 37 || auth_data[..32] != *Sha256::digest(self.rp_id.as_bytes()) {
+            return Err(ServiceError::Unauthorized);
+        }
+        let flags = auth_data[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            return Err(ServiceError::Unauthorized);
+        }
+        let sign_count =
+            u32::from_be_bytes([auth_data[33], auth_data[34], auth_data[35], auth_data[36]]);
+
+        let attested_credential = match flags & FLAG_ATTESTED_CREDENTIAL_DATA {
+            0 => None,
+            _ => {
+                // aaguid (16 bytes), credential id length (2 bytes), credential id, cose key
+                let rest = auth_data.get(37 + 16..).ok_or_else(invalid_credential)?;
+                let id_length = u16::from_be_bytes([
+                    *rest.first().ok_or_else(invalid_credential)?,
+                    *rest.get(1).ok_or_else(invalid_credential)?,
+                ]) as usize;
+                let credential_id = rest.get(2..2 + id_length).ok_or_else(invalid_credential)?;
+                Some((credential_id.to_vec(), &rest[2 + id_length..]))
+            }
+        };
+
+        Ok(AuthenticatorData {
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+}
+
+/// Converts an EC2 P-256 COSE key into an uncompressed SEC1 point.
+fn cose_es256_key(cose_key: &[u8]) -> ServiceResult<Vec<u8>> {
+    let key = ciborium::de::from_reader::<Value, _>(cose_key).map_err(|_| invalid_credential())?;
+    let integer = |label: i64| {
+        map_entry(&key, &Value::Integer(label.into()))
+            .and_then(Value::as_integer)
+            .and_then(|value| i64::try_from(value).ok())
+    };
+    let coordinate = |label: i64| {
+        map_entry(&key, &Value::Integer(label.into()))
+            .and_then(Value::as_bytes)
+            .filter(|bytes| bytes.len() == 32)
+    };
+
+    // kty EC2 (2), alg ES256 (-7), crv P-256 (1)
+    if integer(1) != Some(2) || integer(3) != Some(COSE_ALGORITHM_ES256) || integer(-1) != Some(1) {
+        return Err(ServiceError::BadRequest(
+            "Only ES256 passkeys are supported".to_string(),
+        ));
+    }
+    let (Some(x), Some(y)) = (coordinate(-2), coordinate(-3)) else {
+        return Err(invalid_credential());
+    };
+
+    let mut public_key = vec![0x04];
+    public_key.extend_from_slice(x);
+    public_key.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&public_key).map_err(|_| invalid_credential())?;
+
+    Ok(public_key)
+}
+
+fn map_entry<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(entry_key, _)| entry_key == key)
+        .map(|(_, value)| value)
+}
+
+fn decode_base64(value: &str) -> ServiceResult<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| ServiceError::BadRequest("Invalid base64url value".to_string()))
+}
+
+fn invalid_credential() -> ServiceError {
+    ServiceError::BadRequest("Invalid passkey credential".to_string())
+}
+
+fn now() -> i64 {
+    OffsetDateTime::from(SystemTime::now()).unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    use super::*;
+
+    const ORIGIN: &str = "http://localhost";
+    const RP_ID: &str = "localhost";
+
+    /// Software authenticator holding a single ES256 credential.
+    struct Authenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            let mut credential_id = vec![0u8; 16];
+            rand::thread_rng().fill_bytes(&mut credential_id);
+            Self {
+                key: SigningKey::random(&mut rand::thread_rng()),
+                credential_id,
+                sign_count: 0,
+            }
+        }
+
+        fn id(&self) -> String {
+            URL_SAFE_NO_PAD.encode(&self.credential_id)
+        }
+
+        fn auth_data(&self, rp_id: &str, attested: bool) -> Vec<u8> {
+            let mut flags = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+            if attested {
+                flags |= FLAG_ATTESTED_CREDENTIAL_DATA;
+            }
+            let mut auth_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            auth_data.push(flags);
+            auth_data.extend_from_slice(&self.sign_count.to_be_bytes());
+            if attested {
+                let point = self.key.verifying_key().to_encoded_point(false);
+                let cose_key = Value::Map(vec![
+                    (Value::Integer(1.into()), Value::Integer(2.into())),
+                    (
+                        Value::Integer(3.into()),
+                        Value::Integer(COSE_ALGORITHM_ES256.into()),
+                    ),
+                    (Value::Integer((-1).into()), Value::Integer(1.into())),
+                    (
+                        Value::Integer((-2).into()),
+                        Value::Bytes(point.x().unwrap().to_vec()),
+                    ),
+                    (
+                        Value::Integer((-3).into()),
+                        Value::Bytes(point.y().unwrap().to_vec()),
+                    ),
+                ]);
+                auth_data.extend_from_slice(&[0u8; 16]);
+                auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                auth_data.extend_from_slice(&self.credential_id);
+                ciborium::ser::into_writer(&cose_key, &mut auth_data).unwrap();
+            }
+            auth_data
+        }
+
+        /// Returns the client data json and attestation object of `navigator.credentials.create`.
+        fn create(&self, challenge: &str, origin: &str, rp_id: &str) -> (String, String) {
+            let attestation = Value::Map(vec![
+                (
+                    Value::Text("fmt".to_string()),
+                    Value::Text("none".to_string()),
+                ),
+                (Value::Text("attStmt".to_string()), Value::Map(Vec::new())),
+                (
+                    Value::Text("authData".to_string()),
+                    Value::Bytes(self.auth_data(rp_id, true)),
+                ),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+            (
+                client_data("webauthn.create", challenge, origin),
+                URL_SAFE_NO_PAD.encode(attestation_object),
+            )
+        }
+
+        /// Returns the client data json, authenticator data and signature of
+        /// `navigator.credentials.get`.
+        fn get(&mut self, challenge: &str, origin: &str, rp_id: &str) -> (String, String, String) {
+            self.sign_count += 1;
+            let client_data_json = client_data("webauthn.get", challenge, origin);
+            let auth_data = self.auth_data(rp_id, false);
+            let mut signed_data = auth_data.clone();
+            signed_data.extend_from_slice(&Sha256::digest(
+                URL_SAFE_NO_PAD.decode(&client_data_json).unwrap(),
+            ));
+            let signature: Signature = self.key.sign(&signed_data);
+            (
+                client_data_json,
+                URL_SAFE_NO_PAD.encode(auth_data),
+                URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+            )
+        }
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str, origin: &str) -> String {
+        let client_data = serde_json::json!({
+            "type": ceremony_type,
+            "challenge": challenge,
+            "origin": origin,
+        });
+        URL_SAFE_NO_PAD.encode(client_data.to_string())
+    }
+
+    fn register(service: &WebauthnService, authenticator: &Authenticator) {
+        let options = service.begin_registration(1);
+        let (client_data_json, attestation_object) =
+            authenticator.create(&options.challenge, ORIGIN, RP_ID);
+        service
+            .finish_registration(1, "laptop", &client_data_json, &attestation_object)
+            .unwrap();
+    }
+
+    fn login(
+        service: &WebauthnService,
+        authenticator: &mut Authenticator,
+        origin: &str,
+        rp_id: &str,
+    ) -> ServiceResult<i64> {
+        let options = service.begin_login(None);
+        let (client_data_json, auth_data, signature) =
+            authenticator.get(&options.challenge, origin, rp_id);
+        service.finish_login(
+            &authenticator.id(),
+            &client_data_json,
+            &auth_data,
+            &signature,
+        )
+    }
+
+    #[test]
+    fn registers_and_signs_in_with_a_passkey() {
+        let config = AppConfig::for_tests();
+        let service = WebauthnService::open(&config).unwrap();
+        let mut authenticator = Authenticator::new();
+
+        register(&service, &authenticator);
+        assert_eq!(service.list(1)[0].id, authenticator.id());
+        assert_eq!(
+            service.begin_login(Some(1)).allow_credentials,
+            vec![authenticator.id()]
+        );
+        assert_eq!(
+            login(&service, &mut authenticator, ORIGIN, RP_ID).unwrap(),
+            1
+        );
+        assert!(service.list(1)[0].last_used_at.is_some());
+    }
+
+    #[test]
+    fn rejects_a_challenge_that_was_not_issued() {
+        let config = AppConfig::for_tests();
+        let service = WebauthnService::open(&config).unwrap();
+        let mut authenticator = Authenticator::new();
+        let (client_data_json, attestation_object) =
+            authenticator.create("not-issued", ORIGIN, RP_ID);
+        assert!(service
+            .finish_registration(1, "laptop", &client_data_json, &attestation_object)
+            .is_err());
+
+        register(&service, &authenticator);
+        service.begin_login(None);
+        let (client_data_json, auth_data, signature) =
+            authenticator.get("not-issued", ORIGIN, RP_ID);
+        assert!(service
+            .finish_login(
+                &authenticator.id(),
+                &client_data_json,
+                &auth_data,
+                &signature
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_another_origin_or_relying_party() {
+        let config = AppConfig::for_tests();
+        let service = WebauthnService::open(&config).unwrap();
+        let mut authenticator = Authenticator::new();
+        let options = service.begin_registration(1);
+        let (client_data_json, attestation_object) =
+            authenticator.create(&options.challenge, "https://evil.example", RP_ID);
+        assert!(service
+            .finish_registration(1, "laptop", &client_data_json, &attestation_object)
+            .is_err());
+        let options = service.begin_registration(1);
+        let (client_data_json, attestation_object) =
+            authenticator.create(&options.challenge, ORIGIN, "evil.example");
+        assert!(service
+            .finish_registration(1, "laptop", &client_data_json, &attestation_object)
+            .is_err());
+        assert!(service.list(1).is_empty());
+
+        register(&service, &authenticator);
+        assert!(login(&service, &mut authenticator, "https://evil.example", RP_ID).is_err());
+        assert!(login(&service, &mut authenticator, ORIGIN, "evil.example").is_err());
+        assert!(login(&service, &mut authenticator, ORIGIN, RP_ID).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_sign_count_that_did_not_increase() {
+        let config = AppConfig::for_tests();
+        let service = WebauthnService::open(&config).unwrap();
+        let mut authenticator = Authenticator::new();
+        register(&service, &authenticator);
+
+        authenticator.sign_count = 9;
+        assert!(login(&service, &mut authenticator, ORIGIN, RP_ID).is_ok());
+        authenticator.sign_count = 4;
+        assert!(login(&service, &mut authenticator, ORIGIN, RP_ID).is_err());
+        authenticator.sign_count = 10;
+        assert!(login(&service, &mut authenticator, ORIGIN, RP_ID).is_ok());
+    }
+
+    #[test]
+    fn passkeys_survive_a_restart() {
+        let config = AppConfig::for_tests();
+        let service = WebauthnService::open(&config).unwrap();
+        let mut authenticator = Authenticator::new();
+        register(&service, &authenticator);
+        assert!(login(&service, &mut authenticator, ORIGIN, RP_ID).is_ok());
+
+        let restarted = WebauthnService::open(&config).unwrap();
+        assert!(login(&restarted, &mut authenticator, ORIGIN, RP_ID).is_ok());
+        authenticator.sign_count = 0;
+        assert!(login(&restarted, &mut authenticator, ORIGIN, RP_ID).is_err());
+    }
+}